@@ -4,7 +4,7 @@ use std::path::PathBuf;
 extern crate spatialos_gdk_codegen;
 
 fn main() {
-    let json_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("json");
+    let schema_path = PathBuf::from("../schema");
     let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("generated.rs");
-    spatialos_gdk_codegen::codegen(json_path, out_path);
+    spatialos_gdk_codegen::codegen_from_schema(vec![schema_path], out_path);
 }
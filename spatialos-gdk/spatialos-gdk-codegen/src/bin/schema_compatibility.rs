@@ -0,0 +1,33 @@
+// Compares two versions of the schema, for example before deploying workers built
+// with a new schema:
+//
+//     $ cargo run --bin schema_compatibility -- old/schema new/schema
+//
+// Every change is printed, and the exit code is 1 if any of them are breaking.
+extern crate spatialos_gdk_codegen;
+
+use std::env;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("Usage: schema_compatibility <old schema directory> <new schema directory>");
+        process::exit(2);
+    }
+
+    let changes = match spatialos_gdk_codegen::compare_schema(vec![&args[0]], vec![&args[1]]) {
+        Ok(changes) => changes,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(2);
+        }
+    };
+
+    for change in changes.iter() {
+        println!("{}", change);
+    }
+    if changes.iter().any(|change| change.is_breaking()) {
+        process::exit(1);
+    }
+}
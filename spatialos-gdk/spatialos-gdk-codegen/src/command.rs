@@ -1,19 +1,9 @@
 use json::{CommandDefinition, ComponentDefinition};
 use quote::Tokens;
 use schema_type::{ReferencedUserType, Type};
+use snake_to_title_case;
 use syn::Ident;
 
-fn snake_to_title_case(snake_case: String) -> String {
-    snake_case
-        .split("_")
-        .flat_map(|word| {
-            let mut v: Vec<char> = word.chars().collect();
-            v[0] = v[0].to_uppercase().nth(0).unwrap();
-            v
-        })
-        .collect()
-}
-
 pub struct Command {
     name: String,
     command_index: u32,
@@ -55,6 +45,19 @@ impl Command {
         }
     }
 
+    pub fn reflection_code(&self) -> Tokens {
+        let name = self.name.as_str();
+        let command_index = self.command_index;
+        let request_type = self.request_type.name.as_str();
+        let response_type = self.response_type.name.as_str();
+        quote!(CommandInfo {
+            name: #name,
+            index: #command_index,
+            request_type: #request_type,
+            response_type: #response_type,
+        })
+    }
+
     pub fn get_global_request_match(&self) -> Tokens {
         let component_id = self.component_id;
         let command_index = self.command_index;
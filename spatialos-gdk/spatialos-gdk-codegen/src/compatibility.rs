@@ -0,0 +1,819 @@
+use json::{CommandDefinition, ComponentDefinition, EnumDefinition, EventDefinition,
+           FieldDefinition, JsonCollection, SchemaTypeDefinition, TypeDefinition};
+use std::fmt;
+
+/// Whether a change to the schema stops it working with data and workers which use
+/// the old schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    /// Data written with one schema can't be read correctly with the other.
+    Breaking,
+    /// Data written with either schema can be read with the other.
+    NonBreaking,
+}
+
+/// A difference between an old and a new version of the schema.
+#[derive(Debug, Clone)]
+pub struct SchemaChange {
+    pub compatibility: Compatibility,
+    /// The qualified name of the changed component, type or enum.
+    pub definition: String,
+    pub message: String,
+}
+
+impl SchemaChange {
+    pub fn is_breaking(&self) -> bool {
+        self.compatibility == Compatibility::Breaking
+    }
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let compatibility = match self.compatibility {
+            Compatibility::Breaking => "breaking",
+            Compatibility::NonBreaking => "non-breaking",
+        };
+        write!(f, "{}: {}: {}", compatibility, self.definition, self.message)
+    }
+}
+
+// Compares the wire format of two versions of the schema. Components, types and enums
+// are matched by qualified name, as that is what generated code refers to them by, and
+// everything inside them is compared by the IDs, numbers and indices which are sent.
+// Types which are referred to under a different name are compared by their fields.
+pub fn compare(old: &JsonCollection, new: &JsonCollection) -> Vec<SchemaChange> {
+    let mut comparison = Comparison {
+        old,
+        new,
+        changes: Vec::new(),
+    };
+
+    for old_component in old.componentDefinitions.iter() {
+        match new.componentDefinitions
+            .iter()
+            .find(|c| c.qualifiedName == old_component.qualifiedName)
+        {
+            Some(new_component) => comparison.compare_components(old_component, new_component),
+            None => comparison.breaking(
+                &old_component.qualifiedName,
+                format!("Component with ID {} was removed.", old_component.id),
+            ),
+        }
+    }
+    for new_component in new.componentDefinitions.iter() {
+        if !old.componentDefinitions
+            .iter()
+            .any(|c| c.qualifiedName == new_component.qualifiedName)
+        {
+            comparison.non_breaking(
+                &new_component.qualifiedName,
+                format!("Component with ID {} was added.", new_component.id),
+            );
+        }
+    }
+
+    // Types and enums which were added or removed are only sent as part of components,
+    // so they are reported where they are used instead.
+    for old_type in old.typeDefinitions.iter() {
+        if let Some(new_type) = new.typeDefinitions
+            .iter()
+            .find(|t| t.qualifiedName == old_type.qualifiedName)
+        {
+            comparison.compare_types(old_type, new_type);
+        }
+    }
+
+    let new_enums = all_enums(new);
+    for old_enum in all_enums(old) {
+        if let Some(new_enum) = new_enums
+            .iter()
+            .find(|e| e.qualifiedName == old_enum.qualifiedName)
+        {
+            comparison.compare_enums(old_enum, new_enum);
+        }
+    }
+
+    comparison.changes
+}
+
+fn all_enums(collection: &JsonCollection) -> Vec<&EnumDefinition> {
+    collection
+        .enumDefinitions
+        .iter()
+        .chain(
+            collection
+                .typeDefinitions
+                .iter()
+                .flat_map(|type_def| type_def.enumDefinitions.iter()),
+        )
+        .collect()
+}
+
+fn type_name(schema_type: &SchemaTypeDefinition) -> &str {
+    schema_type
+        .builtInType
+        .as_ref()
+        .or(schema_type.userType.as_ref())
+        .map(|name| name.as_str())
+        .unwrap_or("")
+}
+
+fn field_type_name(field: &FieldDefinition) -> String {
+    if let Some(ref singular_type) = field.singularType {
+        String::from(type_name(singular_type))
+    } else if let Some(ref option_type) = field.optionType {
+        format!("option<{}>", type_name(&option_type.valueType))
+    } else if let Some(ref list_type) = field.listType {
+        format!("list<{}>", type_name(&list_type.valueType))
+    } else if let Some(ref map_type) = field.mapType {
+        format!(
+            "map<{}, {}>",
+            type_name(&map_type.keyType),
+            type_name(&map_type.valueType)
+        )
+    } else {
+        String::new()
+    }
+}
+
+// The value types of a field, along with which kind of field it is.
+fn field_value_types(field: &FieldDefinition) -> (u8, Vec<&SchemaTypeDefinition>) {
+    if let Some(ref singular_type) = field.singularType {
+        (0, vec![singular_type])
+    } else if let Some(ref option_type) = field.optionType {
+        (1, vec![&option_type.valueType])
+    } else if let Some(ref list_type) = field.listType {
+        (2, vec![&list_type.valueType])
+    } else if let Some(ref map_type) = field.mapType {
+        (3, vec![&map_type.keyType, &map_type.valueType])
+    } else {
+        (4, Vec::new())
+    }
+}
+
+// Something which is sent using a number, but referred to by name in generated code.
+trait Numbered {
+    fn name(&self) -> &str;
+    fn number(&self) -> u32;
+}
+
+impl Numbered for FieldDefinition {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn number(&self) -> u32 {
+        self.number
+    }
+}
+
+impl Numbered for EventDefinition {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn number(&self) -> u32 {
+        self.eventIndex
+    }
+}
+
+impl Numbered for CommandDefinition {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn number(&self) -> u32 {
+        self.commandIndex
+    }
+}
+
+// Fields, events and commands are matched by name, or by number if they have been
+// renamed, which only changes the generated code. A number only counts as a rename if
+// the old name is gone and the new name is new.
+fn find_match<'b, T: Numbered>(old: &[T], new: &'b [T], item: &T) -> Option<&'b T> {
+    new.iter().find(|n| n.name() == item.name()).or_else(|| {
+        new.iter().find(|n| {
+            n.number() == item.number()
+                && !old.iter().any(|o| o.name() == n.name())
+                && !new.iter().any(|o| o.name() == item.name())
+        })
+    })
+}
+
+struct Comparison<'a> {
+    old: &'a JsonCollection,
+    new: &'a JsonCollection,
+    changes: Vec<SchemaChange>,
+}
+
+impl<'a> Comparison<'a> {
+    fn breaking(&mut self, definition: &str, message: String) {
+        self.changes.push(SchemaChange {
+            compatibility: Compatibility::Breaking,
+            definition: String::from(definition),
+            message,
+        });
+    }
+
+    fn non_breaking(&mut self, definition: &str, message: String) {
+        self.changes.push(SchemaChange {
+            compatibility: Compatibility::NonBreaking,
+            definition: String::from(definition),
+            message,
+        });
+    }
+
+    // Reports a change to the type of something, which only breaks the wire format if
+    // the two types are not sent in the same way.
+    fn type_changed(
+        &mut self,
+        definition: &str,
+        what: String,
+        old_type: &str,
+        new_type: &str,
+        same: bool,
+    ) {
+        if old_type == new_type {
+            return;
+        }
+        let message = format!("{} changed from `{}` to `{}`.", what, old_type, new_type);
+        if same {
+            self.non_breaking(definition, message);
+        } else {
+            self.breaking(definition, message);
+        }
+    }
+
+    fn compare_components(&mut self, old: &ComponentDefinition, new: &ComponentDefinition) {
+        let definition = old.qualifiedName.as_str();
+        if old.id != new.id {
+            self.breaking(
+                definition,
+                format!("Component ID changed from {} to {}.", old.id, new.id),
+            );
+        }
+
+        let same = self.same_type(&old.dataDefinition, &new.dataDefinition);
+        self.type_changed(
+            definition,
+            String::from("The data type"),
+            type_name(&old.dataDefinition),
+            type_name(&new.dataDefinition),
+            same,
+        );
+
+        for old_event in old.eventDefinitions.iter() {
+            let new_event = match find_match(
+                &old.eventDefinitions,
+                &new.eventDefinitions,
+                old_event,
+            ) {
+                Some(new_event) => new_event,
+                None => {
+                    self.breaking(
+                        definition,
+                        format!(
+                            "Event `{}` ({}) was removed.",
+                            old_event.name, old_event.eventIndex
+                        ),
+                    );
+                    continue;
+                }
+            };
+
+            if old_event.name != new_event.name {
+                self.non_breaking(
+                    definition,
+                    format!(
+                        "Event {} was renamed from `{}` to `{}`.",
+                        old_event.eventIndex, old_event.name, new_event.name
+                    ),
+                );
+            } else if old_event.eventIndex != new_event.eventIndex {
+                self.breaking(
+                    definition,
+                    format!(
+                        "Event `{}` was renumbered from {} to {}.",
+                        old_event.name, old_event.eventIndex, new_event.eventIndex
+                    ),
+                );
+            }
+
+            let same = self.same_type(&old_event.eventType, &new_event.eventType);
+            self.type_changed(
+                definition,
+                format!("The type of event `{}`", new_event.name),
+                type_name(&old_event.eventType),
+                type_name(&new_event.eventType),
+                same,
+            );
+        }
+        for new_event in new.eventDefinitions.iter() {
+            if find_match(&new.eventDefinitions, &old.eventDefinitions, new_event).is_none() {
+                self.non_breaking(
+                    definition,
+                    format!(
+                        "Event `{}` ({}) was added.",
+                        new_event.name, new_event.eventIndex
+                    ),
+                );
+            }
+        }
+
+        for old_command in old.commandDefinitions.iter() {
+            let new_command = match find_match(
+                &old.commandDefinitions,
+                &new.commandDefinitions,
+                old_command,
+            ) {
+                Some(new_command) => new_command,
+                None => {
+                    self.breaking(
+                        definition,
+                        format!(
+                            "Command `{}` ({}) was removed.",
+                            old_command.name, old_command.commandIndex
+                        ),
+                    );
+                    continue;
+                }
+            };
+
+            if old_command.name != new_command.name {
+                self.non_breaking(
+                    definition,
+                    format!(
+                        "Command {} was renamed from `{}` to `{}`.",
+                        old_command.commandIndex, old_command.name, new_command.name
+                    ),
+                );
+            } else if old_command.commandIndex != new_command.commandIndex {
+                self.breaking(
+                    definition,
+                    format!(
+                        "Command `{}` was renumbered from {} to {}.",
+                        old_command.name, old_command.commandIndex, new_command.commandIndex
+                    ),
+                );
+            }
+
+            let same = self.same_type(&old_command.requestType, &new_command.requestType);
+            self.type_changed(
+                definition,
+                format!("The request type of command `{}`", new_command.name),
+                type_name(&old_command.requestType),
+                type_name(&new_command.requestType),
+                same,
+            );
+            let same = self.same_type(&old_command.responseType, &new_command.responseType);
+            self.type_changed(
+                definition,
+                format!("The response type of command `{}`", new_command.name),
+                type_name(&old_command.responseType),
+                type_name(&new_command.responseType),
+                same,
+            );
+        }
+        for new_command in new.commandDefinitions.iter() {
+            if find_match(
+                &new.commandDefinitions,
+                &old.commandDefinitions,
+                new_command,
+            ).is_none()
+            {
+                self.non_breaking(
+                    definition,
+                    format!(
+                        "Command `{}` ({}) was added.",
+                        new_command.name, new_command.commandIndex
+                    ),
+                );
+            }
+        }
+    }
+
+    fn compare_types(&mut self, old: &TypeDefinition, new: &TypeDefinition) {
+        let definition = old.qualifiedName.as_str();
+        for old_field in old.fieldDefinitions.iter() {
+            let new_field = match find_match(
+                &old.fieldDefinitions,
+                &new.fieldDefinitions,
+                old_field,
+            ) {
+                Some(new_field) => new_field,
+                None => {
+                    self.breaking(
+                        definition,
+                        format!(
+                            "Field `{}` ({}) was removed.",
+                            old_field.name, old_field.number
+                        ),
+                    );
+                    continue;
+                }
+            };
+
+            if old_field.name != new_field.name {
+                self.non_breaking(
+                    definition,
+                    format!(
+                        "Field {} was renamed from `{}` to `{}`.",
+                        old_field.number, old_field.name, new_field.name
+                    ),
+                );
+            } else if old_field.number != new_field.number {
+                self.breaking(
+                    definition,
+                    format!(
+                        "Field `{}` was renumbered from {} to {}.",
+                        old_field.name, old_field.number, new_field.number
+                    ),
+                );
+            }
+
+            let same = self.same_field_type(old_field, new_field, &mut Vec::new());
+            self.type_changed(
+                definition,
+                format!("The type of field `{}`", new_field.name),
+                &field_type_name(old_field),
+                &field_type_name(new_field),
+                same,
+            );
+        }
+
+        for new_field in new.fieldDefinitions.iter() {
+            if find_match(&new.fieldDefinitions, &old.fieldDefinitions, new_field).is_none() {
+                self.non_breaking(
+                    definition,
+                    format!(
+                        "Field `{}` ({}) was added.",
+                        new_field.name, new_field.number
+                    ),
+                );
+            }
+        }
+    }
+
+    // Enum values are sent as numbers, so a value can be renamed but not renumbered.
+    fn compare_enums(&mut self, old: &EnumDefinition, new: &EnumDefinition) {
+        let definition = old.qualifiedName.as_str();
+        for old_value in old.valueDefinitions.iter() {
+            match new.valueDefinitions
+                .iter()
+                .find(|v| v.name == old_value.name)
+            {
+                Some(new_value) if new_value.value != old_value.value => self.breaking(
+                    definition,
+                    format!(
+                        "Value `{}` changed from {} to {}.",
+                        old_value.name, old_value.value, new_value.value
+                    ),
+                ),
+                Some(_) => (),
+                None => match new.valueDefinitions
+                    .iter()
+                    .find(|v| v.value == old_value.value)
+                {
+                    Some(new_value)
+                        if !old.valueDefinitions.iter().any(|o| o.name == new_value.name) =>
+                    {
+                        self.non_breaking(
+                            definition,
+                            format!(
+                                "Value {} was renamed from `{}` to `{}`.",
+                                old_value.value, old_value.name, new_value.name
+                            ),
+                        )
+                    }
+                    _ => self.breaking(
+                        definition,
+                        format!(
+                            "Value `{}` ({}) was removed.",
+                            old_value.name, old_value.value
+                        ),
+                    ),
+                },
+            }
+        }
+
+        for new_value in new.valueDefinitions.iter() {
+            let matched = old.valueDefinitions.iter().any(|v| {
+                v.name == new_value.name
+                    || (v.value == new_value.value
+                        && !new.valueDefinitions.iter().any(|n| n.name == v.name))
+            });
+            if !matched {
+                self.non_breaking(
+                    definition,
+                    format!("Value `{}` ({}) was added.", new_value.name, new_value.value),
+                );
+            }
+        }
+    }
+
+    fn same_type(&self, old: &SchemaTypeDefinition, new: &SchemaTypeDefinition) -> bool {
+        self.same_value_type(old, new, &mut Vec::new())
+    }
+
+    fn same_field_type(
+        &self,
+        old: &FieldDefinition,
+        new: &FieldDefinition,
+        visited: &mut Vec<(String, String)>,
+    ) -> bool {
+        let (old_kind, old_types) = field_value_types(old);
+        let (new_kind, new_types) = field_value_types(new);
+        old_kind == new_kind
+            && old_types
+                .iter()
+                .zip(new_types.iter())
+                .all(|(old, new)| self.same_value_type(old, new, visited))
+    }
+
+    // Whether values of the two types are sent in the same way. A type of the same name
+    // is always the same, as any changes to it are reported when comparing it. Types
+    // with different names are the same if they have the same fields, and enums with
+    // different names are the same if they have the same values.
+    fn same_value_type(
+        &self,
+        old: &SchemaTypeDefinition,
+        new: &SchemaTypeDefinition,
+        visited: &mut Vec<(String, String)>,
+    ) -> bool {
+        let (old_name, new_name) = match (old.userType.as_ref(), new.userType.as_ref()) {
+            (Some(old_name), Some(new_name)) => (old_name, new_name),
+            (None, None) => return old.builtInType == new.builtInType,
+            _ => return false,
+        };
+        if old_name == new_name {
+            return true;
+        }
+
+        // Recursive types are assumed to be the same, unless some other field differs.
+        let pair = (old_name.clone(), new_name.clone());
+        if visited.contains(&pair) {
+            return true;
+        }
+        visited.push(pair);
+
+        let old_type = self.old
+            .typeDefinitions
+            .iter()
+            .find(|t| &t.qualifiedName == old_name);
+        let new_type = self.new
+            .typeDefinitions
+            .iter()
+            .find(|t| &t.qualifiedName == new_name);
+        if let (Some(old_type), Some(new_type)) = (old_type, new_type) {
+            return old_type.fieldDefinitions.len() == new_type.fieldDefinitions.len()
+                && old_type.fieldDefinitions.iter().all(|old_field| {
+                    new_type.fieldDefinitions.iter().any(|new_field| {
+                        new_field.number == old_field.number
+                            && self.same_field_type(old_field, new_field, visited)
+                    })
+                });
+        }
+
+        let old_enum = all_enums(self.old)
+            .into_iter()
+            .find(|e| &e.qualifiedName == old_name);
+        let new_enum = all_enums(self.new)
+            .into_iter()
+            .find(|e| &e.qualifiedName == new_name);
+        if let (Some(old_enum), Some(new_enum)) = (old_enum, new_enum) {
+            let mut old_values: Vec<u32> =
+                old_enum.valueDefinitions.iter().map(|v| v.value).collect();
+            let mut new_values: Vec<u32> =
+                new_enum.valueDefinitions.iter().map(|v| v.value).collect();
+            old_values.sort();
+            new_values.sort();
+            return old_values == new_values;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schema_parser::parse_schema_sources;
+
+    fn changes(old: &str, new: &str) -> Vec<String> {
+        let old = parse_schema_sources(&[("test.schema", old)]).unwrap();
+        let new = parse_schema_sources(&[("test.schema", new)]).unwrap();
+        compare(&old, &new)
+            .iter()
+            .map(|change| change.to_string())
+            .collect()
+    }
+
+    const PLAYER: &str = "
+        package test;
+
+        type Hit { int32 damage = 1; }
+        type Empty {}
+        enum Team { RED = 1; BLUE = 2; }
+
+        component Player {
+            id = 1000;
+            int32 health = 1;
+            Team team = 2;
+            event Hit got_hit;
+            command Empty ping(Empty);
+        }
+    ";
+
+    #[test]
+    fn classifies_changes() {
+        let cases: &[(&str, &str, &str, &[&str])] = &[
+            ("unchanged", "", "", &[]),
+            (
+                "component added",
+                "",
+                "component Other { id = 1001; }",
+                &["non-breaking: test.Other: Component with ID 1001 was added."],
+            ),
+            (
+                "component ID changed",
+                "id = 1000;",
+                "id = 1002;",
+                &["breaking: test.Player: Component ID changed from 1000 to 1002."],
+            ),
+            (
+                "field renamed",
+                "int32 health = 1;",
+                "int32 hp = 1;",
+                &["non-breaking: test.PlayerData: Field 1 was renamed from `health` to `hp`."],
+            ),
+            (
+                "field renumbered",
+                "int32 health = 1;",
+                "int32 health = 3;",
+                &["breaking: test.PlayerData: Field `health` was renumbered from 1 to 3."],
+            ),
+            (
+                "field retyped",
+                "int32 health = 1;",
+                "string health = 1;",
+                &[
+                    "breaking: test.PlayerData: The type of field `health` changed from \
+                     `int32` to `string`.",
+                ],
+            ),
+            (
+                "field removed",
+                "int32 health = 1;",
+                "",
+                &["breaking: test.PlayerData: Field `health` (1) was removed."],
+            ),
+            (
+                "field added",
+                "int32 health = 1;",
+                "int32 health = 1; option<int32> shield = 3;",
+                &["non-breaking: test.PlayerData: Field `shield` (3) was added."],
+            ),
+            (
+                "enum value renamed",
+                "RED = 1;",
+                "CRIMSON = 1;",
+                &["non-breaking: test.Team: Value 1 was renamed from `RED` to `CRIMSON`."],
+            ),
+            (
+                "enum value changed",
+                "BLUE = 2;",
+                "BLUE = 3;",
+                &["breaking: test.Team: Value `BLUE` changed from 2 to 3."],
+            ),
+            (
+                "event renamed",
+                "event Hit got_hit;",
+                "event Hit was_hit;",
+                &["non-breaking: test.Player: Event 1 was renamed from `got_hit` to `was_hit`."],
+            ),
+            (
+                "event retyped",
+                "event Hit got_hit;",
+                "event Empty got_hit;",
+                &[
+                    "breaking: test.Player: The type of event `got_hit` changed from \
+                     `test.Hit` to `test.Empty`.",
+                ],
+            ),
+            (
+                "event removed",
+                "event Hit got_hit;",
+                "",
+                &["breaking: test.Player: Event `got_hit` (1) was removed."],
+            ),
+            (
+                "event added",
+                "event Hit got_hit;",
+                "event Hit got_hit; event Empty healed;",
+                &["non-breaking: test.Player: Event `healed` (2) was added."],
+            ),
+            (
+                "command renamed",
+                "command Empty ping(Empty);",
+                "command Empty poke(Empty);",
+                &["non-breaking: test.Player: Command 1 was renamed from `ping` to `poke`."],
+            ),
+            (
+                "command retyped",
+                "command Empty ping(Empty);",
+                "command Hit ping(Empty);",
+                &[
+                    "breaking: test.Player: The response type of command `ping` changed from \
+                     `test.Empty` to `test.Hit`.",
+                ],
+            ),
+            (
+                "command removed",
+                "command Empty ping(Empty);",
+                "",
+                &["breaking: test.Player: Command `ping` (1) was removed."],
+            ),
+        ];
+
+        for &(name, from, to, expected) in cases.iter() {
+            let new = PLAYER.replacen(from, to, 1);
+            let new = if from.is_empty() {
+                format!("{}\n{}", PLAYER, to)
+            } else {
+                new
+            };
+            assert_eq!(changes(PLAYER, &new), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn component_removed_is_breaking() {
+        assert_eq!(
+            changes(PLAYER, "package test;"),
+            vec!["breaking: test.Player: Component with ID 1000 was removed."]
+        );
+    }
+
+    #[test]
+    fn renamed_data_type_with_the_same_fields_is_not_breaking() {
+        let old = "
+            package test;
+            type Stats { int32 health = 1; option<Stats> parent = 2; }
+            component Player { id = 1000; data Stats; }
+        ";
+        let new = "
+            package test;
+            type Vitals { int32 hp = 1; option<Vitals> parent = 2; }
+            component Player { id = 1000; data Vitals; }
+        ";
+        assert_eq!(
+            changes(old, new),
+            vec![
+                "non-breaking: test.Player: The data type changed from `test.Stats` to \
+                 `test.Vitals`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_data_type_with_different_fields_is_breaking() {
+        let old = "
+            package test;
+            type Stats { int32 health = 1; }
+            component Player { id = 1000; data Stats; }
+        ";
+        let new = "
+            package test;
+            type Vitals { int64 health = 1; }
+            component Player { id = 1000; data Vitals; }
+        ";
+        assert_eq!(
+            changes(old, new),
+            vec![
+                "breaking: test.Player: The data type changed from `test.Stats` to \
+                 `test.Vitals`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_enum_with_the_same_values_is_not_breaking() {
+        let old = "
+            package test;
+            enum Team { RED = 1; BLUE = 2; }
+            type Stats { Team team = 1; }
+        ";
+        let new = "
+            package test;
+            enum Side { RED = 1; BLUE = 2; }
+            type Stats { Side team = 1; }
+        ";
+        assert_eq!(
+            changes(old, new),
+            vec![
+                "non-breaking: test.Stats: The type of field `team` changed from `test.Team` \
+                 to `test.Side`.",
+            ]
+        );
+    }
+}
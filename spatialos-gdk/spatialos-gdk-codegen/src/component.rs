@@ -2,7 +2,8 @@ use command::Command;
 use event::Event;
 use json::ComponentDefinition;
 use quote::Tokens;
-use schema_type::ReferencedUserType;
+use schema_type::{ReferencedUserType, Type};
+use serde_derive_code;
 use syn::Ident;
 use to_rust_qualified_name;
 
@@ -19,6 +20,7 @@ pub struct Component {
 impl From<ComponentDefinition> for Component {
     fn from(value: ComponentDefinition) -> Component {
         let command_component_value = value.clone();
+        let event_component_value = value.clone();
         Component {
             name: value.name,
             component_id: value.id,
@@ -33,7 +35,7 @@ impl From<ComponentDefinition> for Component {
                 value
                     .eventDefinitions
                     .into_iter()
-                    .map(|event_def| Event::from(event_def))
+                    .map(|event_def| Event::from((event_def, event_component_value.clone())))
                     .collect()
             },
             commands: {
@@ -78,6 +80,36 @@ impl Component {
         let event_serialise_from_dirty_data = self.events
             .iter()
             .map(|event| event.serialise_from_dirty_data());
+        let field_discard_dirty_data = fields.iter().map(|field| field.discard_dirty_data());
+        let field_stamp_changed = fields.iter().map(|field| field.stamp_changed_code());
+        let field_changed_mask = fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| field.changed_mask_code(index));
+        let field_equality = fields.iter().map(|field| field.equality_code());
+        let event_equality = self.events.iter().map(|event| event.equality_code());
+        let fields_hashable = self.data_reference_type.is_hashable();
+        let events_hashable = self.events
+            .iter()
+            .all(|event| event.schema_type.is_hashable());
+        let component_derives = if fields_hashable {
+            quote!(, Eq, Hash)
+        } else {
+            quote!()
+        };
+        let (update_derives, data_eq) = if fields_hashable && events_hashable {
+            (quote!(, Eq, Hash), quote!(impl Eq for #data_name {}))
+        } else {
+            (quote!(), quote!())
+        };
+        let serde_derives = serde_derive_code();
+        let field_marker_code = fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| field.marker_code(&self.name, index));
+        let event_discard_dirty_data = self.events
+            .iter()
+            .map(|event| event.discard_dirty_data());
         let field_deserialise_into_update =
             fields.iter().map(|field| field.deserialise_into_update());
         let event_deserialise_into_update = self.events
@@ -86,31 +118,46 @@ impl Component {
         let snapshot_to_data_fields = fields.iter().map(|field| field.snapshot_to_data());
         let snapshot_event_initial_code = self.events.iter().map(|event| event.initial_code());
         let clear_events = self.events.iter().map(|event| event.clear_events_code());
+        let stamp_events = self.events.iter().map(|event| event.stamp_events_code());
+        let loop_back_events = self.events
+            .iter()
+            .map(|event| event.loop_back_events_code());
+        let event_code = self.events.iter().map(|event| event.get_code());
         let contains_events = self.events.iter().map(|event| event.contains_events_code());
         let command_getters = self.commands.iter().map(|command| command.getter_code());
         let command_code = self.commands.iter().map(|command| command.get_code());
+        let schema_value_fields = fields.iter().map(|field| field.schema_value_code());
+        let data_schema_value_fields = fields.iter().map(|field| field.schema_value_code());
 
         quote!{
             #[allow(dead_code, unused_variables)]
-            #[derive(Default)]
+            #[derive(Clone, Debug, Default, PartialEq #component_derives)]
+            #serde_derives
             pub struct #name{
                 #(#field_definitions,)*
             }
 
             #[allow(dead_code, unused_variables)]
             impl #name {
+                // A constant, so that `#[spatial_component]` can check it at compile time.
+                pub const COMPONENT_ID: ComponentId = #component_id;
+
                 #(#command_getters)*
             }
 
             #(#command_code)*
 
+            #(#event_code)*
+
+            #(#field_marker_code)*
+
             #[allow(dead_code, unused_variables)]
             impl Component<Schema> for #name {
                 type Data = #data_name;
                 type Update = #update_name;
 
                 fn component_id() -> ComponentId {
-                    #component_id
+                    #name::COMPONENT_ID
                 }
 
                 fn apply_update_to_data(data: &mut Self::Data, update: &Self::Update) {
@@ -141,18 +188,37 @@ impl Component {
                     }
                 }
 
-                fn serialise_snapshot(self) -> Box<ffi::Schema_ComponentData> {
-                    let data = #data_name {
+                fn into_data(self) -> Self::Data {
+                    #data_name {
                         is_dirty: false,
                         #(#snapshot_to_data_fields,)*
                         #(#snapshot_event_initial_code,)*
-                    };
-                    data.serialise_data()
+                    }
+                }
+            }
+
+            impl SchemaComponent<Schema> for #name {
+                fn serialise_snapshot(self) -> Box<ffi::Schema_ComponentData> {
+                    self.into_data().serialise_data()
+                }
+            }
+
+            impl ToSchemaValue for #name {
+                fn to_schema_value(&self) -> SchemaValue {
+                    SchemaValue::Object(vec![#(#schema_value_fields,)*])
+                }
+            }
+
+            // Events aren't part of the value of the component.
+            impl ToSchemaValue for #data_name {
+                fn to_schema_value(&self) -> SchemaValue {
+                    SchemaValue::Object(vec![#(#data_schema_value_fields,)*])
                 }
             }
 
             #[allow(dead_code, unused_variables)]
-            #[derive(Clone, Debug)]
+            #[derive(Clone, Debug, PartialEq #update_derives)]
+            #serde_derives
             pub struct #update_name {
                 #(#field_update_definitions,)*
                 #(#event_update_definitions,)*
@@ -166,6 +232,15 @@ impl Component {
                 #(#event_data_definitions,)*
             }
 
+            // The dirty bit isn't part of the value of the data, so it is ignored.
+            impl PartialEq for #data_name {
+                fn eq(&self, other: &#data_name) -> bool {
+                    true #(&& #field_equality)* #(&& #event_equality)*
+                }
+            }
+
+            #data_eq
+
             #[allow(dead_code, unused_variables)]
             impl #data_name {
                 pub unsafe fn deserialise(object: *mut Schema_Object) -> #data_name {
@@ -192,7 +267,7 @@ impl Component {
             }
 
             #[allow(dead_code, unused_variables)]
-            impl ComponentDataInterface<Schema> for #data_name {
+            impl SchemaComponentData<Schema> for #data_name {
                 fn deserialise_data(data: Box<ffi::Schema_ComponentData>)
                     -> <Schema as GeneratedSchema>::ComponentData {
                     unsafe {
@@ -214,8 +289,11 @@ impl Component {
                         Box::from_raw(data)
                     }
                 }
+            }
 
-                fn serialise_update(&mut self) -> Box<ffi::Schema_ComponentUpdate> {
+            #[allow(dead_code, unused_variables)]
+            impl ComponentDataInterface<Schema> for #data_name {
+                fn serialise_update(&mut self) -> Option<Box<ffi::Schema_ComponentUpdate>> {
                     unsafe {
                         let update = ffi::Schema_CreateComponentUpdate(#name::component_id());
                         let fields = ffi::Schema_GetComponentUpdateFields(update);
@@ -225,10 +303,15 @@ impl Component {
 
                         #(#event_serialise_from_dirty_data)*
 
-                        Box::from_raw(update)
+                        Some(Box::from_raw(update))
                     }
                 }
 
+                fn discard_update(&mut self) {
+                    #(#field_discard_dirty_data;)*
+                    #(#event_discard_dirty_data;)*
+                }
+
                 fn make_dirty(&mut self) {
                     self.is_dirty = true;
                 }
@@ -239,18 +322,40 @@ impl Component {
                     dirty
                 }
 
-                fn cleanup_after_frame(&mut self) {
-                    #(#clear_events;)*
+                fn stamp_events(&mut self, time: &WorldTime) {
+                    #(#stamp_events;)*
+                }
+
+                fn stamp_changed_fields(&mut self, time: &WorldTime) -> bool {
+                    false #(| #field_stamp_changed)*
+                }
+
+                fn changed_fields_since(&self, time: &WorldTime) -> u64 {
+                    0 #(| #field_changed_mask)*
+                }
+
+                fn loop_back_events(&mut self, time: &WorldTime) -> bool {
+                    false #(| #loop_back_events)*
+                }
+
+                fn cleanup_after_frame(&mut self, oldest_cursor: &WorldTime) -> bool {
+                    // Not short-circuiting, so that every event is cleaned up.
+                    false #(| #clear_events)*
                 }
             }
 
             #[allow(dead_code, unused_variables)]
-            impl ComponentUpdateInterface<Schema> for #update_name {
+            impl SchemaComponentUpdate<Schema> for #update_name {
                 fn deserialise_update(update_box: Box<ffi::Schema_ComponentUpdate>)
                     -> <Schema as GeneratedSchema>::ComponentUpdate {
                     unsafe {
                         let update = Box::into_raw(update_box);
                         let fields = ffi::Schema_GetComponentUpdateFields(update);
+                        let cleared_field_count =
+                            ffi::Schema_GetComponentUpdateClearedFieldCount(update);
+                        let mut cleared_fields = vec![0; cleared_field_count as usize];
+                        ffi::Schema_GetComponentUpdateClearedFieldList(update,
+                            cleared_fields.as_mut_ptr());
                         Box::from_raw(update);
 
                         ComponentUpdate::#enum_name(#update_name {
@@ -259,11 +364,36 @@ impl Component {
                         })
                     }
                 }
+            }
 
+            #[allow(dead_code, unused_variables)]
+            impl ComponentUpdateInterface<Schema> for #update_name {
                 fn contains_events(&self) -> bool {
                     #(#contains_events ||)* false
                 }
             }
         }
     }
+
+    pub fn reflection_code(&self) -> Tokens {
+        let qualified_name = self.qualified_name.join(".");
+        let component_id = self.component_id;
+        let fields = self.data_reference_type
+            .get()
+            .unwrap()
+            .borrow()
+            .fields
+            .iter()
+            .map(|field| field.reflection_code())
+            .collect::<Vec<_>>();
+        let events = self.events.iter().map(|event| event.reflection_code());
+        let commands = self.commands.iter().map(|command| command.reflection_code());
+        quote!(ComponentInfo {
+            qualified_name: #qualified_name,
+            id: #component_id,
+            fields: &[#(#fields,)*],
+            events: &[#(#events,)*],
+            commands: &[#(#commands,)*],
+        })
+    }
 }
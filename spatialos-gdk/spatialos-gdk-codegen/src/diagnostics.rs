@@ -0,0 +1,506 @@
+use json::{FieldDefinition, JsonCollection, SchemaTypeDefinition, SourceReference};
+use schema_parser::BUILT_IN_TYPES;
+use snake_to_title_case;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Every keyword in any edition of Rust, including the reserved ones, as none of
+// them can be used as identifiers in the generated code.
+const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "alignof", "as", "async", "await", "become", "box", "break", "const", "continue",
+    "crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "offsetof", "override", "priv",
+    "proc", "pub", "pure", "ref", "return", "Self", "self", "sizeof", "static", "struct", "super",
+    "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual", "where",
+    "while", "yield",
+];
+
+// Schema is serialised using protobuf, which only allows field numbers up to 2^29 - 1,
+// and reserves a range of them for its own use.
+const MAX_FIELD_NUMBER: u32 = (1 << 29) - 1;
+const FIRST_RESERVED_FIELD_NUMBER: u32 = 19000;
+const LAST_RESERVED_FIELD_NUMBER: u32 = 19999;
+
+// Component IDs from 2^29 upwards are used for local components.
+const MAX_COMPONENT_ID: u32 = (1 << 29) - 1;
+
+// Changes to the fields of a component are tracked in a `u64`.
+pub const MAX_COMPONENT_FIELDS: usize = 64;
+
+/// A problem with the schema which would stop the generated code from compiling.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// The file, line and column of the definition, or its qualified name if its
+    /// position in the schema is not known.
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+// Checks the whole schema before any code is generated, so that every problem can be
+// reported at once rather than panicking at the first one.
+pub fn check(collection: &JsonCollection) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        diagnostics: Vec::new(),
+        types: collection
+            .typeDefinitions
+            .iter()
+            .map(|type_def| type_def.qualifiedName.clone())
+            .collect(),
+        enums: collection
+            .enumDefinitions
+            .iter()
+            .chain(
+                collection
+                    .typeDefinitions
+                    .iter()
+                    .flat_map(|type_def| type_def.enumDefinitions.iter()),
+            )
+            .map(|enum_def| enum_def.qualifiedName.clone())
+            .collect(),
+        checked_packages: HashSet::new(),
+    };
+    checker.check_collection(collection);
+    checker.diagnostics
+}
+
+struct Checker {
+    diagnostics: Vec<Diagnostic>,
+    types: HashSet<String>,
+    enums: HashSet<String>,
+    checked_packages: HashSet<String>,
+}
+
+impl Checker {
+    fn error(&mut self, source: &Option<SourceReference>, definition: &str, message: String) {
+        let location = match *source {
+            Some(ref source) if !source.filePath.is_empty() => {
+                format!("{}:{}:{}", source.filePath, source.line, source.column)
+            }
+            Some(ref source) => format!("{}:{}:{}", definition, source.line, source.column),
+            None => String::from(definition),
+        };
+        self.diagnostics.push(Diagnostic { location, message });
+    }
+
+    fn check_identifier(
+        &mut self,
+        source: &Option<SourceReference>,
+        definition: &str,
+        kind: &str,
+        name: &str,
+    ) {
+        if RUST_KEYWORDS.contains(&name) {
+            self.error(
+                source,
+                definition,
+                format!("The {} `{}` is a Rust keyword.", kind, name),
+            );
+        }
+    }
+
+    // Each part of the package is a module in the generated code.
+    fn check_package(&mut self, source: &Option<SourceReference>, qualified_name: &str) {
+        let mut parts: Vec<&str> = qualified_name.split(".").collect();
+        parts.pop();
+        let package = parts.join(".");
+        if !self.checked_packages.insert(package.clone()) {
+            return;
+        }
+        for part in parts {
+            self.check_identifier(source, qualified_name, "package name", part);
+        }
+    }
+
+    fn check_collection(&mut self, collection: &JsonCollection) {
+        for enum_def in collection.enumDefinitions.iter().chain(
+            collection
+                .typeDefinitions
+                .iter()
+                .flat_map(|type_def| type_def.enumDefinitions.iter()),
+        ) {
+            let source = &enum_def.sourceReference;
+            let definition = enum_def.qualifiedName.as_str();
+            self.check_package(source, definition);
+            self.check_identifier(source, definition, "enum name", enum_def.name.as_str());
+            if enum_def.valueDefinitions.is_empty() {
+                self.error(
+                    source,
+                    definition,
+                    format!("Enum `{}` must have at least one value.", definition),
+                );
+            }
+            let mut values = HashMap::new();
+            for value in enum_def.valueDefinitions.iter() {
+                let variant_name = snake_to_title_case(value.name.to_lowercase());
+                self.check_identifier(source, definition, "enum value", variant_name.as_str());
+                if let Some(other) = values.insert(value.value, value.name.as_str()) {
+                    self.error(
+                        source,
+                        definition,
+                        format!(
+                            "Enum values `{}` and `{}` of `{}` both have the value {}.",
+                            other, value.name, definition, value.value
+                        ),
+                    );
+                }
+            }
+        }
+
+        for type_def in collection.typeDefinitions.iter() {
+            let source = &type_def.sourceReference;
+            let definition = type_def.qualifiedName.as_str();
+            self.check_package(source, definition);
+            self.check_identifier(source, definition, "type name", type_def.name.as_str());
+            self.check_fields(definition, &type_def.fieldDefinitions);
+        }
+
+        let mut component_ids: HashMap<u32, &str> = HashMap::new();
+        for component in collection.componentDefinitions.iter() {
+            let source = &component.sourceReference;
+            let definition = component.qualifiedName.as_str();
+            self.check_package(source, definition);
+            self.check_identifier(source, definition, "component name", component.name.as_str());
+
+            if let Some(other) = component_ids.insert(component.id, definition) {
+                self.error(
+                    source,
+                    definition,
+                    format!(
+                        "Components `{}` and `{}` both have the ID {}.",
+                        other, definition, component.id
+                    ),
+                );
+            }
+            if component.id > MAX_COMPONENT_ID {
+                self.error(
+                    source,
+                    definition,
+                    format!(
+                        "The ID of component `{}` must be at most {}.",
+                        definition, MAX_COMPONENT_ID
+                    ),
+                );
+            }
+
+            if self.check_user_type(source, definition, &component.dataDefinition) {
+                let data_type = collection
+                    .typeDefinitions
+                    .iter()
+                    .find(|type_def| {
+                        Some(&type_def.qualifiedName) == component.dataDefinition.userType.as_ref()
+                    })
+                    .unwrap();
+                if data_type.fieldDefinitions.len() > MAX_COMPONENT_FIELDS {
+                    self.error(
+                        source,
+                        definition,
+                        format!(
+                            "Component `{}` has more than {} fields, so changes to its fields \
+                             cannot be tracked.",
+                            definition, MAX_COMPONENT_FIELDS
+                        ),
+                    );
+                }
+                // The generated data struct has its own `is_dirty` field.
+                for field in data_type.fieldDefinitions.iter() {
+                    if field.name == "is_dirty" {
+                        self.error(
+                            &field.sourceReference,
+                            definition,
+                            format!(
+                                "The field name `is_dirty` of component `{}` is reserved.",
+                                definition
+                            ),
+                        );
+                    }
+                }
+            }
+
+            for event in component.eventDefinitions.iter() {
+                self.check_identifier(source, definition, "event name", event.name.as_str());
+                self.check_user_type(source, definition, &event.eventType);
+            }
+
+            for command in component.commandDefinitions.iter() {
+                self.check_identifier(source, definition, "command name", command.name.as_str());
+                self.check_user_type(source, definition, &command.requestType);
+                self.check_user_type(source, definition, &command.responseType);
+            }
+        }
+    }
+
+    fn check_fields(&mut self, definition: &str, fields: &[FieldDefinition]) {
+        let mut numbers = HashMap::new();
+        let mut names = HashSet::new();
+        for field in fields.iter() {
+            let source = &field.sourceReference;
+            self.check_identifier(source, definition, "field name", field.name.as_str());
+
+            if !names.insert(field.name.as_str()) {
+                self.error(
+                    source,
+                    definition,
+                    format!(
+                        "`{}` has more than one field called `{}`.",
+                        definition, field.name
+                    ),
+                );
+            }
+            if let Some(other) = numbers.insert(field.number, field.name.as_str()) {
+                self.error(
+                    source,
+                    definition,
+                    format!(
+                        "Fields `{}` and `{}` of `{}` both have the number {}.",
+                        other, field.name, definition, field.number
+                    ),
+                );
+            }
+            if field.number == 0 || field.number > MAX_FIELD_NUMBER {
+                self.error(
+                    source,
+                    definition,
+                    format!(
+                        "The number of field `{}` must be between 1 and {}.",
+                        field.name, MAX_FIELD_NUMBER
+                    ),
+                );
+            } else if field.number >= FIRST_RESERVED_FIELD_NUMBER
+                && field.number <= LAST_RESERVED_FIELD_NUMBER
+            {
+                self.error(
+                    source,
+                    definition,
+                    format!(
+                        "The number of field `{}` is in the reserved range {} to {}.",
+                        field.name, FIRST_RESERVED_FIELD_NUMBER, LAST_RESERVED_FIELD_NUMBER
+                    ),
+                );
+            }
+
+            let types: Vec<&SchemaTypeDefinition> = field
+                .singularType
+                .iter()
+                .chain(field.optionType.iter().map(|t| &t.valueType))
+                .chain(field.listType.iter().map(|t| &t.valueType))
+                .chain(field.mapType.iter().map(|t| &t.keyType))
+                .chain(field.mapType.iter().map(|t| &t.valueType))
+                .collect();
+            if types.is_empty() {
+                self.error(
+                    source,
+                    definition,
+                    format!("Field `{}` does not have a type.", field.name),
+                );
+            }
+            for schema_type in types {
+                self.check_type(source, definition, schema_type);
+            }
+        }
+    }
+
+    fn check_type(
+        &mut self,
+        source: &Option<SourceReference>,
+        definition: &str,
+        schema_type: &SchemaTypeDefinition,
+    ) {
+        match (&schema_type.builtInType, &schema_type.userType) {
+            (Some(name), _) => {
+                if !BUILT_IN_TYPES.contains(&name.as_str()) {
+                    self.error(source, definition, format!("Unknown built in type `{}`.", name));
+                }
+            }
+            (None, Some(name)) => {
+                if !self.types.contains(name) && !self.enums.contains(name) {
+                    self.error(source, definition, format!("Unknown type `{}`.", name));
+                }
+            }
+            (None, None) => {
+                self.error(source, definition, String::from("A type is missing."));
+            }
+        }
+    }
+
+    // Returns true if the type is a known user type.
+    fn check_user_type(
+        &mut self,
+        source: &Option<SourceReference>,
+        definition: &str,
+        schema_type: &SchemaTypeDefinition,
+    ) -> bool {
+        match schema_type.userType {
+            Some(ref name) if self.types.contains(name) => true,
+            Some(ref name) => {
+                self.error(
+                    source,
+                    definition,
+                    format!("`{}` is not a type defined in schema.", name),
+                );
+                false
+            }
+            None => {
+                self.error(
+                    source,
+                    definition,
+                    String::from("Component data, events and commands must use user types."),
+                );
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn messages(json: &str) -> Vec<String> {
+        let collection: JsonCollection = serde_json::from_str(json).unwrap();
+        check(&collection)
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect()
+    }
+
+    // A component whose data type has the given fields.
+    fn component_with_fields(fields: &str) -> String {
+        format!(
+            r#"{{
+                "typeDefinitions": [
+                    {{ "name": "PlayerData", "qualifiedName": "test.PlayerData",
+                       "fieldDefinitions": [{}] }}
+                ],
+                "componentDefinitions": [
+                    {{ "id": 1000, "name": "Player", "qualifiedName": "test.Player",
+                       "dataDefinition": {{ "userType": "test.PlayerData" }},
+                       "eventDefinitions": [], "commandDefinitions": [] }}
+                ]
+            }}"#,
+            fields
+        )
+    }
+
+    #[test]
+    fn accepts_a_valid_component() {
+        let json = component_with_fields(
+            r#"{ "name": "health", "number": 1, "singularType": { "builtInType": "int32" } }"#,
+        );
+        assert_eq!(messages(&json), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_duplicate_component_ids() {
+        let json = r#"{
+            "typeDefinitions": [
+                { "name": "Empty", "qualifiedName": "test.Empty", "fieldDefinitions": [] }
+            ],
+            "componentDefinitions": [
+                { "id": 1000, "name": "Player", "qualifiedName": "test.Player",
+                  "dataDefinition": { "userType": "test.Empty" },
+                  "eventDefinitions": [], "commandDefinitions": [] },
+                { "id": 1000, "name": "Enemy", "qualifiedName": "test.Enemy",
+                  "dataDefinition": { "userType": "test.Empty" },
+                  "eventDefinitions": [], "commandDefinitions": [] }
+            ]
+        }"#;
+        assert_eq!(
+            messages(json),
+            vec!["test.Enemy: Components `test.Player` and `test.Enemy` both have the ID 1000."]
+        );
+    }
+
+    #[test]
+    fn reports_unresolved_types() {
+        let json = component_with_fields(
+            r#"{ "name": "target", "number": 1, "singularType": { "userType": "test.Missing" } }"#,
+        );
+        assert_eq!(
+            messages(&json),
+            vec!["test.PlayerData: Unknown type `test.Missing`."]
+        );
+    }
+
+    #[test]
+    fn reports_rust_keywords() {
+        let json = component_with_fields(
+            r#"{ "name": "type", "number": 1, "singularType": { "builtInType": "int32" } }"#,
+        );
+        assert_eq!(
+            messages(&json),
+            vec!["test.PlayerData: The field name `type` is a Rust keyword."]
+        );
+    }
+
+    #[test]
+    fn reports_reserved_field_numbers() {
+        let json = component_with_fields(
+            r#"{ "name": "health", "number": 19000, "singularType": { "builtInType": "int32" } }"#,
+        );
+        assert_eq!(
+            messages(&json),
+            vec![
+                "test.PlayerData: The number of field `health` is in the reserved range 19000 \
+                 to 19999.",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_empty_enums() {
+        let json = r#"{
+            "enumDefinitions": [
+                { "name": "Team", "qualifiedName": "test.Team", "valueDefinitions": [] }
+            ],
+            "typeDefinitions": [],
+            "componentDefinitions": []
+        }"#;
+        assert_eq!(
+            messages(json),
+            vec!["test.Team: Enum `test.Team` must have at least one value."]
+        );
+    }
+
+    #[test]
+    fn reports_components_with_too_many_fields() {
+        let fields: Vec<String> = (1..MAX_COMPONENT_FIELDS + 2)
+            .map(|number| {
+                format!(
+                    r#"{{ "name": "field_{0}", "number": {0},
+                          "singularType": {{ "builtInType": "int32" }} }}"#,
+                    number
+                )
+            })
+            .collect();
+        let json = component_with_fields(&fields.join(","));
+        assert_eq!(
+            messages(&json),
+            vec![
+                "test.Player: Component `test.Player` has more than 64 fields, so changes to \
+                 its fields cannot be tracked.",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_is_dirty_fields() {
+        let json = component_with_fields(
+            r#"{ "name": "is_dirty", "number": 1, "singularType": { "builtInType": "bool" },
+                 "sourceReference": { "filePath": "test.schema", "line": 4, "column": 3 } }"#,
+        );
+        assert_eq!(
+            messages(&json),
+            vec![
+                "test.schema:4:3: The field name `is_dirty` of component `test.Player` is \
+                 reserved.",
+            ]
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use json::EnumDefinition;
+use quote::Tokens;
+use schema_type::Type;
+use serde_derive_code;
+use snake_to_title_case;
+use syn::Ident;
+use to_rust_qualified_name;
+
+pub struct EnumValue {
+    name: String,
+    schema_name: String,
+    value: u32,
+}
+
+pub struct EnumType {
+    name: String,
+    pub qualified_name: Vec<String>,
+    rust_qualified_name: String,
+    values: Vec<EnumValue>,
+}
+
+impl EnumType {
+    // Enums nested inside a type are flattened into the type's package, with the
+    // name of the outer type as a prefix. A Rust module can't share a name with
+    // the struct generated for the outer type, so `a.Outer.Inner` becomes `a::OuterInner`.
+    pub fn new(definition: EnumDefinition, outer_types: &[String]) -> EnumType {
+        let mut qualified_name: Vec<String> = definition
+            .qualifiedName
+            .split(".")
+            .map(String::from)
+            .collect();
+        let package_length = qualified_name.len() - outer_types.len() - 1;
+        qualified_name.truncate(package_length);
+
+        let mut name = outer_types.concat();
+        name.push_str(definition.name.as_str());
+        qualified_name.push(name.clone());
+
+        EnumType {
+            name,
+            rust_qualified_name: to_rust_qualified_name(qualified_name.join(".").as_str()),
+            qualified_name,
+            values: definition
+                .valueDefinitions
+                .into_iter()
+                .map(|value| EnumValue {
+                    name: snake_to_title_case(value.name.to_lowercase()),
+                    schema_name: value.name,
+                    value: value.value,
+                })
+                .collect(),
+        }
+    }
+
+    // Values which aren't in the schema are kept in an extra variant, which is called
+    // `Unknown` unless the schema already has a value with that name.
+    fn unknown_variant_name(&self) -> String {
+        let mut name = String::from("Unknown");
+        while self.values.iter().any(|value| value.name == name) {
+            name.push_str("Value");
+        }
+        name
+    }
+
+    pub fn get_code(&self) -> Tokens {
+        let name = Ident::new(self.name.as_str());
+        let unknown_variant = Ident::new(self.unknown_variant_name());
+        // Values are serialised with the name they have in schema.
+        let variant_definitions = self.values.iter().map(|value| {
+            let variant_name = Ident::new(value.name.as_str());
+            let serde_attributes = if cfg!(feature = "serde") {
+                let schema_name = value.schema_name.as_str();
+                quote!(#[serde(rename = #schema_name)])
+            } else {
+                quote!()
+            };
+            quote!(#serde_attributes #variant_name)
+        });
+        let serde_derives = serde_derive_code();
+        let value_matches = self.values.iter().map(|value| {
+            let variant_name = Ident::new(value.name.as_str());
+            let discriminant = value.value;
+            quote!(#name::#variant_name => #discriminant)
+        });
+        let from_value_matches = self.values.iter().map(|value| {
+            let variant_name = Ident::new(value.name.as_str());
+            let discriminant = value.value;
+            quote!(#discriminant => Some(#name::#variant_name))
+        });
+        // Empty enums are reported by the diagnostics, but an unknown value is used as
+        // the default rather than panicking if one gets this far.
+        let default_value = match self.values.first() {
+            Some(value) => {
+                let variant_name = Ident::new(value.name.as_str());
+                quote!(#name::#variant_name)
+            }
+            None => quote!(#name::#unknown_variant(0)),
+        };
+
+        quote!{
+            #[allow(dead_code)]
+            #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+            #serde_derives
+            pub enum #name {
+                #(#variant_definitions,)*
+                #unknown_variant(u32),
+            }
+
+            impl Default for #name {
+                fn default() -> #name {
+                    #default_value
+                }
+            }
+
+            #[allow(dead_code)]
+            impl #name {
+                pub fn value(&self) -> u32 {
+                    match *self {
+                        #(#value_matches,)*
+                        #name::#unknown_variant(value) => value,
+                    }
+                }
+
+                // Returns `None` for values which aren't in the schema.
+                pub fn from_value(value: u32) -> Option<#name> {
+                    match value {
+                        #(#from_value_matches,)*
+                        _ => None
+                    }
+                }
+            }
+
+            impl ToSchemaValue for #name {
+                fn to_schema_value(&self) -> SchemaValue {
+                    SchemaValue::Enum(self.value())
+                }
+            }
+        }
+    }
+
+    // Values are described with the name they have in schema.
+    pub fn reflection_code(&self) -> Tokens {
+        let qualified_name = self.qualified_name.join(".");
+        let values = self.values.iter().map(|value| {
+            let schema_name = value.schema_name.as_str();
+            let discriminant = value.value;
+            quote!(EnumValueInfo {
+                name: #schema_name,
+                value: #discriminant,
+            })
+        });
+        quote!(EnumInfo {
+            qualified_name: #qualified_name,
+            values: &[#(#values,)*],
+        })
+    }
+}
+
+impl Type for EnumType {
+    fn rust_type_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn rust_qualified_name(&self) -> String {
+        self.rust_qualified_name.clone()
+    }
+
+    fn is_hashable(&self) -> bool {
+        true
+    }
+
+    fn reflection_value_type_code(&self) -> Tokens {
+        let qualified_name = self.qualified_name.join(".");
+        quote!(ValueType::Enum(#qualified_name))
+    }
+
+    // Unknown values can be sent by workers using a newer version of the schema, so
+    // they are kept as they are, and sent back unchanged if the data is written.
+    fn deserialise_code(
+        &self,
+        object_name: &String,
+        field_id: u32,
+        index: Option<String>,
+    ) -> Tokens {
+        let object_name = Ident::new(object_name.as_str());
+        let index = Ident::new(index.unwrap_or(String::from("0")).as_str());
+        let rust_qualified_name = Ident::new(self.rust_qualified_name());
+        let unknown_variant = Ident::new(self.unknown_variant_name());
+        quote!({
+            let value = ffi::Schema_IndexEnum(#object_name, #field_id, #index);
+            #rust_qualified_name::from_value(value)
+                .unwrap_or(#rust_qualified_name::#unknown_variant(value))
+        })
+    }
+
+    fn serialise_code(&self, object_name: &String, field_id: u32, value_name: &String) -> Tokens {
+        let object_name = Ident::new(object_name.as_str());
+        let value_name = Ident::new(value_name.as_str());
+        quote!(ffi::Schema_AddEnum(#object_name, #field_id, (#value_name).value()))
+    }
+
+    fn count_code(&self, object_name: &String, field_id: u32) -> Tokens {
+        let object_name = Ident::new(object_name.as_str());
+        quote!(ffi::Schema_GetEnumCount(#object_name, #field_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn enum_type(values: &str) -> EnumType {
+        let json = format!(
+            r#"{{ "name": "Team", "qualifiedName": "test.Team", "valueDefinitions": [{}] }}"#,
+            values
+        );
+        EnumType::new(serde_json::from_str(&json).unwrap(), &[])
+    }
+
+    #[test]
+    fn keeps_unknown_values() {
+        let code = enum_type(r#"{ "name": "RED", "value": 0 }"#).get_code().to_string();
+        assert!(code.contains("Unknown ( u32 )"));
+        assert!(code.contains("Team :: Unknown ( value ) => value"));
+    }
+
+    #[test]
+    fn renames_the_unknown_variant_if_it_is_taken() {
+        let team =
+            enum_type(r#"{ "name": "RED", "value": 0 }, { "name": "UNKNOWN", "value": 1 }"#);
+        assert_eq!(team.unknown_variant_name(), "UnknownValue");
+    }
+
+    #[test]
+    fn generates_a_default_for_empty_enums() {
+        let code = enum_type("").get_code().to_string();
+        assert!(code.contains("Team :: Unknown ( 0 )"));
+    }
+}
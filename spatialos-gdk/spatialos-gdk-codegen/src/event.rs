@@ -1,7 +1,8 @@
-use json::{EventDefinition, ListTypeDefinition};
+use json::{ComponentDefinition, EventDefinition, ListTypeDefinition};
 use quote::Tokens;
 use schema_type::Type;
 use schema_type::{ListType, ReferencedUserType};
+use snake_to_title_case;
 use syn::Ident;
 
 pub struct Event {
@@ -9,13 +10,23 @@ pub struct Event {
     event_index: u32,
     pub schema_type: ReferencedUserType,
     pub list_schema_type: ListType,
+    struct_name: String,
+    component_name: String,
 }
 
-impl From<EventDefinition> for Event {
-    fn from(value: EventDefinition) -> Event {
+impl From<(EventDefinition, ComponentDefinition)> for Event {
+    fn from(value: (EventDefinition, ComponentDefinition)) -> Event {
+        let (value, component) = value;
+        let struct_name = format!(
+            "{}{}",
+            component.name,
+            snake_to_title_case(value.name.clone())
+        );
         Event {
             name: value.name,
             event_index: value.eventIndex,
+            struct_name,
+            component_name: component.name,
             schema_type: ReferencedUserType::from(value.eventType.clone()),
             list_schema_type: ListType::from(ListTypeDefinition {
                 valueType: value.eventType,
@@ -34,7 +45,12 @@ impl Event {
     pub fn definition_in_update(&self) -> Tokens {
         let type_name = Ident::new(self.schema_type.rust_qualified_name().as_str());
         let field_name = Ident::new(self.name.as_str());
-        quote!(pub #field_name: Vec<#type_name>)
+        let serde_attributes = if cfg!(feature = "serde") {
+            quote!(#[serde(default, skip_serializing_if = "Vec::is_empty")])
+        } else {
+            quote!()
+        };
+        quote!(#serde_attributes pub #field_name: Vec<#type_name>)
     }
 
     pub fn apply_from_update(&self) -> Tokens {
@@ -73,6 +89,42 @@ impl Event {
         }
     }
 
+    pub fn discard_dirty_data(&self) -> Tokens {
+        let field_name = Ident::new(self.name.as_str());
+        quote!(self.#field_name.clear_staged_events())
+    }
+
+    pub fn stamp_events_code(&self) -> Tokens {
+        let field_name = Ident::new(self.name.as_str());
+        quote!(self.#field_name.stamp_events(time))
+    }
+
+    pub fn loop_back_events_code(&self) -> Tokens {
+        let field_name = Ident::new(self.name.as_str());
+        quote!(self.#field_name.loop_back_staged_events(time))
+    }
+
+    pub fn get_code(&self) -> Tokens {
+        let struct_name = Ident::new(self.struct_name.as_str());
+        let component_name = Ident::new(self.component_name.as_str());
+        let data_name = Ident::new(format!("{}Data", &self.component_name));
+        let type_name = Ident::new(self.schema_type.rust_qualified_name().as_str());
+        let field_name = Ident::new(self.name.as_str());
+
+        quote!{
+            pub struct #struct_name;
+
+            impl ComponentEvent<Schema> for #struct_name {
+                type Component = #component_name;
+                type Value = #type_name;
+
+                fn get_event(data: &#data_name) -> &Event<#type_name> {
+                    &data.#field_name
+                }
+            }
+        }
+    }
+
     pub fn initial_code(&self) -> Tokens {
         let field_name = Ident::new(self.name.as_str());
         quote!(#field_name: Event::new())
@@ -83,8 +135,24 @@ impl Event {
         quote!(self.#field_name.len() > 0)
     }
 
+    pub fn reflection_code(&self) -> Tokens {
+        let name = self.name.as_str();
+        let event_index = self.event_index;
+        let event_type = self.schema_type.name.as_str();
+        quote!(EventInfo {
+            name: #name,
+            index: #event_index,
+            event_type: #event_type,
+        })
+    }
+
+    pub fn equality_code(&self) -> Tokens {
+        let field_name = Ident::new(self.name.as_str());
+        quote!(self.#field_name == other.#field_name)
+    }
+
     pub fn clear_events_code(&self) -> Tokens {
         let field_name = Ident::new(self.name.as_str());
-        quote!(self.#field_name.clear())
+        quote!(self.#field_name.clear_before(oldest_cursor))
     }
 }
@@ -1,6 +1,7 @@
 use json::FieldDefinition;
 use quote::Tokens;
 use schema_type::{ListType, MapType, OptionType, Type};
+use snake_to_title_case;
 use syn::Ident;
 
 pub struct Field {
@@ -31,21 +32,44 @@ impl From<FieldDefinition> for Field {
 }
 
 impl Field {
+    // Data types aren't serialisable, so only fields which aren't wrapped in a
+    // `Property` need serde attributes.
     pub fn definition_in_struct(&self, wrap_in_property: bool) -> Tokens {
-        let type_name = if wrap_in_property {
-            format!("Property<{}>", self.schema_type.rust_qualified_name())
+        let (type_name, serde_attributes) = if wrap_in_property {
+            (
+                format!("Property<{}>", self.schema_type.rust_qualified_name()),
+                quote!(),
+            )
         } else {
-            self.schema_type.rust_qualified_name()
+            (
+                self.schema_type.rust_qualified_name(),
+                self.serde_attributes_code(false),
+            )
         };
         let type_name = Ident::new(type_name);
         let field_name = Ident::new(self.name.as_str());
-        quote!(pub #field_name: #type_name)
+        quote!(#serde_attributes pub #field_name: #type_name)
     }
 
     pub fn definition_in_update(&self) -> Tokens {
         let type_name = Ident::new(self.schema_type.rust_qualified_name().as_str());
         let field_name = Ident::new(self.name.as_str());
-        quote!(pub #field_name: Option<#type_name>)
+        let serde_attributes = self.serde_attributes_code(true);
+        quote!(#serde_attributes pub #field_name: Option<#type_name>)
+    }
+
+    // Fields which aren't set in an update are left out, rather than being `null`.
+    fn serde_attributes_code(&self, in_update: bool) -> Tokens {
+        if !cfg!(feature = "serde") {
+            return quote!();
+        }
+        match (in_update, self.schema_type.is_map_type()) {
+            (false, false) => quote!(),
+            (false, true) => quote!(#[serde(with = "::spatialos_gdk::serde_support::map")]),
+            (true, false) => quote!(#[serde(default, skip_serializing_if = "Option::is_none")]),
+            (true, true) => quote!(#[serde(default, skip_serializing_if = "Option::is_none",
+                with = "::spatialos_gdk::serde_support::option_map")]),
+        }
     }
 
     pub fn apply_from_update(&self) -> Tokens {
@@ -98,8 +122,9 @@ impl Field {
         let deserialise_code =
             self.schema_type
                 .deserialise_code(&String::from("fields"), self.field_id, None);
+        let cleared_code = self.cleared_code();
         quote!(#field_name: {
-			if #count_code > 0 {
+			if #count_code > 0 || #cleared_code {
 				Some(#deserialise_code)
 			}else{
 				None
@@ -107,6 +132,18 @@ impl Field {
 		})
     }
 
+    // Empty options, lists and maps don't write anything to the update, so they are
+    // sent as cleared fields instead, so that they can be told apart from fields which
+    // weren't updated.
+    fn cleared_code(&self) -> Tokens {
+        if self.schema_type.is_collection_type() {
+            let field_id = self.field_id;
+            quote!(cleared_fields.contains(&#field_id))
+        } else {
+            quote!(false)
+        }
+    }
+
     pub fn serialise_from_dirty_data(&self) -> Tokens {
         let field_name = Ident::new(self.name.as_str());
         let prefix = if self.schema_type.is_built_in_type() {
@@ -120,13 +157,86 @@ impl Field {
             self.field_id,
             &String::from(value_name),
         );
+        let clear_code = if self.schema_type.is_collection_type() {
+            let count_code = self.schema_type
+                .count_code(&String::from("fields"), self.field_id);
+            let field_id = self.field_id;
+            quote!{
+                if #count_code == 0 {
+                    ffi::Schema_AddComponentUpdateClearedField(update, #field_id);
+                }
+            }
+        } else {
+            quote!()
+        };
         quote!{
             if self.#field_name.get_and_clear_dirty_bit() {
                 #serialise_code;
+                #clear_code
+            }
+        }
+    }
+
+    pub fn discard_dirty_data(&self) -> Tokens {
+        let field_name = Ident::new(self.name.as_str());
+        quote!(self.#field_name.get_and_clear_dirty_bit())
+    }
+
+    pub fn stamp_changed_code(&self) -> Tokens {
+        let field_name = Ident::new(self.name.as_str());
+        quote!(self.#field_name.stamp_changed(time))
+    }
+
+    pub fn changed_mask_code(&self, field_index: usize) -> Tokens {
+        let field_name = Ident::new(self.name.as_str());
+        let mask = 1u64 << field_index;
+        quote!(if self.#field_name.changed_after(time) { #mask } else { 0 })
+    }
+
+    pub fn marker_code(&self, component_name: &str, field_index: usize) -> Tokens {
+        let struct_name = Ident::new(format!(
+            "{}{}",
+            component_name,
+            snake_to_title_case(self.name.clone())
+        ));
+        let component_name = Ident::new(component_name);
+        let mask = 1u64 << field_index;
+
+        quote!{
+            pub struct #struct_name;
+
+            impl ComponentField<Schema> for #struct_name {
+                type Component = #component_name;
+
+                fn mask() -> u64 {
+                    #mask
+                }
             }
         }
     }
 
+    pub fn reflection_code(&self) -> Tokens {
+        let name = self.name.as_str();
+        let field_id = self.field_id;
+        let field_type = self.schema_type.reflection_field_type_code();
+        quote!(FieldInfo {
+            name: #name,
+            number: #field_id,
+            field_type: #field_type,
+        })
+    }
+
+    pub fn schema_value_code(&self) -> Tokens {
+        let name = self.name.as_str();
+        let field_name = Ident::new(self.name.as_str());
+        quote!((#name, self.#field_name.to_schema_value()))
+    }
+
+    pub fn equality_code(&self) -> Tokens {
+        let field_name = Ident::new(self.name.as_str());
+        quote!(self.#field_name == other.#field_name)
+    }
+
     pub fn is_dirty(&self) -> Tokens {
         let field_name = Ident::new(self.name.as_str());
         quote!(self.#field_name.get_dirty_bit() ||)
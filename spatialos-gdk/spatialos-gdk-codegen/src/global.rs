@@ -1,22 +1,25 @@
 use component::Component;
+use diagnostics::{Diagnostic, MAX_COMPONENT_FIELDS};
+use enum_type::EnumType;
 use json::JsonCollection;
 use quote::Tokens;
 use schema_type::Type;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::rc::Rc;
 use syn::Ident;
 use user_type::UserType;
 
 struct Module {
-    sub_modules: HashMap<String, Module>,
+    // Sorted by name, so that modules are always generated in the same order.
+    sub_modules: BTreeMap<String, Module>,
     tokens: Vec<Tokens>,
 }
 
 impl Module {
     pub fn new() -> Module {
         Module {
-            sub_modules: HashMap::new(),
+            sub_modules: BTreeMap::new(),
             tokens: Vec::new(),
         }
     }
@@ -37,13 +40,18 @@ impl Module {
         let sub_module_code = self.sub_modules.iter().map(|(name, module)| {
             let name = Ident::new(name.as_str());
             let module_code = module.get_code();
+
             quote!{
                 #[allow(unused_parens,unused_imports)]
                 pub mod #name {
                     use spatialos_gdk::worker::{EntityId, ComponentId};
                     use spatialos_gdk::worker::schema::{GeneratedSchema, Property, Event, Component,
-                        ComponentDataInterface, ComponentUpdateInterface, Command,
-                        CommandRequestInterface, CommandResponseInterface};
+                        ComponentDataInterface, ComponentUpdateInterface, SchemaComponent,
+                        SchemaComponentData, SchemaComponentUpdate, Command,
+                        CommandRequestInterface, CommandResponseInterface, ComponentEvent,
+                        ComponentField};
+                    use spatialos_gdk::WorldTime;
+                    use spatialos_gdk::reflection::{SchemaValue, ToSchemaValue};
                     use spatialos_gdk::worker::ffi::{self, Schema_Object, Schema_ComponentData,
                         Schema_ComponentUpdate, Schema_CommandRequest, Schema_CommandResponse};
                     use schema::{Schema, ComponentData, ComponentUpdate};
@@ -66,11 +74,31 @@ impl Module {
 
 pub struct Global {
     types: HashMap<String, Rc<RefCell<UserType>>>,
+    enums: HashMap<String, Rc<EnumType>>,
     components: Vec<Component>,
 }
 
-impl From<JsonCollection> for Global {
-    fn from(value: JsonCollection) -> Global {
+impl Global {
+    // Resolves the types referred to by name. The schema has already been checked, so
+    // this should only fail if the checks miss something, but it reports the problem
+    // rather than panicking in case they do.
+    pub fn new(mut value: JsonCollection) -> Result<Global, Vec<Diagnostic>> {
+        let mut enums: HashMap<String, Rc<EnumType>> = HashMap::new();
+        for enum_def in value.enumDefinitions.drain(..) {
+            enums.insert(
+                enum_def.qualifiedName.clone(),
+                Rc::new(EnumType::new(enum_def, &[])),
+            );
+        }
+        for type_def in value.typeDefinitions.iter_mut() {
+            for enum_def in type_def.enumDefinitions.drain(..) {
+                enums.insert(
+                    enum_def.qualifiedName.clone(),
+                    Rc::new(EnumType::new(enum_def, ::std::slice::from_ref(&type_def.name))),
+                );
+            }
+        }
+
         let mut types: HashMap<String, Rc<RefCell<UserType>>> = value
             .typeDefinitions
             .into_iter()
@@ -88,23 +116,62 @@ impl From<JsonCollection> for Global {
             .map(|component_def| Component::from(component_def))
             .collect();
 
-        for (_, mut user_type) in types.iter() {
-            for mut field in user_type.borrow_mut().fields.iter_mut() {
-                field.schema_type.got_all_types(&types);
+        let mut diagnostics = Vec::new();
+        for user_type in types.values() {
+            for field in user_type.borrow_mut().fields.iter_mut() {
+                diagnostics.extend(field.schema_type.got_all_types(&types, &enums).err());
             }
         }
 
         for component in components.iter_mut() {
-            component.data_reference_type.got_all_types(&types);
+            let mut results = vec![
+                component.data_reference_type.got_all_types(&types, &enums),
+            ];
 
-            for mut event in component.events.iter_mut() {
-                event.schema_type.got_all_types(&types);
-                event.list_schema_type.got_all_types(&types);
+            for event in component.events.iter_mut() {
+                results.push(event.schema_type.got_all_types(&types, &enums));
+                results.push(event.list_schema_type.got_all_types(&types, &enums));
             }
 
-            for mut command in component.commands.iter_mut() {
-                command.request_type.got_all_types(&types);
-                command.response_type.got_all_types(&types);
+            for command in component.commands.iter_mut() {
+                results.push(command.request_type.got_all_types(&types, &enums));
+                results.push(command.response_type.got_all_types(&types, &enums));
+            }
+
+            diagnostics.extend(results.into_iter().filter_map(Result::err));
+
+            let field_count = component
+                .data_reference_type
+                .get()
+                .map_or(0, |data_type| data_type.borrow().fields.len());
+            if field_count > MAX_COMPONENT_FIELDS {
+                let definition = component.qualified_name.join(".");
+                diagnostics.push(Diagnostic {
+                    message: format!(
+                        "Component `{}` has more than {} fields, so changes to its fields \
+                         cannot be tracked.",
+                        definition, MAX_COMPONENT_FIELDS
+                    ),
+                    location: definition,
+                });
+            }
+        }
+
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+
+        // Types are assumed to be hashable until one of their fields is found not
+        // to be, which has to be repeated until nothing changes as types can refer
+        // to each other.
+        let mut hashable_changed = true;
+        while hashable_changed {
+            hashable_changed = false;
+            for user_type in types.values() {
+                let fields_hashable = user_type.borrow().fields_hashable();
+                if user_type.borrow_mut().update_hashable(fields_hashable) {
+                    hashable_changed = true;
+                }
             }
         }
 
@@ -112,11 +179,13 @@ impl From<JsonCollection> for Global {
             types.remove(&component.data_reference_type.name);
         }
 
-        Global { types, components }
+        Ok(Global {
+            types,
+            enums,
+            components,
+        })
     }
-}
 
-impl Global {
     pub fn get_code(&self) -> Tokens {
         let number_of_components = self.components.len();
         let dynamic_handler_code = self.components.iter().map(|component| {
@@ -173,6 +242,15 @@ impl Global {
                 }
             }
         });
+        let data_component_id = self.components.iter().map(|component| {
+            let enum_name = Ident::new(component.enum_name().as_str());
+            let component_id = component.component_id;
+            quote!(&ComponentData::#enum_name(_) => #component_id)
+        });
+        let data_schema_value = self.components.iter().map(|component| {
+            let enum_name = Ident::new(component.enum_name().as_str());
+            quote!(&ComponentData::#enum_name(ref data) => data.to_schema_value())
+        });
         let update_deserialise = self.components.iter().map(|component| {
             let component_id = component.component_id;
             let update_qualified_name =
@@ -180,15 +258,27 @@ impl Global {
             quote!(#component_id => Some(#update_qualified_name::deserialise_update(update)))
         });
 
+        // Types and enums are sorted so that the generated code doesn't depend on the
+        // order of the `HashMap`s.
+        let mut types: Vec<_> = self.types.values().map(|t| t.borrow()).collect();
+        types.sort_by(|a, b| a.qualified_name.cmp(&b.qualified_name));
+        let mut enums: Vec<_> = self.enums.values().collect();
+        enums.sort_by(|a, b| a.qualified_name.cmp(&b.qualified_name));
+
         let mut module = Module::new();
 
-        for user_type in self.types.values() {
-            let user_type = user_type.borrow();
+        for user_type in types.iter() {
             let mut qualified_name = user_type.qualified_name.clone();
             qualified_name.pop();
             module.add_tokens(qualified_name, user_type.get_code());
         }
 
+        for enum_type in enums.iter() {
+            let mut qualified_name = enum_type.qualified_name.clone();
+            qualified_name.pop();
+            module.add_tokens(qualified_name, enum_type.get_code());
+        }
+
         for component in self.components.iter() {
             let mut qualified_name = component.qualified_name.clone();
             qualified_name.pop();
@@ -197,16 +287,26 @@ impl Global {
 
         let module_code = module.get_code();
 
+        let type_reflection = types.iter().map(|user_type| user_type.reflection_code());
+        let enum_reflection = enums.iter().map(|enum_type| enum_type.reflection_code());
+        let component_reflection = self.components
+            .iter()
+            .map(|component| component.reflection_code());
+
         quote!{
             #[allow(unused_variables,unused_imports)]
             pub mod schema {
-                use spatialos_gdk::worker::schema::{Component, GeneratedSchema,
+                use spatialos_gdk::worker::schema::{Component, SchemaComponent, GeneratedSchema,
                     GlobalComponentDataInterface, GlobalComponentUpdateInterface,
-                    ComponentDataInterface, ComponentUpdateInterface,
+                    ComponentDataInterface, ComponentUpdateInterface, SchemaComponentData,
+                    SchemaComponentUpdate, CommandRequestInterface, CommandResponseInterface,
                     DynamicComponentHandler};
                 use spatialos_gdk::worker::ffi::{Schema_ComponentData, Schema_ComponentUpdate,
                     Schema_CommandResponse, Schema_CommandRequest};
                 use spatialos_gdk::worker::{ComponentId};
+                use spatialos_gdk::reflection::{SchemaInfo, ComponentInfo, TypeInfo, EnumInfo,
+                    EnumValueInfo, FieldInfo, EventInfo, CommandInfo, FieldType, ValueType,
+                    SchemaValue, ToSchemaValue};
                 use std::any::Any;
                 use spatialos_gdk::{ComponentBitField};
                 use std::collections::HashMap;
@@ -219,6 +319,12 @@ impl Global {
                 #[derive(Default)]
                 pub struct Schema;
 
+                static SCHEMA_INFO: SchemaInfo = SchemaInfo {
+                    components: &[#(#component_reflection,)*],
+                    types: &[#(#type_reflection,)*],
+                    enums: &[#(#enum_reflection,)*],
+                };
+
                 #module_code
 
                 impl GeneratedSchema for Schema {
@@ -275,11 +381,17 @@ impl Global {
                             _ => None
                         }
                     }
+
+                    fn schema_info() -> &'static SchemaInfo {
+                        &SCHEMA_INFO
+                    }
                 }
 
 
+                // Local components are given the bits after the schema components.
                 const GENERATED_NUMBER_OF_FIELDS: usize =
-                    (1 + ((GENERATED_NUMBER_OF_COMPONENTS - 1) / spatialos_gdk::FIELD_SIZE_BITS));
+                    (1 + ((GENERATED_NUMBER_OF_COMPONENTS + spatialos_gdk::MAX_LOCAL_COMPONENTS - 1)
+                        / spatialos_gdk::FIELD_SIZE_BITS));
 
                 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Hash, Debug, Default)]
                 pub struct GeneratedComponentBitField {
@@ -305,7 +417,8 @@ impl Global {
                     fn get_unique_index(component_id: ComponentId) -> Option<usize> {
                         match component_id {
                             #(#unique_index_match,)*
-                            _ => None,
+                            _ => spatialos_gdk::local_component_unique_index(
+                                component_id, GENERATED_NUMBER_OF_COMPONENTS),
                         }
                     }
                 }
@@ -340,6 +453,18 @@ impl Global {
                             #(#data_apply_update,)*
                         }
                     }
+
+                    fn component_id(&self) -> ComponentId {
+                        match self {
+                            #(#data_component_id,)*
+                        }
+                    }
+
+                    fn to_schema_value(&self) -> SchemaValue {
+                        match self {
+                            #(#data_schema_value,)*
+                        }
+                    }
                 }
 
                 #[allow(dead_code, unused_variables)]
@@ -356,3 +481,65 @@ impl Global {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    // Builds the schema without checking it first, as the checks would report these
+    // problems before `Global::new` is reached.
+    fn errors(fields: &str) -> Vec<String> {
+        let json = format!(
+            r#"{{
+                "typeDefinitions": [
+                    {{ "name": "PlayerData", "qualifiedName": "test.PlayerData",
+                       "fieldDefinitions": [{}] }}
+                ],
+                "componentDefinitions": [
+                    {{ "id": 1000, "name": "Player", "qualifiedName": "test.Player",
+                       "dataDefinition": {{ "userType": "test.PlayerData" }},
+                       "eventDefinitions": [], "commandDefinitions": [] }}
+                ]
+            }}"#,
+            fields
+        );
+        let collection: JsonCollection = serde_json::from_str(&json).unwrap();
+        Global::new(collection)
+            .err()
+            .expect("the schema should be invalid")
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn reports_unresolved_types() {
+        let fields =
+            r#"{ "name": "target", "number": 1, "singularType": { "userType": "test.Missing" } }"#;
+        assert_eq!(
+            errors(fields),
+            vec!["test.Missing: Unknown type `test.Missing`."]
+        );
+    }
+
+    #[test]
+    fn reports_components_with_too_many_fields() {
+        let fields: Vec<String> = (1..MAX_COMPONENT_FIELDS + 2)
+            .map(|number| {
+                format!(
+                    r#"{{ "name": "field_{0}", "number": {0},
+                          "singularType": {{ "builtInType": "int32" }} }}"#,
+                    number
+                )
+            })
+            .collect();
+        assert_eq!(
+            errors(&fields.join(",")),
+            vec![
+                "test.Player: Component `test.Player` has more than 64 fields, so changes to \
+                 its fields cannot be tracked.",
+            ]
+        );
+    }
+}
@@ -6,12 +6,15 @@ use std::path::Path;
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct JsonCollection {
+    #[serde(default)]
+    pub enumDefinitions: Vec<EnumDefinition>,
     pub typeDefinitions: Vec<TypeDefinition>,
     pub componentDefinitions: Vec<ComponentDefinition>,
 }
 
 impl JsonCollection {
     pub fn append(&mut self, mut other: JsonCollection) {
+        self.enumDefinitions.append(&mut other.enumDefinitions);
         self.typeDefinitions.append(&mut other.typeDefinitions);
         self.componentDefinitions
             .append(&mut other.componentDefinitions);
@@ -27,6 +30,8 @@ pub struct ComponentDefinition {
     pub dataDefinition: SchemaTypeDefinition,
     pub eventDefinitions: Vec<EventDefinition>,
     pub commandDefinitions: Vec<CommandDefinition>,
+    #[serde(default)]
+    pub sourceReference: Option<SourceReference>,
 }
 
 #[allow(non_snake_case)]
@@ -55,6 +60,27 @@ pub struct TypeDefinition {
     pub fieldDefinitions: Vec<FieldDefinition>,
     pub name: String,
     pub qualifiedName: String,
+    #[serde(default)]
+    pub enumDefinitions: Vec<EnumDefinition>,
+    #[serde(default)]
+    pub sourceReference: Option<SourceReference>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnumDefinition {
+    pub name: String,
+    pub qualifiedName: String,
+    pub valueDefinitions: Vec<EnumValueDefinition>,
+    #[serde(default)]
+    pub sourceReference: Option<SourceReference>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnumValueDefinition {
+    pub name: String,
+    pub value: u32,
 }
 
 #[allow(non_snake_case)]
@@ -66,6 +92,8 @@ pub struct FieldDefinition {
     pub optionType: Option<OptionTypeDefinition>,
     pub listType: Option<ListTypeDefinition>,
     pub mapType: Option<MapTypeDefinition>,
+    #[serde(default)]
+    pub sourceReference: Option<SourceReference>,
 }
 
 #[allow(non_snake_case)]
@@ -94,6 +122,16 @@ pub struct SchemaTypeDefinition {
     pub userType: Option<String>,
 }
 
+// Where a definition is in the schema files, which is used to point at it in errors.
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SourceReference {
+    #[serde(default)]
+    pub filePath: String,
+    pub line: u32,
+    pub column: u32,
+}
+
 pub fn parse_json<P: AsRef<Path>>(path: P) -> Result<JsonCollection, Box<Error>> {
     let file = File::open(path)?;
     let json = serde_json::from_reader(file)?;
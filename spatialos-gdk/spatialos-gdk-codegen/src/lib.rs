@@ -13,16 +13,28 @@ extern crate serde_json;
 extern crate serde_derive;
 
 mod command;
+mod compatibility;
 mod component;
+mod diagnostics;
+mod enum_type;
 mod event;
 mod field;
 mod global;
 mod json;
+mod rust_component;
+mod schema_parser;
 mod schema_type;
 mod user_type;
 
+pub use compatibility::{Compatibility, SchemaChange};
+pub use diagnostics::Diagnostic;
 use global::Global;
+use json::JsonCollection;
+pub use rust_component::GeneratedSchemaFile;
+pub use schema_parser::SchemaError;
 use quote::Tokens;
+use std::error::Error;
+use std::fmt;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
@@ -37,7 +49,10 @@ pub fn codegen<P: AsRef<Path>, O: AsRef<Path> + Clone>(json_dir: P, output_file:
             .map(|e| PathBuf::from(e.path()))
             .collect();
 
-        let tokens = parse_json(json_files);
+        let tokens = match parse_json(json_files) {
+            Ok(tokens) => tokens,
+            Err(error) => panic!("Invalid schema:\n{}", error),
+        };
 
         ::std::fs::write(output_file.clone(), tokens.to_string()).expect("Unable to write file");
 
@@ -45,17 +60,143 @@ pub fn codegen<P: AsRef<Path>, O: AsRef<Path> + Clone>(json_dir: P, output_file:
     }
 }
 
-fn format_file<P: AsRef<Path>>(path: P) {
-    let output = Command::new("rustfmt")
-        .arg(path.as_ref().to_str().unwrap())
-        .output()
-        .expect("ls command failed to start");
+/// Generates code from the `.schema` files in the given directories, without
+/// needing the `spatial` CLI. The `improbable` standard library is built in.
+///
+/// Panics with the file, line and column of every error in the schema.
+pub fn codegen_from_schema<P: AsRef<Path>, O: AsRef<Path> + Clone>(
+    schema_dirs: Vec<P>,
+    output_file: O,
+) {
+    let tokens = match parse_schema(schema_dirs) {
+        Ok(tokens) => tokens,
+        Err(error) => panic!("Invalid schema:\n{}", error),
+    };
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
+    ::std::fs::write(output_file.clone(), tokens.to_string()).expect("Unable to write file");
 
-    if !output.status.success() {
-        panic!("Formatting generated code failed: {} {}", stdout, stderr);
+    format_file(output_file);
+}
+
+/// Generates code in the same way as `codegen_from_schema`, including every struct marked
+/// with `#[spatial_component(id = ..)]` in the `.rs` files below `source_dirs`.
+///
+/// A `.schema` file is generated for each of these components in the `schema` directory
+/// of `OUT_DIR`, next to the generated code, so the build never changes the source tree.
+/// Use `codegen_with_rust_components_in_schema_dir` to write them where other workers and
+/// the `spatial` CLI can see them.
+pub fn codegen_with_rust_components<P, R, O>(
+    schema_dirs: Vec<P>,
+    source_dirs: Vec<R>,
+    output_file: O,
+) where
+    P: AsRef<Path>,
+    R: AsRef<Path>,
+    O: AsRef<Path> + Clone,
+{
+    let out_dir = ::std::env::var_os("OUT_DIR")
+        .map(PathBuf::from)
+        .expect("OUT_DIR is not set, so this must be called from a build script");
+    codegen_with_rust_components_in_schema_dir(
+        schema_dirs,
+        source_dirs,
+        out_dir.join("schema"),
+        output_file,
+    );
+}
+
+/// Generates code in the same way as `codegen_with_rust_components`, but writes the
+/// `.schema` file of each component into `schema_output_dir`, which is usually one of the
+/// `schema_dirs`, so that other workers and the `spatial` CLI see the same components.
+/// Files which were generated for components that no longer exist are removed.
+///
+/// This changes the source tree during the build, so it fails for read-only or vendored
+/// sources and in `cargo package`, and crates which share `schema_output_dir` must not be
+/// built at the same time. It is best used in a workspace which is only built locally.
+pub fn codegen_with_rust_components_in_schema_dir<P, R, D, O>(
+    schema_dirs: Vec<P>,
+    source_dirs: Vec<R>,
+    schema_output_dir: D,
+    output_file: O,
+) where
+    P: AsRef<Path>,
+    R: AsRef<Path>,
+    D: AsRef<Path>,
+    O: AsRef<Path> + Clone,
+{
+    let (tokens, schema_files) = match parse_schema_with_rust_components(schema_dirs, source_dirs)
+    {
+        Ok(result) => result,
+        Err(error) => panic!("Invalid schema:\n{}", error),
+    };
+
+    write_generated_schema(schema_output_dir, &schema_files);
+
+    ::std::fs::write(output_file.clone(), tokens.to_string()).expect("Unable to write file");
+
+    format_file(output_file);
+}
+
+// Only files which were generated from this crate before are ever overwritten or removed,
+// so that a component defined in Rust can't replace a hand written schema file, or one
+// generated by another worker.
+fn write_generated_schema<D: AsRef<Path>>(
+    schema_output_dir: D,
+    schema_files: &[GeneratedSchemaFile],
+) {
+    let schema_output_dir = schema_output_dir.as_ref();
+    let is_generated = |path: &Path| {
+        ::std::fs::read_to_string(path)
+            .map(|source| rust_component::is_generated_by_this_crate(&source))
+            .unwrap_or(false)
+    };
+
+    let paths: Vec<PathBuf> = schema_files
+        .iter()
+        .map(|file| schema_output_dir.join(file.path.as_str()))
+        .collect();
+    for entry in WalkDir::new(schema_output_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) == Some("schema")
+            && !paths.iter().any(|p| p.as_path() == path) && is_generated(path)
+        {
+            ::std::fs::remove_file(path).expect("Unable to remove generated schema file");
+        }
+    }
+
+    for (file, path) in schema_files.iter().zip(paths.iter()) {
+        if path.exists() {
+            if !is_generated(path) {
+                panic!(
+                    "{} was not generated from this crate, so it will not be overwritten.",
+                    path.display()
+                );
+            }
+            if ::std::fs::read_to_string(path).ok().as_ref() == Some(&file.source) {
+                continue;
+            }
+        }
+        ::std::fs::create_dir_all(path.parent().unwrap()).expect("Unable to create directory");
+        ::std::fs::write(path, file.source.as_str()).expect("Unable to write file");
+    }
+}
+
+// Formatting only makes the generated code easier to read, so if `rustfmt` isn't
+// installed or fails, the unformatted code is kept and a warning is shown.
+fn format_file<P: AsRef<Path>>(path: P) {
+    match Command::new("rustfmt").arg(path.as_ref()).output() {
+        Ok(ref output) if output.status.success() => {}
+        Ok(output) => println!(
+            "cargo:warning=Formatting generated code failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(error) => println!(
+            "cargo:warning=Unable to run rustfmt on generated code: {}",
+            error
+        ),
     }
 }
 
@@ -89,18 +230,136 @@ fn generate_json_ast<P: AsRef<Path>>(json_dir: &P) -> bool {
     }
 }
 
-pub fn parse_json<P: AsRef<Path>>(paths: Vec<P>) -> Tokens {
-    let mut collection = json::JsonCollection::default();
+/// An error which stops code from being generated.
+#[derive(Debug)]
+pub enum CodegenError {
+    /// An AST JSON file could not be read.
+    Json(PathBuf, String),
+    /// A schema file could not be parsed.
+    Schema(SchemaError),
+    /// The schema was parsed, but code could not be generated from it.
+    Invalid(Vec<Diagnostic>),
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodegenError::Json(ref path, ref error) => write!(f, "{}: {}", path.display(), error),
+            CodegenError::Schema(ref error) => write!(f, "{}", error),
+            CodegenError::Invalid(ref diagnostics) => {
+                for diagnostic in diagnostics.iter() {
+                    writeln!(f, "{}", diagnostic)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for CodegenError {
+    fn description(&self) -> &str {
+        "Unable to generate code from the schema"
+    }
+}
+
+impl From<SchemaError> for CodegenError {
+    fn from(error: SchemaError) -> CodegenError {
+        CodegenError::Schema(error)
+    }
+}
+
+pub fn parse_json<P: AsRef<Path>>(paths: Vec<P>) -> Result<Tokens, CodegenError> {
+    generate_code(read_json(paths)?)
+}
+
+fn read_json<P: AsRef<Path>>(paths: Vec<P>) -> Result<JsonCollection, CodegenError> {
+    let mut collection = JsonCollection::default();
     for path in paths.into_iter() {
-        let result = json::parse_json(path).unwrap();
+        let result = json::parse_json(&path).map_err(|error| {
+            CodegenError::Json(PathBuf::from(path.as_ref()), error.to_string())
+        })?;
         collection.append(result);
     }
+    Ok(collection)
+}
+
+pub fn parse_schema<P: AsRef<Path>>(schema_dirs: Vec<P>) -> Result<Tokens, CodegenError> {
+    let collection = schema_parser::parse_schema_dirs(schema_dirs)?;
+    generate_code(collection)
+}
+
+/// Generates code from the schema and the Rust components in the same way as
+/// `codegen_with_rust_components`, returning the `.schema` files generated for the Rust
+/// components rather than writing them.
+pub fn parse_schema_with_rust_components<P: AsRef<Path>, R: AsRef<Path>>(
+    schema_dirs: Vec<P>,
+    source_dirs: Vec<R>,
+) -> Result<(Tokens, Vec<GeneratedSchemaFile>), CodegenError> {
+    let rust_components = rust_component::find_rust_components(&source_dirs)?;
+    let (collection, schema_files) =
+        schema_parser::parse_schema_dirs_with_rust_components(schema_dirs, &rust_components)?;
+    Ok((generate_code(collection)?, schema_files))
+}
 
-    let schema = Global::from(collection);
-    schema.get_code()
+/// Compares the `.schema` files in two sets of directories, and returns every change
+/// from the old schema to the new one which affects how data is sent, such as changed
+/// component IDs and renumbered fields. Each change is either breaking, meaning that
+/// snapshots and workers using the old schema won't work with the new one, or not.
+pub fn compare_schema<P: AsRef<Path>, Q: AsRef<Path>>(
+    old_schema_dirs: Vec<P>,
+    new_schema_dirs: Vec<Q>,
+) -> Result<Vec<SchemaChange>, CodegenError> {
+    let old = schema_parser::parse_schema_dirs(old_schema_dirs)?;
+    let new = schema_parser::parse_schema_dirs(new_schema_dirs)?;
+    Ok(compatibility::compare(&old, &new))
+}
+
+/// Compares two sets of AST JSON files in the same way as `compare_schema`.
+pub fn compare_json<P: AsRef<Path>, Q: AsRef<Path>>(
+    old_paths: Vec<P>,
+    new_paths: Vec<Q>,
+) -> Result<Vec<SchemaChange>, CodegenError> {
+    let old = read_json(old_paths)?;
+    let new = read_json(new_paths)?;
+    Ok(compatibility::compare(&old, &new))
+}
+
+fn generate_code(collection: JsonCollection) -> Result<Tokens, CodegenError> {
+    let diagnostics = diagnostics::check(&collection);
+    if !diagnostics.is_empty() {
+        return Err(CodegenError::Invalid(diagnostics));
+    }
+
+    let schema = Global::new(collection).map_err(CodegenError::Invalid)?;
+    Ok(schema.get_code())
+}
+
+// Makes a generated type implement `Serialize` and `Deserialize` if the `serde` feature
+// is enabled. The generated code uses the `serde` crate re-exported by the GDK, so it
+// doesn't need to be a dependency of the worker.
+fn serde_derive_code() -> Tokens {
+    if cfg!(feature = "serde") {
+        quote!{
+            #[derive(::spatialos_gdk::serde::Serialize, ::spatialos_gdk::serde::Deserialize)]
+            #[serde(crate = "::spatialos_gdk::serde")]
+        }
+    } else {
+        quote!()
+    }
 }
 
 fn to_rust_qualified_name(qualified_name: &str) -> String {
     let parts: Vec<&str> = qualified_name.split(".").collect();
     format!("::schema::{}", parts.join("::"))
 }
+
+fn snake_to_title_case(snake_case: String) -> String {
+    snake_case
+        .split("_")
+        .flat_map(|word| {
+            let mut v: Vec<char> = word.chars().collect();
+            v[0] = v[0].to_uppercase().nth(0).unwrap();
+            v
+        })
+        .collect()
+}
@@ -0,0 +1,422 @@
+use schema_parser::SchemaError;
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use syn::{self, Body, DeriveInput, Field, Lit, MetaItem, NestedMetaItem, PathParameters, Ty,
+          VariantData};
+use walkdir::WalkDir;
+
+// The start of the first line of every `.schema` file generated from a Rust component,
+// which is followed by the crate and the file the component is defined in.
+const GENERATED_SCHEMA_HEADER: &str = "// Generated by spatialos-gdk-codegen from";
+
+const ATTRIBUTE: &str = "#[spatial_component";
+
+/// A `.schema` file generated from a component defined in Rust.
+#[derive(Debug, Clone)]
+pub struct GeneratedSchemaFile {
+    /// The path of the file, relative to the schema directory it is written to.
+    pub path: String,
+    pub source: String,
+}
+
+// The type of a field of a Rust component, as written in schema.
+enum FieldType {
+    Singular(TypeName),
+    Option(TypeName),
+    List(TypeName),
+    Map(TypeName, TypeName),
+}
+
+enum TypeName {
+    BuiltIn(&'static str),
+    // The path of a generated type, without the leading `schema` module.
+    User(Vec<String>),
+}
+
+// A struct marked with `#[spatial_component(id = ..)]`.
+pub struct RustComponent {
+    file: PathBuf,
+    line: usize,
+    column: usize,
+    package: String,
+    name: String,
+    id: u32,
+    fields: Vec<(String, FieldType)>,
+}
+
+// Maps the name of every type and enum in the generated code, qualified by its package,
+// to its name in schema and the import path of the file it is defined in.
+pub type ModelSymbols = HashMap<String, (String, String)>;
+
+// Finds every struct marked with `#[spatial_component]` in the `.rs` files below the
+// given directories.
+pub fn find_rust_components<P: AsRef<Path>>(
+    source_dirs: &[P],
+) -> Result<Vec<RustComponent>, SchemaError> {
+    let mut components = Vec::new();
+    for source_dir in source_dirs.iter() {
+        for entry in WalkDir::new(source_dir).into_iter().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("rs") {
+                continue;
+            }
+            let source = fs::read_to_string(path).map_err(|e| SchemaError {
+                file: PathBuf::from(path),
+                line: 0,
+                column: 0,
+                message: format!("Unable to read file: {}", e),
+            })?;
+
+            let mut line_start = 0;
+            for (index, line) in source.split('\n').enumerate() {
+                let indent = line.len() - line.trim_start().len();
+                if line.trim_start().starts_with(ATTRIBUTE) {
+                    let start = line_start + indent;
+                    components.push(RustComponent::parse(
+                        PathBuf::from(path),
+                        index + 1,
+                        indent + 1,
+                        item_source(&source[start..]),
+                    )?);
+                }
+                line_start += line.len() + 1;
+            }
+        }
+    }
+    Ok(components)
+}
+
+// Finds the end of the item starting at the beginning of `source`, which is either the
+// `}` closing its body or a `;` outside of any brackets.
+fn item_source(source: &str) -> &str {
+    let mut depth = 0;
+    let mut chars = source.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '/' if chars.peek().map(|&(_, c)| c) == Some('/') => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => (),
+                    }
+                }
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' => depth -= 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return &source[..index + 1];
+                }
+            }
+            ';' if depth == 0 => return &source[..index + 1],
+            _ => (),
+        }
+    }
+    source
+}
+
+fn crate_name() -> String {
+    env::var("CARGO_PKG_NAME").unwrap_or_default()
+}
+
+// Components are put in a package named after the crate, unless one is given.
+fn default_package() -> String {
+    crate_name().replace("-", "_")
+}
+
+// Files generated from this crate's Rust components are generated again from the Rust
+// components themselves, rather than being read. Files generated by other crates which
+// share the same schema directory are read in the same way as any other schema.
+pub fn is_generated_by_this_crate(source: &str) -> bool {
+    source.starts_with(&format!("{} {}/", GENERATED_SCHEMA_HEADER, crate_name()))
+}
+
+impl RustComponent {
+    fn parse(
+        file: PathBuf,
+        line: usize,
+        column: usize,
+        source: &str,
+    ) -> Result<RustComponent, SchemaError> {
+        let error = |message: String| SchemaError {
+            file: file.clone(),
+            line,
+            column,
+            message,
+        };
+
+        let input: DeriveInput = syn::parse_derive_input(source).map_err(|e| {
+            error(format!(
+                "`#[spatial_component]` must be used on a struct: {}",
+                e
+            ))
+        })?;
+        let name = input.ident.to_string();
+        if !input.generics.lifetimes.is_empty() || !input.generics.ty_params.is_empty() {
+            return Err(error(format!("Component `{}` cannot be generic.", name)));
+        }
+
+        let mut id = None;
+        let mut package = default_package();
+        for attr in input.attrs.iter().filter(|a| a.name() == "spatial_component") {
+            let items = match attr.value {
+                MetaItem::List(_, ref items) => items,
+                _ => return Err(error(String::from("Expected `#[spatial_component(id = ..)]`."))),
+            };
+            for item in items.iter() {
+                match *item {
+                    NestedMetaItem::MetaItem(MetaItem::NameValue(ref key, Lit::Int(value, _)))
+                        if key == "id" =>
+                    {
+                        id = Some(value as u32)
+                    }
+                    NestedMetaItem::MetaItem(MetaItem::NameValue(
+                        ref key,
+                        Lit::Str(ref value, _),
+                    )) if key == "package" =>
+                    {
+                        package = value.clone()
+                    }
+                    _ => {
+                        return Err(error(String::from(
+                            "Expected `id = <integer>` or `package = \"<package>\"`.",
+                        )))
+                    }
+                }
+            }
+        }
+        let id = match id {
+            Some(id) => id,
+            None => return Err(error(format!("Component `{}` does not have an `id`.", name))),
+        };
+
+        let fields: &[Field] = match input.body {
+            Body::Struct(VariantData::Struct(ref fields)) => fields,
+            Body::Struct(VariantData::Unit) => &[],
+            _ => {
+                return Err(error(format!(
+                    "Component `{}` must be a struct with named fields.",
+                    name
+                )))
+            }
+        };
+        let mut field_types = Vec::new();
+        for field in fields.iter() {
+            let field_name = field.ident.as_ref().unwrap().to_string();
+            let field_type = FieldType::from_ty(&field.ty).ok_or_else(|| {
+                error(format!(
+                    "The type of field `{}` of component `{}` cannot be used in schema.",
+                    field_name, name
+                ))
+            })?;
+            field_types.push((field_name, field_type));
+        }
+
+        Ok(RustComponent {
+            file,
+            line,
+            column,
+            package,
+            name,
+            id,
+            fields: field_types,
+        })
+    }
+
+    fn error(&self, message: String) -> SchemaError {
+        SchemaError {
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+            message,
+        }
+    }
+
+    // The file is named after the component, in a directory for each part of its package.
+    pub fn schema_path(&self) -> String {
+        let mut file_name = String::new();
+        for (index, c) in self.name.chars().enumerate() {
+            if c.is_uppercase() && index > 0 {
+                file_name.push('_');
+            }
+            file_name.extend(c.to_lowercase());
+        }
+        let mut parts: Vec<&str> = self.package.split(".").collect();
+        let file_name = format!("{}.schema", file_name);
+        parts.push(file_name.as_str());
+        parts.join("/")
+    }
+
+    // Writes the component in schema, with every user type fully qualified and imported.
+    pub fn schema_file(&self, symbols: &ModelSymbols) -> Result<GeneratedSchemaFile, SchemaError> {
+        let path = self.schema_path();
+        let mut imports = BTreeSet::new();
+        let mut fields = String::new();
+        for (index, (name, field_type)) in self.fields.iter().enumerate() {
+            let mut resolve = |type_name: &TypeName| -> Result<String, SchemaError> {
+                match *type_name {
+                    TypeName::BuiltIn(name) => Ok(String::from(name)),
+                    TypeName::User(ref segments) => {
+                        let (schema_name, import_path) = self.resolve(symbols, segments)?;
+                        if import_path != path {
+                            imports.insert(import_path);
+                        }
+                        Ok(schema_name)
+                    }
+                }
+            };
+            let schema_type = match *field_type {
+                FieldType::Singular(ref t) => resolve(t)?,
+                FieldType::Option(ref t) => format!("option<{}>", resolve(t)?),
+                FieldType::List(ref t) => format!("list<{}>", resolve(t)?),
+                FieldType::Map(ref k, ref v) => format!("map<{}, {}>", resolve(k)?, resolve(v)?),
+            };
+            fields.push_str(&format!("  {} {} = {};\n", schema_type, name, index + 1));
+        }
+
+        let mut source = format!(
+            "{} {}/{}. Do not edit.\npackage {};\n\n",
+            GENERATED_SCHEMA_HEADER,
+            crate_name(),
+            self.file.display(),
+            self.package
+        );
+        for import in imports.iter() {
+            source.push_str(&format!("import \"{}\";\n", import));
+        }
+        if !imports.is_empty() {
+            source.push('\n');
+        }
+        source.push_str(&format!(
+            "component {} {{\n  id = {};\n{}}}\n",
+            self.name, self.id, fields
+        ));
+
+        Ok(GeneratedSchemaFile { path, source })
+    }
+
+    // A single name is looked up in the component's package first, in the same way as
+    // a name written in schema, and then in every other package, as the Rust struct
+    // will usually import it from there.
+    fn resolve(
+        &self,
+        symbols: &ModelSymbols,
+        segments: &[String],
+    ) -> Result<(String, String), SchemaError> {
+        let name = segments.join(".");
+        let mut found = if segments.len() == 1 {
+            symbols
+                .get(&format!("{}.{}", self.package, name))
+                .or_else(|| symbols.get(&name))
+                .cloned()
+        } else {
+            symbols.get(&name).cloned()
+        };
+        if found.is_none() && segments.len() == 1 {
+            let suffix = format!(".{}", name);
+            let mut matches = symbols.iter().filter(|&(key, _)| key.ends_with(&suffix));
+            if let (Some((_, symbol)), None) = (matches.next(), matches.next()) {
+                found = Some(symbol.clone());
+            }
+        }
+        found.ok_or_else(|| {
+            self.error(format!(
+                "`{}` used by component `{}` is not a type defined in schema, or is \
+                 defined in more than one package.",
+                segments.join("::"),
+                self.name
+            ))
+        })
+    }
+}
+
+impl FieldType {
+    fn from_ty(ty: &Ty) -> Option<FieldType> {
+        let segment = match *ty {
+            Ty::Path(None, ref path) => path.segments.last()?,
+            _ => return None,
+        };
+        let parameters = match segment.parameters {
+            PathParameters::AngleBracketed(ref data) => &data.types,
+            _ => return None,
+        };
+        match (segment.ident.as_ref(), parameters.len()) {
+            ("Option", 1) => Some(FieldType::Option(TypeName::from_ty(&parameters[0])?)),
+            ("Vec", 1) if TypeName::is_u8(&parameters[0]) => {
+                Some(FieldType::Singular(TypeName::BuiltIn("bytes")))
+            }
+            ("Vec", 1) => Some(FieldType::List(TypeName::from_ty(&parameters[0])?)),
+            ("HashMap", 2) => Some(FieldType::Map(
+                TypeName::from_ty(&parameters[0])?,
+                TypeName::from_ty(&parameters[1])?,
+            )),
+            _ => Some(FieldType::Singular(TypeName::from_ty(ty)?)),
+        }
+    }
+}
+
+impl TypeName {
+    fn is_u8(ty: &Ty) -> bool {
+        match *ty {
+            Ty::Path(None, ref path) => path.segments.len() == 1 && path.segments[0].ident == "u8",
+            _ => false,
+        }
+    }
+
+    fn from_ty(ty: &Ty) -> Option<TypeName> {
+        let path = match *ty {
+            Ty::Path(None, ref path) => path,
+            _ => return None,
+        };
+        if path.segments
+            .iter()
+            .any(|segment| !segment.parameters.is_empty())
+        {
+            return None;
+        }
+        let mut segments: Vec<String> = path.segments
+            .iter()
+            .map(|segment| segment.ident.to_string())
+            .collect();
+
+        if segments.last().unwrap() == "EntityId" {
+            return Some(TypeName::BuiltIn("EntityId"));
+        }
+        if segments.len() == 1 {
+            let built_in = match segments[0].as_str() {
+                "bool" => Some("bool"),
+                "u32" => Some("uint32"),
+                "u64" => Some("uint64"),
+                "i32" => Some("int32"),
+                "i64" => Some("int64"),
+                "f32" => Some("float"),
+                "f64" => Some("double"),
+                "String" => Some("string"),
+                _ => None,
+            };
+            if let Some(built_in) = built_in {
+                return Some(TypeName::BuiltIn(built_in));
+            }
+        }
+
+        // Generated types live in the `schema` module, in a module for each part of
+        // their package.
+        if segments.len() > 1 && segments[0] == "schema" {
+            segments.remove(0);
+        }
+        Some(TypeName::User(segments))
+    }
+}
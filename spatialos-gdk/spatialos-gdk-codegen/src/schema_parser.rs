@@ -0,0 +1,1386 @@
+use json::{CommandDefinition, ComponentDefinition, EnumDefinition, EnumValueDefinition,
+           EventDefinition, FieldDefinition, JsonCollection, ListTypeDefinition,
+           MapTypeDefinition, OptionTypeDefinition, SchemaTypeDefinition, SourceReference,
+           TypeDefinition};
+use rust_component::{self, GeneratedSchemaFile, ModelSymbols, RustComponent};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+const STANDARD_LIBRARY_PATH: &str = "improbable/standard_library.schema";
+const STANDARD_LIBRARY: &str = include_str!("../schema/improbable/standard_library.schema");
+
+pub const BUILT_IN_TYPES: &[&str] = &[
+    "bool", "uint32", "uint64", "int32", "int64", "sint32", "sint64", "fixed32", "fixed64",
+    "sfixed32", "sfixed64", "float", "double", "string", "bytes", "EntityId",
+];
+
+/// An error found while parsing a schema file, or a component defined in Rust.
+#[derive(Debug, Clone)]
+pub struct SchemaError {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file.display(),
+            self.line,
+            self.column,
+            self.message
+        )
+    }
+}
+
+impl Error for SchemaError {
+    fn description(&self) -> &str {
+        self.message.as_str()
+    }
+}
+
+// Parses every `.schema` file below the given directories into the same model
+// as the AST JSON produced by `spatial process_schema`. The standard library is
+// built in, unless one of the directories provides its own copy.
+pub fn parse_schema_dirs<P: AsRef<Path>>(
+    schema_dirs: Vec<P>,
+) -> Result<JsonCollection, SchemaError> {
+    let files = read_schema_dirs(schema_dirs, false)?;
+    collection_from_files(files)
+}
+
+// Parses the schema in the same way as `parse_schema_dirs`, along with a generated
+// `.schema` file for each of the given Rust components.
+pub fn parse_schema_dirs_with_rust_components<P: AsRef<Path>>(
+    schema_dirs: Vec<P>,
+    rust_components: &[RustComponent],
+) -> Result<(JsonCollection, Vec<GeneratedSchemaFile>), SchemaError> {
+    let mut files = read_schema_dirs(schema_dirs, true)?;
+
+    let mut model_symbols = ModelSymbols::new();
+    for file in files.iter() {
+        // Any symbols defined more than once are reported once the collection is built.
+        let mut symbols = Symbols::new();
+        let _ = file.add_symbols(&mut symbols);
+        for (schema_name, model_name) in symbols.into_iter() {
+            // Nested enums are named after their outer types in Rust, in the same way as
+            // nested types already are in the model.
+            let package = file.package_name();
+            let rust_name = format!(
+                "{}.{}",
+                package,
+                model_name[package.len() + 1..].replace(".", "")
+            );
+            model_symbols.insert(rust_name, (schema_name, file.import_path.clone()));
+        }
+    }
+
+    let mut generated_files = Vec::new();
+    for component in rust_components.iter() {
+        let generated = component.schema_file(&model_symbols)?;
+        files.push(parse_file(
+            PathBuf::from(generated.path.as_str()),
+            generated.path.clone(),
+            generated.source.as_str(),
+        )?);
+        generated_files.push(generated);
+    }
+
+    Ok((collection_from_files(files)?, generated_files))
+}
+
+fn read_schema_dirs<P: AsRef<Path>>(
+    schema_dirs: Vec<P>,
+    skip_generated: bool,
+) -> Result<Vec<SchemaFile>, SchemaError> {
+    let mut files = Vec::new();
+    for schema_dir in schema_dirs.iter() {
+        let schema_dir = schema_dir.as_ref();
+        for entry in WalkDir::new(schema_dir).into_iter().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("schema") {
+                continue;
+            }
+            let source = fs::read_to_string(path).map_err(|e| SchemaError {
+                file: PathBuf::from(path),
+                line: 0,
+                column: 0,
+                message: format!("Unable to read file: {}", e),
+            })?;
+            if skip_generated && rust_component::is_generated_by_this_crate(&source) {
+                continue;
+            }
+            let import_path = path.strip_prefix(schema_dir)
+                .unwrap()
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<String>>()
+                .join("/");
+            files.push(parse_file(PathBuf::from(path), import_path, source.as_str())?);
+        }
+    }
+
+    if !files.iter().any(|file| file.import_path == STANDARD_LIBRARY_PATH) {
+        files.push(parse_file(
+            PathBuf::from(STANDARD_LIBRARY_PATH),
+            String::from(STANDARD_LIBRARY_PATH),
+            STANDARD_LIBRARY,
+        )?);
+    }
+    Ok(files)
+}
+
+fn collection_from_files(files: Vec<SchemaFile>) -> Result<JsonCollection, SchemaError> {
+    let import_paths: HashSet<String> = files.iter().map(|f| f.import_path.clone()).collect();
+    for file in files.iter() {
+        for &(ref import, location) in file.imports.iter() {
+            if !import_paths.contains(import) {
+                return Err(file.error(
+                    location,
+                    format!("Cannot find imported file \"{}\".", import),
+                ));
+            }
+        }
+    }
+
+    let mut symbols = HashMap::new();
+    for file in files.iter() {
+        file.add_symbols(&mut symbols)?;
+    }
+
+    let mut collection = JsonCollection::default();
+    for file in files.iter() {
+        file.add_to_collection(&symbols, &mut collection)?;
+    }
+    Ok(collection)
+}
+
+// Parses schema files given as `(import path, source)` pairs along with the standard
+// library, so that tests don't need to write them to disk.
+#[cfg(test)]
+pub fn parse_schema_sources(sources: &[(&str, &str)]) -> Result<JsonCollection, SchemaError> {
+    let mut files = Vec::new();
+    for &(import_path, source) in sources.iter() {
+        files.push(parse_file(
+            PathBuf::from(import_path),
+            String::from(import_path),
+            source,
+        )?);
+    }
+    files.push(parse_file(
+        PathBuf::from(STANDARD_LIBRARY_PATH),
+        String::from(STANDARD_LIBRARY_PATH),
+        STANDARD_LIBRARY,
+    )?);
+    collection_from_files(files)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Location {
+    line: usize,
+    column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Identifier(String),
+    Number(String),
+    StringLiteral(String),
+    Symbol(char),
+    End,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TokenKind::Identifier(ref name) => write!(f, "`{}`", name),
+            TokenKind::Number(ref number) => write!(f, "`{}`", number),
+            TokenKind::StringLiteral(ref value) => write!(f, "\"{}\"", value),
+            TokenKind::Symbol(symbol) => write!(f, "`{}`", symbol),
+            TokenKind::End => write!(f, "end of file"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    location: Location,
+}
+
+fn tokenise(file: &Path, source: &str) -> Result<Vec<Token>, SchemaError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut line = 1;
+    let mut column = 1;
+
+    macro_rules! advance {
+        () => {{
+            if chars[i] == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+            i += 1;
+        }};
+    }
+
+    while i < chars.len() {
+        let c = chars[i];
+        let location = Location { line, column };
+
+        if c.is_whitespace() {
+            advance!();
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                advance!();
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            advance!();
+            advance!();
+            loop {
+                if i >= chars.len() {
+                    return Err(SchemaError {
+                        file: PathBuf::from(file),
+                        line: location.line,
+                        column: location.column,
+                        message: String::from("Unterminated block comment."),
+                    });
+                }
+                if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                    advance!();
+                    advance!();
+                    break;
+                }
+                advance!();
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let mut identifier = String::new();
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                identifier.push(chars[i]);
+                advance!();
+            }
+            tokens.push(Token {
+                kind: TokenKind::Identifier(identifier),
+                location,
+            });
+        } else if c.is_ascii_digit() {
+            // Annotations can contain floats, so anything number-like is kept as text
+            // and only converted where an integer is expected.
+            let mut number = String::new();
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+            {
+                number.push(chars[i]);
+                advance!();
+            }
+            tokens.push(Token {
+                kind: TokenKind::Number(number),
+                location,
+            });
+        } else if c == '"' {
+            let mut value = String::new();
+            advance!();
+            loop {
+                if i >= chars.len() || chars[i] == '\n' {
+                    return Err(SchemaError {
+                        file: PathBuf::from(file),
+                        line: location.line,
+                        column: location.column,
+                        message: String::from("Unterminated string literal."),
+                    });
+                }
+                if chars[i] == '"' {
+                    advance!();
+                    break;
+                }
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    advance!();
+                }
+                value.push(chars[i]);
+                advance!();
+            }
+            tokens.push(Token {
+                kind: TokenKind::StringLiteral(value),
+                location,
+            });
+        } else if "{}()[]<>;=,.-+:".contains(c) {
+            advance!();
+            tokens.push(Token {
+                kind: TokenKind::Symbol(c),
+                location,
+            });
+        } else {
+            return Err(SchemaError {
+                file: PathBuf::from(file),
+                line: location.line,
+                column: location.column,
+                message: format!("Unexpected character `{}`.", c),
+            });
+        }
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::End,
+        location: Location { line, column },
+    });
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum TypeReference {
+    BuiltIn(String, Location),
+    User(String, Location),
+}
+
+#[derive(Debug)]
+enum FieldType {
+    Singular(TypeReference),
+    Option(TypeReference),
+    List(TypeReference),
+    Map(TypeReference, TypeReference),
+}
+
+#[derive(Debug)]
+struct FieldDecl {
+    name: String,
+    location: Location,
+    number: u32,
+    field_type: FieldType,
+}
+
+#[derive(Debug)]
+struct EnumDecl {
+    name: String,
+    location: Location,
+    values: Vec<(String, u32)>,
+}
+
+#[derive(Debug)]
+struct TypeDecl {
+    name: String,
+    location: Location,
+    fields: Vec<FieldDecl>,
+    types: Vec<TypeDecl>,
+    enums: Vec<EnumDecl>,
+}
+
+#[derive(Debug)]
+struct ComponentDecl {
+    name: String,
+    location: Location,
+    id: u32,
+    data: Option<TypeReference>,
+    fields: Vec<FieldDecl>,
+    events: Vec<(String, TypeReference)>,
+    commands: Vec<(String, TypeReference, TypeReference)>,
+}
+
+struct SchemaFile {
+    path: PathBuf,
+    import_path: String,
+    package: Vec<String>,
+    imports: Vec<(String, Location)>,
+    types: Vec<TypeDecl>,
+    enums: Vec<EnumDecl>,
+    components: Vec<ComponentDecl>,
+}
+
+fn parse_file(path: PathBuf, import_path: String, source: &str) -> Result<SchemaFile, SchemaError> {
+    let tokens = tokenise(&path, source)?;
+    let mut parser = Parser {
+        file: SchemaFile {
+            path,
+            import_path,
+            package: Vec::new(),
+            imports: Vec::new(),
+            types: Vec::new(),
+            enums: Vec::new(),
+            components: Vec::new(),
+        },
+        tokens,
+        position: 0,
+    };
+    parser.parse_file()?;
+    Ok(parser.file)
+}
+
+struct Parser {
+    file: SchemaFile,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, SchemaError> {
+        let token = self.peek();
+        Err(self.file.error(
+            token.location,
+            format!("Expected {}, found {}.", expected, token.kind),
+        ))
+    }
+
+    fn is_symbol(&self, symbol: char) -> bool {
+        self.peek().kind == TokenKind::Symbol(symbol)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek().kind {
+            TokenKind::Identifier(ref name) => name == keyword,
+            _ => false,
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), SchemaError> {
+        if self.is_symbol(symbol) {
+            self.next();
+            Ok(())
+        } else {
+            self.unexpected(format!("`{}`", symbol).as_str())
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), SchemaError> {
+        if self.is_keyword(keyword) {
+            self.next();
+            Ok(())
+        } else {
+            self.unexpected(format!("`{}`", keyword).as_str())
+        }
+    }
+
+    fn expect_identifier(&mut self, expected: &str) -> Result<(String, Location), SchemaError> {
+        match self.peek().kind.clone() {
+            TokenKind::Identifier(name) => Ok((name, self.next().location)),
+            _ => self.unexpected(expected),
+        }
+    }
+
+    fn expect_integer(&mut self, expected: &str) -> Result<u32, SchemaError> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Number(ref number) => match number.parse::<u32>() {
+                Ok(value) => {
+                    self.next();
+                    Ok(value)
+                }
+                Err(_) => Err(self.file.error(
+                    token.location,
+                    format!("`{}` is not a valid {}.", number, expected),
+                )),
+            },
+            _ => self.unexpected(expected),
+        }
+    }
+
+    fn expect_qualified_identifier(
+        &mut self,
+        expected: &str,
+    ) -> Result<(String, Location), SchemaError> {
+        let (mut name, location) = self.expect_identifier(expected)?;
+        while self.is_symbol('.') {
+            self.next();
+            name.push('.');
+            name.push_str(self.expect_identifier("an identifier")?.0.as_str());
+        }
+        Ok((name, location))
+    }
+
+    // Annotations have no effect on the generated code, so they are skipped.
+    fn skip_annotations(&mut self) -> Result<(), SchemaError> {
+        while self.is_symbol('[') {
+            let start = self.peek().location;
+            let mut depth = 0;
+            loop {
+                let token = self.next();
+                match token.kind {
+                    TokenKind::Symbol('[') => depth += 1,
+                    TokenKind::Symbol(']') => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    TokenKind::End => {
+                        return Err(self.file
+                            .error(start, String::from("Unterminated annotation.")))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_file(&mut self) -> Result<(), SchemaError> {
+        self.skip_annotations()?;
+        self.expect_keyword("package")?;
+        let (package, _) = self.expect_qualified_identifier("a package name")?;
+        self.file.package = package.split(".").map(String::from).collect();
+        self.expect_symbol(';')?;
+
+        loop {
+            self.skip_annotations()?;
+            if self.peek().kind == TokenKind::End {
+                return Ok(());
+            } else if self.is_keyword("import") {
+                self.next();
+                let token = self.next();
+                match token.kind {
+                    TokenKind::StringLiteral(import) => {
+                        self.file.imports.push((import, token.location))
+                    }
+                    _ => {
+                        self.position -= 1;
+                        return self.unexpected("an import path");
+                    }
+                }
+                self.expect_symbol(';')?;
+            } else if self.is_keyword("type") {
+                let type_decl = self.parse_type()?;
+                self.file.types.push(type_decl);
+            } else if self.is_keyword("enum") {
+                let enum_decl = self.parse_enum()?;
+                self.file.enums.push(enum_decl);
+            } else if self.is_keyword("component") {
+                let component = self.parse_component()?;
+                self.file.components.push(component);
+            } else {
+                return self.unexpected("`import`, `type`, `enum` or `component`");
+            }
+        }
+    }
+
+    fn parse_type(&mut self) -> Result<TypeDecl, SchemaError> {
+        self.expect_keyword("type")?;
+        let (name, location) = self.expect_identifier("a type name")?;
+        let mut type_decl = TypeDecl {
+            name,
+            location,
+            fields: Vec::new(),
+            types: Vec::new(),
+            enums: Vec::new(),
+        };
+        self.expect_symbol('{')?;
+        loop {
+            self.skip_annotations()?;
+            if self.is_symbol('}') {
+                self.next();
+                return Ok(type_decl);
+            } else if self.is_keyword("type") {
+                type_decl.types.push(self.parse_type()?);
+            } else if self.is_keyword("enum") {
+                type_decl.enums.push(self.parse_enum()?);
+            } else {
+                type_decl.fields.push(self.parse_field()?);
+            }
+        }
+    }
+
+    fn parse_enum(&mut self) -> Result<EnumDecl, SchemaError> {
+        self.expect_keyword("enum")?;
+        let (name, location) = self.expect_identifier("an enum name")?;
+        let mut values = Vec::new();
+        self.expect_symbol('{')?;
+        loop {
+            self.skip_annotations()?;
+            if self.is_symbol('}') {
+                self.next();
+                break;
+            }
+            let (value_name, _) = self.expect_identifier("an enum value name")?;
+            self.expect_symbol('=')?;
+            let value = self.expect_integer("enum value")?;
+            self.expect_symbol(';')?;
+            values.push((value_name, value));
+        }
+        if values.is_empty() {
+            return Err(self.file.error(
+                location,
+                format!("Enum `{}` must have at least one value.", name),
+            ));
+        }
+        Ok(EnumDecl {
+            name,
+            location,
+            values,
+        })
+    }
+
+    fn parse_component(&mut self) -> Result<ComponentDecl, SchemaError> {
+        self.expect_keyword("component")?;
+        let (name, location) = self.expect_identifier("a component name")?;
+        let mut id = None;
+        let mut component = ComponentDecl {
+            name,
+            location,
+            id: 0,
+            data: None,
+            fields: Vec::new(),
+            events: Vec::new(),
+            commands: Vec::new(),
+        };
+        self.expect_symbol('{')?;
+        loop {
+            self.skip_annotations()?;
+            if self.is_symbol('}') {
+                self.next();
+                break;
+            } else if self.is_keyword("id") {
+                self.next();
+                self.expect_symbol('=')?;
+                id = Some(self.expect_integer("component ID")?);
+                self.expect_symbol(';')?;
+            } else if self.is_keyword("data") {
+                self.next();
+                component.data = Some(self.parse_type_reference()?);
+                self.expect_symbol(';')?;
+            } else if self.is_keyword("event") {
+                self.next();
+                let event_type = self.parse_type_reference()?;
+                let (event_name, _) = self.expect_identifier("an event name")?;
+                self.expect_symbol(';')?;
+                component.events.push((event_name, event_type));
+            } else if self.is_keyword("command") {
+                self.next();
+                let response_type = self.parse_type_reference()?;
+                let (command_name, _) = self.expect_identifier("a command name")?;
+                self.expect_symbol('(')?;
+                let request_type = self.parse_type_reference()?;
+                self.expect_symbol(')')?;
+                self.expect_symbol(';')?;
+                component
+                    .commands
+                    .push((command_name, request_type, response_type));
+            } else {
+                component.fields.push(self.parse_field()?);
+            }
+        }
+
+        if component.data.is_some() && !component.fields.is_empty() {
+            return Err(self.file.error(
+                location,
+                format!(
+                    "Component `{}` cannot have both a `data` type and fields.",
+                    component.name
+                ),
+            ));
+        }
+        match id {
+            Some(id) => component.id = id,
+            None => {
+                return Err(self.file.error(
+                    location,
+                    format!("Component `{}` does not have an `id`.", component.name),
+                ))
+            }
+        }
+        Ok(component)
+    }
+
+    fn parse_type_reference(&mut self) -> Result<TypeReference, SchemaError> {
+        let (name, location) = self.expect_qualified_identifier("a type")?;
+        if BUILT_IN_TYPES.contains(&name.as_str()) {
+            Ok(TypeReference::BuiltIn(name, location))
+        } else {
+            Ok(TypeReference::User(name, location))
+        }
+    }
+
+    fn parse_field(&mut self) -> Result<FieldDecl, SchemaError> {
+        let field_type = if self.is_keyword("option") || self.is_keyword("list")
+            || self.is_keyword("map")
+        {
+            let (collection, _) = self.expect_identifier("a type")?;
+            self.expect_symbol('<')?;
+            let first_type = self.parse_type_reference()?;
+            let field_type = match collection.as_str() {
+                "option" => FieldType::Option(first_type),
+                "list" => FieldType::List(first_type),
+                _ => {
+                    self.expect_symbol(',')?;
+                    FieldType::Map(first_type, self.parse_type_reference()?)
+                }
+            };
+            self.expect_symbol('>')?;
+            field_type
+        } else {
+            FieldType::Singular(self.parse_type_reference()?)
+        };
+        let (name, location) = self.expect_identifier("a field name")?;
+        self.expect_symbol('=')?;
+        let number = self.expect_integer("field number")?;
+        self.expect_symbol(';')?;
+        Ok(FieldDecl {
+            name,
+            location,
+            number,
+            field_type,
+        })
+    }
+}
+
+// Maps the qualified name of every type and enum, as written in schema, to the
+// qualified name used in the generated model. Nested types are flattened into
+// their package with the outer type names as a prefix.
+type Symbols = HashMap<String, String>;
+
+impl SchemaFile {
+    fn error(&self, location: Location, message: String) -> SchemaError {
+        SchemaError {
+            file: self.path.clone(),
+            line: location.line,
+            column: location.column,
+            message,
+        }
+    }
+
+    fn source_reference(&self, location: Location) -> Option<SourceReference> {
+        Some(SourceReference {
+            filePath: self.path.display().to_string(),
+            line: location.line as u32,
+            column: location.column as u32,
+        })
+    }
+
+    fn package_name(&self) -> String {
+        self.package.join(".")
+    }
+
+    fn add_symbol(
+        &self,
+        symbols: &mut Symbols,
+        schema_name: String,
+        model_name: String,
+        location: Location,
+    ) -> Result<(), SchemaError> {
+        if symbols.contains_key(&schema_name) {
+            return Err(self.error(
+                location,
+                format!("`{}` is defined more than once.", schema_name),
+            ));
+        }
+        symbols.insert(schema_name, model_name);
+        Ok(())
+    }
+
+    fn add_symbols(&self, symbols: &mut Symbols) -> Result<(), SchemaError> {
+        for enum_decl in self.enums.iter() {
+            let name = format!("{}.{}", self.package_name(), enum_decl.name);
+            self.add_symbol(symbols, name.clone(), name, enum_decl.location)?;
+        }
+        for type_decl in self.types.iter() {
+            self.add_type_symbols(symbols, type_decl, &self.package, "")?;
+        }
+        for component in self.components.iter() {
+            let name = format!("{}.{}", self.package_name(), component.name);
+            self.add_symbol(symbols, name.clone(), name.clone(), component.location)?;
+            if component.data.is_none() {
+                let data_name = format!("{}Data", name);
+                self.add_symbol(symbols, data_name.clone(), data_name, component.location)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_type_symbols(
+        &self,
+        symbols: &mut Symbols,
+        type_decl: &TypeDecl,
+        scope: &[String],
+        model_prefix: &str,
+    ) -> Result<(), SchemaError> {
+        let schema_name = format!("{}.{}", scope.join("."), type_decl.name);
+        let model_name = format!(
+            "{}.{}{}",
+            self.package_name(),
+            model_prefix,
+            type_decl.name
+        );
+        self.add_symbol(
+            symbols,
+            schema_name.clone(),
+            model_name.clone(),
+            type_decl.location,
+        )?;
+
+        for enum_decl in type_decl.enums.iter() {
+            self.add_symbol(
+                symbols,
+                format!("{}.{}", schema_name, enum_decl.name),
+                format!("{}.{}", model_name, enum_decl.name),
+                enum_decl.location,
+            )?;
+        }
+
+        let mut inner_scope = scope.to_vec();
+        inner_scope.push(type_decl.name.clone());
+        let inner_prefix = format!("{}{}", model_prefix, type_decl.name);
+        for inner_type in type_decl.types.iter() {
+            self.add_type_symbols(symbols, inner_type, &inner_scope, inner_prefix.as_str())?;
+        }
+        Ok(())
+    }
+
+    // Names are looked up from the innermost scope outwards, so a type can refer to
+    // its nested types, to other types in its package and to fully qualified types.
+    fn resolve(
+        &self,
+        symbols: &Symbols,
+        scope: &[String],
+        reference: &TypeReference,
+    ) -> Result<SchemaTypeDefinition, SchemaError> {
+        match *reference {
+            TypeReference::BuiltIn(ref name, _) => Ok(SchemaTypeDefinition {
+                builtInType: Some(name.clone()),
+                userType: None,
+            }),
+            TypeReference::User(ref name, location) => {
+                for depth in (0..scope.len() + 1).rev() {
+                    let mut candidate = scope[..depth].to_vec();
+                    candidate.push(name.clone());
+                    if let Some(model_name) = symbols.get(&candidate.join(".")) {
+                        return Ok(SchemaTypeDefinition {
+                            builtInType: None,
+                            userType: Some(model_name.clone()),
+                        });
+                    }
+                }
+                Err(self.error(location, format!("Unknown type `{}`.", name)))
+            }
+        }
+    }
+
+    fn resolve_user_type(
+        &self,
+        symbols: &Symbols,
+        scope: &[String],
+        reference: &TypeReference,
+        usage: &str,
+    ) -> Result<SchemaTypeDefinition, SchemaError> {
+        match *reference {
+            TypeReference::BuiltIn(ref name, location) => Err(self.error(
+                location,
+                format!("{} must be a user type, not `{}`.", usage, name),
+            )),
+            TypeReference::User(_, _) => self.resolve(symbols, scope, reference),
+        }
+    }
+
+    fn field_definitions(
+        &self,
+        symbols: &Symbols,
+        scope: &[String],
+        fields: &[FieldDecl],
+    ) -> Result<Vec<FieldDefinition>, SchemaError> {
+        let mut definitions = Vec::new();
+        for field in fields.iter() {
+            let mut definition = FieldDefinition {
+                name: field.name.clone(),
+                number: field.number,
+                singularType: None,
+                optionType: None,
+                listType: None,
+                mapType: None,
+                sourceReference: self.source_reference(field.location),
+            };
+            match field.field_type {
+                FieldType::Singular(ref t) => {
+                    definition.singularType = Some(self.resolve(symbols, scope, t)?)
+                }
+                FieldType::Option(ref t) => {
+                    definition.optionType = Some(OptionTypeDefinition {
+                        valueType: self.resolve(symbols, scope, t)?,
+                    })
+                }
+                FieldType::List(ref t) => {
+                    definition.listType = Some(ListTypeDefinition {
+                        valueType: self.resolve(symbols, scope, t)?,
+                    })
+                }
+                FieldType::Map(ref k, ref v) => {
+                    definition.mapType = Some(MapTypeDefinition {
+                        keyType: self.resolve(symbols, scope, k)?,
+                        valueType: self.resolve(symbols, scope, v)?,
+                    })
+                }
+            }
+            definitions.push(definition);
+        }
+        Ok(definitions)
+    }
+
+    fn enum_definition(&self, enum_decl: &EnumDecl, qualified_name: String) -> EnumDefinition {
+        EnumDefinition {
+            name: enum_decl.name.clone(),
+            qualifiedName: qualified_name,
+            valueDefinitions: enum_decl
+                .values
+                .iter()
+                .map(|&(ref name, value)| EnumValueDefinition {
+                    name: name.clone(),
+                    value,
+                })
+                .collect(),
+            sourceReference: self.source_reference(enum_decl.location),
+        }
+    }
+
+    fn add_type_to_collection(
+        &self,
+        symbols: &Symbols,
+        type_decl: &TypeDecl,
+        scope: &[String],
+        collection: &mut JsonCollection,
+    ) -> Result<(), SchemaError> {
+        let mut inner_scope = scope.to_vec();
+        inner_scope.push(type_decl.name.clone());
+        let qualified_name = symbols[&inner_scope.join(".")].clone();
+
+        for inner_type in type_decl.types.iter() {
+            self.add_type_to_collection(symbols, inner_type, &inner_scope, collection)?;
+        }
+
+        collection.typeDefinitions.push(TypeDefinition {
+            fieldDefinitions: self.field_definitions(symbols, &inner_scope, &type_decl.fields)?,
+            name: qualified_name.split(".").last().unwrap().to_string(),
+            enumDefinitions: type_decl
+                .enums
+                .iter()
+                .map(|enum_decl| {
+                    self.enum_definition(
+                        enum_decl,
+                        format!("{}.{}", qualified_name, enum_decl.name),
+                    )
+                })
+                .collect(),
+            qualifiedName: qualified_name,
+            sourceReference: self.source_reference(type_decl.location),
+        });
+        Ok(())
+    }
+
+    fn add_to_collection(
+        &self,
+        symbols: &Symbols,
+        collection: &mut JsonCollection,
+    ) -> Result<(), SchemaError> {
+        let package_name = self.package_name();
+
+        for enum_decl in self.enums.iter() {
+            collection.enumDefinitions.push(self.enum_definition(
+                enum_decl,
+                format!("{}.{}", package_name, enum_decl.name),
+            ));
+        }
+
+        for type_decl in self.types.iter() {
+            self.add_type_to_collection(symbols, type_decl, &self.package, collection)?;
+        }
+
+        for component in self.components.iter() {
+            let qualified_name = format!("{}.{}", package_name, component.name);
+
+            // Components declared with fields get a generated data type, in the same
+            // way as `spatial process_schema` does.
+            let data_definition = match component.data {
+                Some(ref data) => {
+                    self.resolve_user_type(symbols, &self.package, data, "Component data")?
+                }
+                None => {
+                    let data_name = format!("{}Data", qualified_name);
+                    collection.typeDefinitions.push(TypeDefinition {
+                        fieldDefinitions: self.field_definitions(
+                            symbols,
+                            &self.package,
+                            &component.fields,
+                        )?,
+                        name: format!("{}Data", component.name),
+                        qualifiedName: data_name.clone(),
+                        enumDefinitions: Vec::new(),
+                        sourceReference: self.source_reference(component.location),
+                    });
+                    SchemaTypeDefinition {
+                        builtInType: None,
+                        userType: Some(data_name),
+                    }
+                }
+            };
+
+            let mut event_definitions = Vec::new();
+            for (index, (name, event_type)) in component.events.iter().enumerate() {
+                event_definitions.push(EventDefinition {
+                    name: name.clone(),
+                    eventType: self.resolve_user_type(
+                        symbols,
+                        &self.package,
+                        event_type,
+                        "An event",
+                    )?,
+                    eventIndex: index as u32 + 1,
+                });
+            }
+
+            let mut command_definitions = Vec::new();
+            for (index, (name, request, response)) in
+                component.commands.iter().enumerate()
+            {
+                command_definitions.push(CommandDefinition {
+                    name: name.clone(),
+                    requestType: self.resolve_user_type(
+                        symbols,
+                        &self.package,
+                        request,
+                        "A command request",
+                    )?,
+                    responseType: self.resolve_user_type(
+                        symbols,
+                        &self.package,
+                        response,
+                        "A command response",
+                    )?,
+                    commandIndex: index as u32 + 1,
+                });
+            }
+
+            collection.componentDefinitions.push(ComponentDefinition {
+                id: component.id,
+                name: component.name.clone(),
+                qualifiedName: qualified_name,
+                dataDefinition: data_definition,
+                eventDefinitions: event_definitions,
+                commandDefinitions: command_definitions,
+                sourceReference: self.source_reference(component.location),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generate_code;
+    use json;
+    use serde_json;
+
+    fn parse(source: &str) -> JsonCollection {
+        parse_schema_sources(&[("test.schema", source)]).unwrap()
+    }
+
+    fn parse_error(source: &str) -> String {
+        parse_schema_sources(&[("test.schema", source)])
+            .expect_err("the schema should not parse")
+            .to_string()
+    }
+
+    fn type_def<'a>(collection: &'a JsonCollection, qualified_name: &str) -> &'a TypeDefinition {
+        collection
+            .typeDefinitions
+            .iter()
+            .find(|t| t.qualifiedName == qualified_name)
+            .expect(qualified_name)
+    }
+
+    fn component<'a>(
+        collection: &'a JsonCollection,
+        qualified_name: &str,
+    ) -> &'a ComponentDefinition {
+        collection
+            .componentDefinitions
+            .iter()
+            .find(|c| c.qualifiedName == qualified_name)
+            .expect(qualified_name)
+    }
+
+    fn user_type(schema_type: &Option<SchemaTypeDefinition>) -> &str {
+        schema_type.as_ref().unwrap().userType.as_ref().unwrap()
+    }
+
+    #[test]
+    fn resolves_types_across_packages_and_imports() {
+        let collection = parse_schema_sources(&[
+            (
+                "game/common.schema",
+                "package game.common; type Shared { int32 value = 1; }",
+            ),
+            (
+                "game/player.schema",
+                "package game.player;
+                 import \"game/common.schema\";
+                 type Player {
+                     game.common.Shared qualified = 1;
+                     common.Shared relative = 2;
+                 }",
+            ),
+        ]).unwrap();
+
+        let player = type_def(&collection, "game.player.Player");
+        assert_eq!(player.name, "Player");
+        assert_eq!(
+            user_type(&player.fieldDefinitions[0].singularType),
+            "game.common.Shared"
+        );
+        assert_eq!(
+            user_type(&player.fieldDefinitions[1].singularType),
+            "game.common.Shared"
+        );
+    }
+
+    #[test]
+    fn reports_missing_imports() {
+        assert_eq!(
+            parse_error("package test;\nimport \"missing.schema\";"),
+            "test.schema:2:8: Cannot find imported file \"missing.schema\"."
+        );
+    }
+
+    #[test]
+    fn flattens_nested_types_and_enums() {
+        let collection = parse(
+            "package test;
+             type Outer {
+                 type Inner { int32 value = 1; }
+                 enum State { IDLE = 1; BUSY = 2; }
+                 Inner inner = 1;
+                 State state = 2;
+             }",
+        );
+
+        let inner = type_def(&collection, "test.OuterInner");
+        assert_eq!(inner.name, "OuterInner");
+        assert_eq!(inner.fieldDefinitions[0].name, "value");
+
+        let outer = type_def(&collection, "test.Outer");
+        assert_eq!(outer.enumDefinitions[0].qualifiedName, "test.Outer.State");
+        let values: Vec<(&str, u32)> = outer.enumDefinitions[0]
+            .valueDefinitions
+            .iter()
+            .map(|v| (v.name.as_str(), v.value))
+            .collect();
+        assert_eq!(values, vec![("IDLE", 1), ("BUSY", 2)]);
+        assert_eq!(
+            user_type(&outer.fieldDefinitions[0].singularType),
+            "test.OuterInner"
+        );
+        assert_eq!(
+            user_type(&outer.fieldDefinitions[1].singularType),
+            "test.Outer.State"
+        );
+    }
+
+    #[test]
+    fn parses_collection_fields() {
+        let collection = parse(
+            "package test;
+             type Item {}
+             type Inventory {
+                 option<int32> selected = 1;
+                 list<Item> items = 2;
+                 map<string, Item> by_name = 3;
+                 bytes raw = 4;
+             }",
+        );
+
+        let fields = &type_def(&collection, "test.Inventory").fieldDefinitions;
+        let option = fields[0].optionType.as_ref().unwrap();
+        assert_eq!(option.valueType.builtInType, Some(String::from("int32")));
+        let list = fields[1].listType.as_ref().unwrap();
+        assert_eq!(list.valueType.userType, Some(String::from("test.Item")));
+        let map = fields[2].mapType.as_ref().unwrap();
+        assert_eq!(map.keyType.builtInType, Some(String::from("string")));
+        assert_eq!(map.valueType.userType, Some(String::from("test.Item")));
+        assert_eq!(
+            fields[3].singularType.as_ref().unwrap().builtInType,
+            Some(String::from("bytes"))
+        );
+        assert_eq!(
+            fields.iter().map(|f| f.number).collect::<Vec<u32>>(),
+            vec![1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn parses_events_and_commands() {
+        let collection = parse(
+            "package test;
+             type Hit { int32 damage = 1; }
+             type Empty {}
+             component Health {
+                 id = 1001;
+                 [some.annotation(1.5)]
+                 int32 current = 1;
+                 event Hit got_hit;
+                 event Empty healed;
+                 command Empty ping(Hit);
+             }",
+        );
+
+        let health = component(&collection, "test.Health");
+        assert_eq!(health.id, 1001);
+        assert_eq!(
+            health.dataDefinition.userType,
+            Some(String::from("test.HealthData"))
+        );
+        assert_eq!(type_def(&collection, "test.HealthData").fieldDefinitions[0].name, "current");
+
+        let events: Vec<(&str, u32, &str)> = health
+            .eventDefinitions
+            .iter()
+            .map(|e| {
+                (
+                    e.name.as_str(),
+                    e.eventIndex,
+                    e.eventType.userType.as_ref().unwrap().as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![("got_hit", 1, "test.Hit"), ("healed", 2, "test.Empty")]
+        );
+
+        let ping = &health.commandDefinitions[0];
+        assert_eq!(ping.name, "ping");
+        assert_eq!(ping.commandIndex, 1);
+        assert_eq!(ping.requestType.userType, Some(String::from("test.Hit")));
+        assert_eq!(ping.responseType.userType, Some(String::from("test.Empty")));
+    }
+
+    #[test]
+    fn rejects_built_in_event_types() {
+        assert_eq!(
+            parse_error("package test;\ncomponent Health {\n  id = 1001;\n  event int32 hit;\n}"),
+            "test.schema:4:9: An event must be a user type, not `int32`."
+        );
+    }
+
+    #[test]
+    fn includes_the_standard_library() {
+        let collection = parse(
+            "package test;
+             type Target { improbable.Coordinates position = 1; EntityId entity = 2; }",
+        );
+
+        assert_eq!(component(&collection, "improbable.Position").id, 54);
+        assert_eq!(component(&collection, "improbable.EntityAcl").id, 50);
+        assert_eq!(
+            user_type(&type_def(&collection, "test.Target").fieldDefinitions[0].singularType),
+            "improbable.Coordinates"
+        );
+    }
+
+    #[test]
+    fn reports_the_location_of_errors() {
+        let cases = &[
+            (
+                "package test;\ntype A {\n  string s = \"abc;\n}",
+                "test.schema:3:14: Unterminated string literal.",
+            ),
+            (
+                "package test;\n/* comment\ntype A {}",
+                "test.schema:2:1: Unterminated block comment.",
+            ),
+            (
+                "package test;\ntype A {\n  int32 value = 1\n}",
+                "test.schema:4:1: Expected `;`, found `}`.",
+            ),
+            (
+                "package test;\n  struct A {}",
+                "test.schema:2:3: Expected `import`, `type`, `enum` or `component`, \
+                 found `struct`.",
+            ),
+            (
+                "package test;\ntype A { int32 value = 1; }\n#",
+                "test.schema:3:1: Unexpected character `#`.",
+            ),
+            (
+                "package test;\ntype A { Missing value = 1; }",
+                "test.schema:2:10: Unknown type `Missing`.",
+            ),
+            (
+                "package test;\ncomponent A {\n  int32 value = 1;\n}",
+                "test.schema:2:11: Component `A` does not have an `id`.",
+            ),
+        ];
+
+        for &(source, expected) in cases.iter() {
+            assert_eq!(parse_error(source), expected);
+        }
+    }
+
+    // Sorts every definition and removes source references, which the JSON doesn't
+    // need to have, so that only the model itself is compared.
+    fn normalise(mut collection: JsonCollection) -> JsonCollection {
+        collection
+            .typeDefinitions
+            .sort_by(|a, b| a.qualifiedName.cmp(&b.qualifiedName));
+        collection
+            .componentDefinitions
+            .sort_by(|a, b| a.qualifiedName.cmp(&b.qualifiedName));
+        collection
+            .enumDefinitions
+            .sort_by(|a, b| a.qualifiedName.cmp(&b.qualifiedName));
+        for type_def in collection.typeDefinitions.iter_mut() {
+            type_def.sourceReference = None;
+            for field in type_def.fieldDefinitions.iter_mut() {
+                field.sourceReference = None;
+            }
+            for enum_def in type_def.enumDefinitions.iter_mut() {
+                enum_def.sourceReference = None;
+            }
+        }
+        for component in collection.componentDefinitions.iter_mut() {
+            component.sourceReference = None;
+        }
+        for enum_def in collection.enumDefinitions.iter_mut() {
+            enum_def.sourceReference = None;
+        }
+        collection
+    }
+
+    #[test]
+    fn matches_the_ast_json_of_the_example_schema() {
+        let from_schema =
+            normalise(parse_schema_dirs(vec!["../../demo-project/schema"]).unwrap());
+
+        let mut from_json = JsonCollection::default();
+        for path in &[
+            "test_data/ast/demogame/Movement.json",
+            "test_data/ast/improbable/standard_library.json",
+        ] {
+            from_json.append(json::parse_json(path).unwrap());
+        }
+        let from_json = normalise(from_json);
+
+        assert_eq!(
+            serde_json::to_value(&from_schema).unwrap(),
+            serde_json::to_value(&from_json).unwrap()
+        );
+        assert_eq!(
+            generate_code(from_schema).unwrap().to_string(),
+            generate_code(from_json).unwrap().to_string()
+        );
+    }
+}
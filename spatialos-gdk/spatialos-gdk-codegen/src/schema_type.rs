@@ -1,3 +1,5 @@
+use diagnostics::Diagnostic;
+use enum_type::EnumType;
 use json::{ListTypeDefinition, MapTypeDefinition, OptionTypeDefinition, SchemaTypeDefinition};
 use quote::Tokens;
 use std::cell::RefCell;
@@ -26,7 +28,35 @@ pub trait Type {
     fn is_built_in_type(&self) -> bool {
         false
     }
-    fn got_all_types(&mut self, _: &HashMap<String, Rc<RefCell<UserType>>>) {}
+    fn is_map_type(&self) -> bool {
+        false
+    }
+    // Options, lists and maps write nothing when they are empty, so they have to be
+    // sent as cleared fields in updates.
+    fn is_collection_type(&self) -> bool {
+        false
+    }
+    // Whether the Rust type implements `Eq` and `Hash`, so they can be derived.
+    fn is_hashable(&self) -> bool;
+    // The `ValueType` of the type in the reflection metadata. Collections can't be
+    // nested in schema, so only singular types need to implement this.
+    fn reflection_value_type_code(&self) -> Tokens {
+        panic!(
+            "{} can't be the value of a collection.",
+            self.rust_qualified_name()
+        );
+    }
+    fn reflection_field_type_code(&self) -> Tokens {
+        let value_type = self.reflection_value_type_code();
+        quote!(FieldType::Singular(#value_type))
+    }
+    fn got_all_types(
+        &mut self,
+        _: &HashMap<String, Rc<RefCell<UserType>>>,
+        _: &HashMap<String, Rc<EnumType>>,
+    ) -> Result<(), Diagnostic> {
+        Ok(())
+    }
 }
 
 impl From<SchemaTypeDefinition> for Box<Type> {
@@ -44,9 +74,11 @@ impl From<SchemaTypeDefinition> for Box<Type> {
     }
 }
 
+// A reference to a type by name, which can either be a user type or an enum.
 pub struct ReferencedUserType {
     pub name: String,
     referenced_type: Option<Rc<RefCell<UserType>>>,
+    referenced_enum: Option<Rc<EnumType>>,
 }
 
 impl From<SchemaTypeDefinition> for ReferencedUserType {
@@ -54,6 +86,7 @@ impl From<SchemaTypeDefinition> for ReferencedUserType {
         ReferencedUserType {
             name: value.userType.unwrap(),
             referenced_type: None,
+            referenced_enum: None,
         }
     }
 }
@@ -62,6 +95,14 @@ impl ReferencedUserType {
     pub fn get(&self) -> Option<&Rc<RefCell<UserType>>> {
         self.referenced_type.as_ref()
     }
+
+    fn with_type<R, F: FnOnce(&Type) -> R>(&self, f: F) -> R {
+        if let Some(ref user_type) = self.referenced_type {
+            f(&*user_type.borrow())
+        } else {
+            f(&**self.referenced_enum.as_ref().unwrap())
+        }
+    }
 }
 
 // impl From<SchemaTypeDefinition> for ReferencedUserType {
@@ -75,19 +116,11 @@ impl ReferencedUserType {
 
 impl Type for ReferencedUserType {
     fn rust_type_name(&self) -> String {
-        self.referenced_type
-            .as_ref()
-            .unwrap()
-            .borrow()
-            .rust_type_name()
+        self.with_type(|t| t.rust_type_name())
     }
 
     fn rust_qualified_name(&self) -> String {
-        self.referenced_type
-            .as_ref()
-            .unwrap()
-            .borrow()
-            .rust_qualified_name()
+        self.with_type(|t| t.rust_qualified_name())
     }
 
     fn deserialise_code(
@@ -96,31 +129,41 @@ impl Type for ReferencedUserType {
         field_id: u32,
         index: Option<String>,
     ) -> Tokens {
-        self.referenced_type
-            .as_ref()
-            .unwrap()
-            .borrow()
-            .deserialise_code(object_name, field_id, index)
+        self.with_type(|t| t.deserialise_code(object_name, field_id, index))
     }
 
     fn serialise_code(&self, object_name: &String, field_id: u32, value_name: &String) -> Tokens {
-        self.referenced_type
-            .as_ref()
-            .unwrap()
-            .borrow()
-            .serialise_code(object_name, field_id, value_name)
+        self.with_type(|t| t.serialise_code(object_name, field_id, value_name))
     }
 
     fn count_code(&self, object_name: &String, field_id: u32) -> Tokens {
-        self.referenced_type
-            .as_ref()
-            .unwrap()
-            .borrow()
-            .count_code(object_name, field_id)
+        self.with_type(|t| t.count_code(object_name, field_id))
+    }
+
+    fn is_hashable(&self) -> bool {
+        self.with_type(|t| t.is_hashable())
     }
 
-    fn got_all_types(&mut self, all_types: &HashMap<String, Rc<RefCell<UserType>>>) {
-        self.referenced_type = Some(all_types[&self.name].clone());
+    fn reflection_value_type_code(&self) -> Tokens {
+        self.with_type(|t| t.reflection_value_type_code())
+    }
+
+    fn got_all_types(
+        &mut self,
+        all_types: &HashMap<String, Rc<RefCell<UserType>>>,
+        all_enums: &HashMap<String, Rc<EnumType>>,
+    ) -> Result<(), Diagnostic> {
+        if let Some(user_type) = all_types.get(&self.name) {
+            self.referenced_type = Some(user_type.clone());
+        } else if let Some(enum_type) = all_enums.get(&self.name) {
+            self.referenced_enum = Some(enum_type.clone());
+        } else {
+            return Err(Diagnostic {
+                location: self.name.clone(),
+                message: format!("Unknown type `{}`.", self.name),
+            });
+        }
+        Ok(())
     }
 }
 
@@ -196,6 +239,19 @@ impl Type for BuiltInType {
         true
     }
 
+    fn is_hashable(&self) -> bool {
+        *self != BuiltInType::Float && *self != BuiltInType::Double
+    }
+
+    fn reflection_value_type_code(&self) -> Tokens {
+        let variant = Ident::new(match self {
+            BuiltInType::Boolean => "Bool",
+            BuiltInType::Bytes => "Bytes",
+            _ => self.type_method_group(),
+        });
+        quote!(ValueType::#variant)
+    }
+
     fn rust_type_name(&self) -> String {
         String::from(match self {
             BuiltInType::Boolean => "bool",
@@ -272,6 +328,24 @@ impl Type for OptionType {
         format!("Option<{}>", self.value_type.rust_qualified_name())
     }
 
+    fn is_collection_type(&self) -> bool {
+        true
+    }
+
+    // The values are stored directly in the field, rather than in an object.
+    fn count_code(&self, object_name: &String, field_id: u32) -> Tokens {
+        self.value_type.count_code(object_name, field_id)
+    }
+
+    fn is_hashable(&self) -> bool {
+        self.value_type.is_hashable()
+    }
+
+    fn reflection_field_type_code(&self) -> Tokens {
+        let value_type = self.value_type.reflection_value_type_code();
+        quote!(FieldType::Option(#value_type))
+    }
+
     fn deserialise_code(&self, object_name: &String, field_id: u32, _: Option<String>) -> Tokens {
         let get_count_code = self.count_code(object_name, field_id);
         let value_deserialise_code = self.value_type
@@ -291,7 +365,7 @@ impl Type for OptionType {
         let value_name = Ident::new(value_name.as_str());
         let value_serialise_code =
             self.value_type
-                .serialise_code(object_name, field_id, &String::from("*value"));
+                .serialise_code(object_name, field_id, &String::from("(*value)"));
         quote!{
             {
                 if let Some(ref value) = #value_name {
@@ -301,8 +375,12 @@ impl Type for OptionType {
         }
     }
 
-    fn got_all_types(&mut self, all_types: &HashMap<String, Rc<RefCell<UserType>>>) {
-        self.value_type.got_all_types(all_types);
+    fn got_all_types(
+        &mut self,
+        all_types: &HashMap<String, Rc<RefCell<UserType>>>,
+        all_enums: &HashMap<String, Rc<EnumType>>,
+    ) -> Result<(), Diagnostic> {
+        self.value_type.got_all_types(all_types, all_enums)
     }
 }
 
@@ -327,6 +405,24 @@ impl Type for ListType {
         format!("Vec<{}>", self.value_type.rust_qualified_name())
     }
 
+    fn is_collection_type(&self) -> bool {
+        true
+    }
+
+    // The values are stored directly in the field, rather than in an object.
+    fn count_code(&self, object_name: &String, field_id: u32) -> Tokens {
+        self.value_type.count_code(object_name, field_id)
+    }
+
+    fn is_hashable(&self) -> bool {
+        self.value_type.is_hashable()
+    }
+
+    fn reflection_field_type_code(&self) -> Tokens {
+        let value_type = self.value_type.reflection_value_type_code();
+        quote!(FieldType::List(#value_type))
+    }
+
     fn deserialise_code(&self, object_name: &String, field_id: u32, _: Option<String>) -> Tokens {
         let rust_type_name = Ident::new(self.rust_qualified_name().as_str());
         let get_count_code = self.count_code(object_name, field_id);
@@ -359,8 +455,12 @@ impl Type for ListType {
         }
     }
 
-    fn got_all_types(&mut self, all_types: &HashMap<String, Rc<RefCell<UserType>>>) {
-        self.value_type.got_all_types(all_types);
+    fn got_all_types(
+        &mut self,
+        all_types: &HashMap<String, Rc<RefCell<UserType>>>,
+        all_enums: &HashMap<String, Rc<EnumType>>,
+    ) -> Result<(), Diagnostic> {
+        self.value_type.got_all_types(all_types, all_enums)
     }
 }
 
@@ -395,6 +495,25 @@ impl Type for MapType {
         )
     }
 
+    fn is_map_type(&self) -> bool {
+        true
+    }
+
+    fn is_collection_type(&self) -> bool {
+        true
+    }
+
+    // `HashMap` doesn't implement `Hash`.
+    fn is_hashable(&self) -> bool {
+        false
+    }
+
+    fn reflection_field_type_code(&self) -> Tokens {
+        let key_type = self.key_type.reflection_value_type_code();
+        let value_type = self.value_type.reflection_value_type_code();
+        quote!(FieldType::Map(#key_type, #value_type))
+    }
+
     fn deserialise_code(&self, object_name: &String, field_id: u32, _: Option<String>) -> Tokens {
         let rust_type_name = Ident::new(self.rust_qualified_name().as_str());
         let get_count_code = self.count_code(object_name, field_id);
@@ -425,7 +544,7 @@ impl Type for MapType {
                 .serialise_code(&String::from("kvp"), 1, &String::from("*key"));
         let value_serialise_code =
             self.value_type
-                .serialise_code(&String::from("kvp"), 2, &String::from("value"));
+                .serialise_code(&String::from("kvp"), 2, &String::from("(*value)"));
         quote!{
             {
                 for (key, value) in #value_name.iter() {
@@ -437,8 +556,12 @@ impl Type for MapType {
         }
     }
 
-    fn got_all_types(&mut self, all_types: &HashMap<String, Rc<RefCell<UserType>>>) {
-        self.key_type.got_all_types(all_types);
-        self.value_type.got_all_types(all_types);
+    fn got_all_types(
+        &mut self,
+        all_types: &HashMap<String, Rc<RefCell<UserType>>>,
+        all_enums: &HashMap<String, Rc<EnumType>>,
+    ) -> Result<(), Diagnostic> {
+        self.key_type.got_all_types(all_types, all_enums)?;
+        self.value_type.got_all_types(all_types, all_enums)
     }
 }
@@ -2,6 +2,7 @@ use field::Field;
 use json::TypeDefinition;
 use quote::Tokens;
 use schema_type::Type;
+use serde_derive_code;
 use syn::Ident;
 use to_rust_qualified_name;
 
@@ -10,11 +11,33 @@ pub struct UserType {
     pub qualified_name: Vec<String>,
     rust_qualified_name: String,
     pub fields: Vec<Field>,
+    // Assumed to be true until `update_hashable` finds a field which isn't, so that
+    // recursive types can be hashable.
+    hashable: bool,
 }
 
 impl UserType {
+    // Returns true if this changed whether the type is hashable.
+    pub fn update_hashable(&mut self, fields_hashable: bool) -> bool {
+        let changed = self.hashable != fields_hashable;
+        self.hashable = fields_hashable;
+        changed
+    }
+
+    pub fn fields_hashable(&self) -> bool {
+        self.fields
+            .iter()
+            .all(|field| field.schema_type.is_hashable())
+    }
+
     pub fn get_code(&self) -> Tokens {
         let name = Ident::new(self.name.as_str());
+        let hashable_derives = if self.hashable {
+            quote!(, Eq, Hash)
+        } else {
+            quote!()
+        };
+        let serde_derives = serde_derive_code();
         let field_definitions = self.fields
             .iter()
             .map(|field| field.definition_in_struct(false));
@@ -24,10 +47,12 @@ impl UserType {
         let serialise_fields = self.fields
             .iter()
             .map(|field| field.serialise_from_struct());
+        let schema_value_fields = self.fields.iter().map(|field| field.schema_value_code());
 
         quote!{
             #[allow(dead_code, unused_variables)]
-            #[derive(Clone, Debug, Default)]
+            #[derive(Clone, Debug, Default, PartialEq #hashable_derives)]
+            #serde_derives
             pub struct #name {
                 #(#field_definitions,)*
             }
@@ -44,8 +69,23 @@ impl UserType {
                     #(#serialise_fields;)*
                 }
             }
+
+            impl ToSchemaValue for #name {
+                fn to_schema_value(&self) -> SchemaValue {
+                    SchemaValue::Object(vec![#(#schema_value_fields,)*])
+                }
+            }
         }
     }
+
+    pub fn reflection_code(&self) -> Tokens {
+        let qualified_name = self.qualified_name.join(".");
+        let fields = self.fields.iter().map(|field| field.reflection_code());
+        quote!(TypeInfo {
+            qualified_name: #qualified_name,
+            fields: &[#(#fields,)*],
+        })
+    }
 }
 
 impl Type for UserType {
@@ -79,6 +119,15 @@ impl Type for UserType {
     fn is_singular_type(&self) -> bool {
         true
     }
+
+    fn is_hashable(&self) -> bool {
+        self.hashable
+    }
+
+    fn reflection_value_type_code(&self) -> Tokens {
+        let qualified_name = self.qualified_name.join(".");
+        quote!(ValueType::Type(#qualified_name))
+    }
 }
 
 impl From<TypeDefinition> for UserType {
@@ -96,6 +145,7 @@ impl From<TypeDefinition> for UserType {
                 .into_iter()
                 .map(|field_definition| field_definition.into())
                 .collect(),
+            hashable: true,
         }
     }
 }
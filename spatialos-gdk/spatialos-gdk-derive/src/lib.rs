@@ -1,4 +1,4 @@
-#![recursion_limit = "128"]
+#![recursion_limit = "256"]
 
 extern crate proc_macro;
 extern crate syn;
@@ -7,7 +7,8 @@ extern crate quote;
 
 use proc_macro::TokenStream;
 use quote::Tokens;
-use syn::{Body, DeriveInput, Field, Ident, PathParameters, Ty, VariantData};
+use syn::{Body, DeriveInput, Field, Ident, Lit, MetaItem, NestedMetaItem, PathParameters,
+          PathSegment, Ty, VariantData};
 
 #[proc_macro_derive(ComponentGroup)]
 pub fn component_group(input: TokenStream) -> TokenStream {
@@ -17,30 +18,179 @@ pub fn component_group(input: TokenStream) -> TokenStream {
     gen.parse().unwrap()
 }
 
+/// Defines a SpatialOS component in Rust rather than in schema.
+///
+/// The struct is generated along with the rest of the schema by
+/// `spatialos_gdk_codegen::codegen_with_rust_components`, which also generates a `.schema`
+/// file for it, so this attribute replaces it with a `use` of the generated struct. The
+/// component is put in a package named after the crate, unless one is given:
+///
+/// ```
+/// #[spatial_component(id = 1000, package = "demogame")]
+/// pub struct Health {
+///     pub current: i32,
+///     pub target: Option<EntityId>,
+/// }
+/// ```
+///
+/// The generated struct already derives `Clone`, `Debug`, `Default` and `PartialEq`, so
+/// other attributes and derives are rejected rather than dropped, apart from doc comments.
+/// The `id` is checked against the generated struct.
+///
+/// The generated struct is used from `::schema::<package>::<Name>`, so the generated code
+/// must be included at the root of the crate, where it defines the `schema` module:
+///
+/// ```
+/// include!(concat!(env!("OUT_DIR"), "/generated.rs"));
+/// ```
+#[proc_macro_attribute]
+pub fn spatial_component(args: TokenStream, input: TokenStream) -> TokenStream {
+    let s = format!("#[spatial_component({})] {}", args, input);
+    let ast = syn::parse_derive_input(&s).unwrap();
+    let gen = impl_spatial_component(&ast);
+    gen.parse().unwrap()
+}
+
+fn impl_spatial_component(input: &DeriveInput) -> Tokens {
+    let name = &input.ident;
+    let visibility = &input.vis;
+
+    // The struct is replaced with a `use` of the generated one, so any other attribute
+    // or derive would be lost. Doc comments are kept on the `use`.
+    let mut docs = Vec::new();
+    let mut id = None;
+    let mut package = std::env::var("CARGO_PKG_NAME")
+        .unwrap_or_default()
+        .replace("-", "_");
+    for attr in input.attrs.iter() {
+        if attr.is_sugared_doc {
+            docs.push(attr);
+            continue;
+        }
+        let items = match attr.value {
+            MetaItem::List(ref key, ref items) if key == "spatial_component" => items,
+            _ => panic!(
+                "`#[spatial_component]` can't be used with other attributes, such as `#[{}]`, \
+                 as the struct is replaced with the generated one",
+                attr.name()
+            ),
+        };
+        for item in items.iter() {
+            match *item {
+                NestedMetaItem::MetaItem(MetaItem::NameValue(ref key, Lit::Int(value, _)))
+                    if key == "id" =>
+                {
+                    id = Some(value)
+                }
+                NestedMetaItem::MetaItem(MetaItem::NameValue(ref key, Lit::Str(ref value, _)))
+                    if key == "package" =>
+                {
+                    package = value.clone()
+                }
+                _ => panic!("Expected `id = <integer>` or `package = \"<package>\"`"),
+            }
+        }
+    }
+    let id = match id {
+        Some(id) => id as usize,
+        None => panic!("`#[spatial_component]` needs an `id`"),
+    };
+    let mut segments = vec![PathSegment::from("schema")];
+    for part in package.split('.') {
+        if syn::parse_ident(part).is_err() {
+            panic!("`{}` is not a valid package for a `spatial_component`", package);
+        }
+        segments.push(PathSegment::from(part));
+    }
+    segments.push(PathSegment::from(name.clone()));
+    let generated_path = syn::Path {
+        global: true,
+        segments,
+    };
+
+    let fields: &[Field] = match input.body {
+        Body::Struct(VariantData::Struct(ref fields)) => fields,
+        Body::Struct(VariantData::Unit) => &[],
+        _ => panic!("Only structs with named fields can be a `spatial_component`"),
+    };
+    for field in fields.iter() {
+        if field.attrs.iter().any(|attr| !attr.is_sugared_doc) {
+            panic!(
+                "The fields of a `spatial_component` can't have attributes, as the struct is \
+                 replaced with the generated one"
+            );
+        }
+    }
+    let field_names = fields.iter().map(|field| field.ident.clone().unwrap());
+    let field_types = fields.iter().map(|field| &field.ty);
+    let check_name = Ident::new(format!("_check_spatial_component_{}", name));
+
+    quote!{
+        #(#docs)*
+        #visibility use #generated_path;
+
+        // Fails to compile if the generated struct doesn't have the fields or the ID it was
+        // generated from, which also uses any imports only needed by those fields.
+        #[allow(dead_code, non_snake_case)]
+        fn #check_name(component: &#name) {
+            #(let _: &#field_types = &component.#field_names;)*
+            let _: [(); #id] = [(); #name::COMPONENT_ID as usize];
+        }
+    }
+}
+
+fn get_last_segment(ty: &Ty) -> &PathSegment {
+    match ty {
+        &Ty::Path(_, ref path) if path.segments.len() > 0 => path.segments.last().unwrap(),
+        _ => panic!("All fields must be component types"),
+    }
+}
+
+fn get_type_parameter(segment: &PathSegment, index: usize) -> &Ty {
+    match segment.parameters {
+        PathParameters::AngleBracketed(ref parameter_data) if parameter_data.types.len() > index => {
+            &parameter_data.types[index]
+        }
+        _ => panic!("All fields must be component types"),
+    }
+}
+
 enum GroupField {
     Component(ComponentField),
     EntityId(Ident),
+    Without(Ident, Ident),
 }
 
 impl GroupField {
     fn from_field(field: &Field) -> GroupField {
-        let requirement = match field.ty {
-            Ty::Path(_, ref path) if path.segments.len() > 0 => path.segments.last().unwrap(),
-            _ => panic!("All fields must be component types"),
-        }.clone();
+        let requirement = get_last_segment(&field.ty);
 
         match requirement.ident.as_ref() {
             "EntityId" => GroupField::EntityId(field.ident.clone().unwrap()),
+            "Without" => GroupField::Without(
+                field.ident.clone().unwrap(),
+                get_last_segment(get_type_parameter(requirement, 1))
+                    .ident
+                    .clone(),
+            ),
             _ => GroupField::Component(ComponentField::from_field(field)),
         }
     }
 
+    fn get_excluded_component(&self) -> Option<Ident> {
+        match self {
+            GroupField::Without(_, component) => Some(component.clone()),
+            _ => None,
+        }
+    }
+
     fn get_index_storage_code(&self) -> Tokens {
         match self {
             GroupField::Component(component) => component.get_index_storage_code(),
             GroupField::EntityId(field_name) => {
                 quote!(#field_name: (*chunk_ptr.0).get_entity_id(_index))
             }
+            GroupField::Without(field_name, _) => quote!(#field_name: Without::new()),
         }
     }
 
@@ -50,6 +200,7 @@ impl GroupField {
             GroupField::EntityId(field_name) => {
                 quote!(#field_name: (*chunk_ptr.0).get_entity_id(_index))
             }
+            GroupField::Without(field_name, _) => quote!(#field_name: Without::new()),
         }
     }
 }
@@ -58,35 +209,78 @@ struct ComponentField {
     field_name: Ident,
     component: Ident,
     requirement: Ident,
+    // Optional fields match entities whether or not they have the component.
+    optional: bool,
+    // The event type of an `Events` field, or the field type of a `ModifiedField`.
+    marker: Option<Ident>,
 }
 
 impl ComponentField {
     fn from_field(field: &Field) -> ComponentField {
-        let requirement = match field.ty {
-            Ty::Path(_, ref path) if path.segments.len() > 0 => path.segments.last().unwrap(),
-            _ => panic!("All fields must be component types"),
-        }.clone();
-        let component = match requirement.parameters {
-            PathParameters::AngleBracketed(ref parameter_data) => match &parameter_data.types[1] {
-                Ty::Path(_, ref path) if path.segments.len() > 0 => {
-                    &path.segments.last().unwrap().ident
-                }
-                _ => panic!("All fields must be component types"),
-            },
-            _ => panic!("All fields must be component types"),
-        }.clone();
+        let mut requirement = get_last_segment(&field.ty);
+        let optional = requirement.ident.as_ref() == "Option";
+        if optional {
+            requirement = get_last_segment(get_type_parameter(requirement, 0));
+            match requirement.ident.as_ref() {
+                "Read" | "Write" => (),
+                _ => panic!("Optional fields must be either `Read` or `Write`"),
+            }
+        }
+        let component = get_last_segment(get_type_parameter(requirement, 1))
+            .ident
+            .clone();
+        let marker = match requirement.ident.as_ref() {
+            "Events" | "ModifiedField" => Some(
+                get_last_segment(get_type_parameter(requirement, 2))
+                    .ident
+                    .clone(),
+            ),
+            _ => None,
+        };
 
         ComponentField {
             field_name: field.ident.clone().unwrap(),
             component,
-            requirement: requirement.ident,
+            requirement: requirement.ident.clone(),
+            optional,
+            marker,
+        }
+    }
+
+    fn get_required_component(&self) -> Option<Ident> {
+        if self.optional {
+            None
+        } else {
+            Some(self.component.clone())
+        }
+    }
+
+    fn get_written_component(&self) -> Option<Ident> {
+        match self.requirement.as_ref() {
+            "Write" | "ModifiedWrite" => Some(self.component.clone()),
+            _ => None,
+        }
+    }
+
+    // Every component which is read but not written, including by optional fields.
+    fn get_read_component(&self) -> Option<Ident> {
+        match self.get_written_component() {
+            Some(_) => None,
+            None => Some(self.component.clone()),
         }
     }
 
     fn get_authority_filter_code(&self) -> Tokens {
         let storage_name = Ident::new(format!("storage{}", self.component));
+        if self.optional {
+            return quote!(true);
+        }
         match self.requirement.as_ref() {
-            "Write" | "ModifiedWrite" => quote!((*#storage_name).get_authority(*_index) != 
+            "Write" | "ModifiedWrite" | "ReadAuthoritative" => {
+                quote!((*#storage_name).get_authority(*_index) != 
+                    ::spatialos_gdk::worker::Authority::NotAuthoritative)
+            }
+            "ReadNotAuthoritative" => quote!((*#storage_name).get_authority(*_index) == 
                     ::spatialos_gdk::worker::Authority::NotAuthoritative),
             _ => quote!(true),
         }
@@ -94,8 +288,15 @@ impl ComponentField {
 
     fn get_sendable_authority_filter_code(&self) -> Tokens {
         let storage_name = Ident::new(format!("storage{}", self.component));
+        if self.optional {
+            return quote!(true);
+        }
         match self.requirement.as_ref() {
-            "Write" | "ModifiedWrite" => quote!((*#storage_name.0).get_authority(_index) != 
+            "Write" | "ModifiedWrite" | "ReadAuthoritative" => {
+                quote!((*#storage_name.0).get_authority(_index) != 
+                    ::spatialos_gdk::worker::Authority::NotAuthoritative)
+            }
+            "ReadNotAuthoritative" => quote!((*#storage_name.0).get_authority(_index) == 
                     ::spatialos_gdk::worker::Authority::NotAuthoritative),
             _ => quote!(true),
         }
@@ -108,6 +309,19 @@ impl ComponentField {
                 quote!((*#storage_name).get_component_data_entry(*_index).last_updated
                     .occured_after(_from_time))
             }
+            "Events" => {
+                let event = &self.marker;
+                quote!(<#event as ::spatialos_gdk::worker::schema::ComponentEvent<::schema::Schema>>
+                    ::get_event(&(*#storage_name).get_component_data_entry(*_index).data)
+                    .has_events_after(_from_time))
+            }
+            "ModifiedField" => {
+                let field = &self.marker;
+                quote!((*#storage_name).get_component_data_entry(*_index).data
+                    .changed_fields_since(_from_time) &
+                    <#field as ::spatialos_gdk::worker::schema::ComponentField<::schema::Schema>>
+                    ::mask() != 0)
+            }
             _ => quote!(true),
         }
     }
@@ -119,6 +333,19 @@ impl ComponentField {
                 quote!((*#storage_name.0).get_component_data_entry(_index).last_updated
                     .occured_after(_from_time))
             }
+            "Events" => {
+                let event = &self.marker;
+                quote!(<#event as ::spatialos_gdk::worker::schema::ComponentEvent<::schema::Schema>>
+                    ::get_event(&(*#storage_name.0).get_component_data_entry(_index).data)
+                    .has_events_after(_from_time))
+            }
+            "ModifiedField" => {
+                let field = &self.marker;
+                quote!((*#storage_name.0).get_component_data_entry(_index).data
+                    .changed_fields_since(_from_time) &
+                    <#field as ::spatialos_gdk::worker::schema::ComponentField<::schema::Schema>>
+                    ::mask() != 0)
+            }
             _ => quote!(true),
         }
     }
@@ -126,6 +353,11 @@ impl ComponentField {
     fn get_chunk_storage_code(&self) -> Tokens {
         let storage_name = Ident::new(format!("storage{}", self.component));
         let component = &self.component;
+        if self.optional {
+            return quote!(let #storage_name = chunk.get_component_storage::<#component>()
+                .map(|storage| storage
+                    as *mut ::spatialos_gdk::ComponentStorage<::schema::Schema, #component>));
+        }
         quote!(let #storage_name = chunk.get_component_storage::<#component>().unwrap()
             as *mut ::spatialos_gdk::ComponentStorage<::schema::Schema, #component>)
     }
@@ -133,6 +365,11 @@ impl ComponentField {
     fn get_sendable_chunk_storage_code(&self) -> Tokens {
         let storage_name = Ident::new(format!("storage{}", self.component));
         let component = &self.component;
+        if self.optional {
+            return quote!(let #storage_name = chunk.get_component_storage::<#component>()
+                .map(|storage| ::spatialos_gdk::UnsafeSendablePointer(storage
+                    as *mut ::spatialos_gdk::ComponentStorage<::schema::Schema, #component>)));
+        }
         quote!(let #storage_name = ::spatialos_gdk::UnsafeSendablePointer(
             chunk.get_component_storage::<#component>().unwrap()
             as *mut ::spatialos_gdk::ComponentStorage<::schema::Schema, #component>
@@ -140,8 +377,14 @@ impl ComponentField {
     }
 
     fn get_chunk_storage_dirty_code(&self) -> Tokens {
+        let storage_name = Ident::new(format!("storage{}", self.component));
         let component = &self.component;
         match self.requirement.as_ref() {
+            "Write" | "ModifiedWrite" if self.optional => {
+                quote!(if #storage_name.is_some() {
+                    chunk.mark_component_storage_as_dirty::<#component>();
+                })
+            }
             "Write" | "ModifiedWrite" => {
                 quote!(chunk.mark_component_storage_as_dirty::<#component>();)
             }
@@ -149,14 +392,41 @@ impl ComponentField {
         }
     }
 
+    // An optional `Write` is only given if this worker is authoritative over the component.
+    fn get_optional_index_storage_code(&self, storage: Tokens) -> Tokens {
+        let field_name = &self.field_name;
+        match self.requirement.as_ref() {
+            "Read" => quote!(#field_name: #storage.map(|storage| Read::new(&(*storage)
+                    .get_component_data_entry(_index).data))),
+            "Write" => quote!(#field_name: #storage.and_then(|storage| {
+                    if (*storage).get_authority(_index) ==
+                        ::spatialos_gdk::worker::Authority::NotAuthoritative {
+                        None
+                    } else {
+                        Some(Write::new(&mut (*storage).get_component_data_entry(_index).data))
+                    }
+                })),
+            _ => panic!("Optional fields must be either `Read` or `Write`"),
+        }
+    }
+
     fn get_index_storage_code(&self) -> Tokens {
         let storage_name = Ident::new(format!("storage{}", self.component));
         let field_name = &self.field_name;
+        if self.optional {
+            return self.get_optional_index_storage_code(quote!(#storage_name));
+        }
         match self.requirement.as_ref() {
-            "Read" | "ModifiedRead" => quote!(#field_name: Read::new(&(*#storage_name)
-                    .get_component_data_entry(_index).data)),
+            "Read" | "ModifiedRead" | "ReadAuthoritative" | "ReadNotAuthoritative" => {
+                quote!(#field_name: Read::new(&(*#storage_name)
+                    .get_component_data_entry(_index).data))
+            }
             "Write" | "ModifiedWrite" => quote!(#field_name: Write::new(&mut (*#storage_name)
                     .get_component_data_entry(_index).data)),
+            "Events" => quote!(#field_name: Events::new(&(*#storage_name)
+                    .get_component_data_entry(_index).data, _from_time)),
+            "ModifiedField" => quote!(#field_name: ModifiedField::new(&(*#storage_name)
+                    .get_component_data_entry(_index).data)),
             _ => panic!("All fields must be component types"),
         }
     }
@@ -164,11 +434,22 @@ impl ComponentField {
     fn get_sendable_index_storage_code(&self) -> Tokens {
         let storage_name = Ident::new(format!("storage{}", self.component));
         let field_name = &self.field_name;
+        if self.optional {
+            return self.get_optional_index_storage_code(
+                quote!(#storage_name.as_ref().map(|storage| storage.0)),
+            );
+        }
         match self.requirement.as_ref() {
-            "Read" | "ModifiedRead" => quote!(#field_name: Read::new(&(*#storage_name.0)
-                    .get_component_data_entry(_index).data)),
+            "Read" | "ModifiedRead" | "ReadAuthoritative" | "ReadNotAuthoritative" => {
+                quote!(#field_name: Read::new(&(*#storage_name.0)
+                    .get_component_data_entry(_index).data))
+            }
             "Write" | "ModifiedWrite" => quote!(#field_name: Write::new(&mut (*#storage_name.0)
                     .get_component_data_entry(_index).data)),
+            "Events" => quote!(#field_name: Events::new(&(*#storage_name.0)
+                    .get_component_data_entry(_index).data, _from_time)),
+            "ModifiedField" => quote!(#field_name: ModifiedField::new(&(*#storage_name.0)
+                    .get_component_data_entry(_index).data)),
             _ => panic!("All fields must be component types"),
         }
     }
@@ -189,7 +470,16 @@ fn impl_component_group(input: &DeriveInput) -> Tokens {
     });
     let component_names = component_fields
         .clone()
-        .map(|field| field.component.clone());
+        .filter_map(|field| field.get_required_component());
+    let written_component_names = component_fields
+        .clone()
+        .filter_map(|field| field.get_written_component());
+    let read_component_names = component_fields
+        .clone()
+        .filter_map(|field| field.get_read_component());
+    let excluded_component_names = fields
+        .clone()
+        .filter_map(|field| field.get_excluded_component());
     let authority_filter_code = component_fields
         .clone()
         .map(|field| field.get_authority_filter_code());
@@ -222,16 +512,39 @@ fn impl_component_group(input: &DeriveInput) -> Tokens {
         #[allow(non_snake_case,unused_unsafe,unused_imports)]
         impl<'a> ::spatialos_gdk::ComponentGroup<'a, ::schema::Schema> for #input_type<'a> {
             fn add_to_bit_field(bit_field: &mut <::schema::Schema as
+                ::spatialos_gdk::worker::schema::GeneratedSchema>::ComponentBitField,
+                excluded_bit_field: &mut <::schema::Schema as
                 ::spatialos_gdk::worker::schema::GeneratedSchema>::ComponentBitField) {
                 use ::spatialos_gdk::ComponentBitField;
                 use ::spatialos_gdk::worker::schema::Component;
 
-                #(bit_field.add_component(#component_names::component_id());)*
+                #(bit_field.add_component(
+                    <#component_names as Component<::schema::Schema>>::component_id());)*
+                #(excluded_bit_field.add_component(
+                    <#excluded_component_names as Component<::schema::Schema>>::component_id());)*
+            }
+
+            fn add_to_written_bit_field(bit_field: &mut <::schema::Schema as
+                ::spatialos_gdk::worker::schema::GeneratedSchema>::ComponentBitField) {
+                use ::spatialos_gdk::ComponentBitField;
+                use ::spatialos_gdk::worker::schema::Component;
+
+                #(bit_field.add_component(
+                    <#written_component_names as Component<::schema::Schema>>::component_id());)*
+            }
+
+            fn add_to_read_bit_field(bit_field: &mut <::schema::Schema as
+                ::spatialos_gdk::worker::schema::GeneratedSchema>::ComponentBitField) {
+                use ::spatialos_gdk::ComponentBitField;
+                use ::spatialos_gdk::worker::schema::Component;
+
+                #(bit_field.add_component(
+                    <#read_component_names as Component<::schema::Schema>>::component_id());)*
             }
 
             fn get_iterator(chunk: &'a mut ::spatialos_gdk::Chunk<::schema::Schema>,
                 _from_time: &'a ::spatialos_gdk::WorldTime) -> Box<Iterator<Item = Self> + 'a> {
-                use ::spatialos_gdk::worker::schema::Component;
+                use ::spatialos_gdk::worker::schema::{Component, ComponentDataInterface};
 
                 let chunk_ptr: ::spatialos_gdk::UnsafeSendablePointer
                     <::spatialos_gdk::Chunk<::schema::Schema>> =
@@ -261,7 +574,7 @@ fn impl_component_group(input: &DeriveInput) -> Tokens {
                 _from_time: &'a ::spatialos_gdk::WorldTime, cb: F)
                 where F: Fn(&mut Self)
             {
-                use ::spatialos_gdk::worker::schema::Component;
+                use ::spatialos_gdk::worker::schema::{Component, ComponentDataInterface};
 
                 let chunk_ptr: ::spatialos_gdk::UnsafeSendablePointer
                     <::spatialos_gdk::Chunk<::schema::Schema>> =
@@ -286,3 +599,116 @@ fn impl_component_group(input: &DeriveInput) -> Tokens {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_fields(fields: &str) -> Vec<Field> {
+        let input = format!("pub struct Group<'a> {{ {} }}", fields);
+        match syn::parse_derive_input(&input).unwrap().body {
+            Body::Struct(VariantData::Struct(fields)) => fields,
+            _ => unreachable!(),
+        }
+    }
+
+    fn component_group(fields: &str) -> String {
+        let input = format!("pub struct Group<'a> {{ {} }}", fields);
+        impl_component_group(&syn::parse_derive_input(&input).unwrap()).to_string()
+    }
+
+    fn name(ident: Option<Ident>) -> Option<String> {
+        ident.map(|ident| ident.to_string())
+    }
+
+    #[test]
+    fn optional_fields_are_not_required() {
+        let read = ComponentField::from_field(&parse_fields(
+            "pub health: Option<Read<'a, Schema, Health>>",
+        )[0]);
+        assert!(read.optional);
+        assert_eq!(name(read.get_required_component()), None);
+        assert_eq!(name(read.get_written_component()), None);
+
+        let write = ComponentField::from_field(&parse_fields(
+            "pub health: Option<Write<'a, Schema, Health>>",
+        )[0]);
+        assert_eq!(name(write.get_required_component()), None);
+        assert_eq!(name(write.get_written_component()), Some("Health".to_string()));
+    }
+
+    #[test]
+    fn every_component_which_is_not_written_is_read() {
+        let fields = parse_fields(
+            "pub position: Write<'a, Schema, Position>, \
+             pub health: Option<Read<'a, Schema, Health>>, \
+             pub hits: Events<'a, Schema, Health, HealthGotHit>",
+        );
+        let read: Vec<Option<String>> = fields
+            .iter()
+            .map(|field| name(ComponentField::from_field(field).get_read_component()))
+            .collect();
+        assert_eq!(read, vec![None, Some("Health".to_string()), Some("Health".to_string())]);
+    }
+
+    #[test]
+    fn optional_writes_are_only_given_with_authority() {
+        let code = component_group("pub health: Option<Write<'a, Schema, Health>>,");
+        assert!(code.contains(
+            "if ( * storage ) . get_authority ( _index ) == \
+             :: spatialos_gdk :: worker :: Authority :: NotAuthoritative { None }"
+        ));
+    }
+
+    #[test]
+    fn authority_filters_are_generated_for_both_iterators() {
+        let owned = component_group("pub position: ReadAuthoritative<'a, Schema, Position>,");
+        assert_eq!(
+            owned
+                .matches(") != :: spatialos_gdk :: worker :: Authority :: NotAuthoritative")
+                .count(),
+            2
+        );
+
+        let remote = component_group("pub position: ReadNotAuthoritative<'a, Schema, Position>,");
+        assert_eq!(
+            remote
+                .matches(") == :: spatialos_gdk :: worker :: Authority :: NotAuthoritative")
+                .count(),
+            2
+        );
+        assert!(!remote.contains("!="));
+
+        let read = component_group("pub position: Read<'a, Schema, Position>,");
+        assert!(!read.contains("get_authority"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Optional fields must be either `Read` or `Write`")]
+    fn optional_fields_must_be_read_or_write() {
+        parse_fields("pub hits: Option<Events<'a, Schema, Health, HealthGotHit>>")
+            .iter()
+            .for_each(|field| {
+                GroupField::from_field(field);
+            });
+    }
+
+    #[test]
+    fn without_fields_exclude_their_component() {
+        let fields = parse_fields(
+            "pub position: Read<'a, Schema, Position>, pub no_weapon: Without<Schema, Weapon>",
+        );
+        assert_eq!(name(GroupField::from_field(&fields[0]).get_excluded_component()), None);
+        assert_eq!(
+            name(GroupField::from_field(&fields[1]).get_excluded_component()),
+            Some("Weapon".to_string())
+        );
+
+        let code = component_group(
+            "pub position: Read<'a, Schema, Position>, pub no_weapon: Without<Schema, Weapon>,",
+        );
+        assert!(code.contains("excluded_bit_field . add_component ( < Weapon as Component"));
+        assert_eq!(code.matches("add_component ( < Weapon").count(), 1);
+        assert!(code.contains("no_weapon : Without :: new ( )"));
+    }
+}
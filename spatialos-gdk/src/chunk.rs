@@ -1,18 +1,23 @@
+use ComponentBitField;
 use TagComponentArray;
+use command_buffer;
 use downcast_rs::Downcast;
-use entity::Entity;
-use std::cell::RefCell;
+use entity::is_local_entity;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::rc::Rc;
+use std::mem;
 use worker::schema::DynamicComponentHandler;
 use worker::schema::{Component, GeneratedSchema};
 use worker::schema::{ComponentDataInterface, ComponentUpdateInterface};
 use worker::{Authority, ComponentId, Connection, EntityId};
 use world::{PartialEntity, WorldTime};
 
-pub const MAX_ENTITIES_PER_CHUNK: usize = 1024;
+// The number of entities which fit in a chunk, unless set using
+// `World::set_max_entities_per_chunk`.
+pub const DEFAULT_MAX_ENTITIES_PER_CHUNK: usize = 1024;
+
+pub type StorageConstructor<S> = fn(&mut WorldTime) -> Box<ComponentStorageInterface<S>>;
 
 #[derive(Default)]
 pub struct ComponentDataEntry<S: GeneratedSchema, C: Component<S>> {
@@ -29,7 +34,7 @@ pub struct ComponentStorage<S: GeneratedSchema, C: Component<S>> {
     is_dirty: bool,
 }
 
-pub trait ComponentStorageInterface<S: GeneratedSchema>: Downcast {
+pub trait ComponentStorageInterface<S: GeneratedSchema>: Downcast + Send {
     fn update_last_updated(&mut self, world_time: &mut WorldTime);
     fn set_component_data(
         &mut self,
@@ -52,9 +57,23 @@ pub trait ComponentStorageInterface<S: GeneratedSchema>: Downcast {
         num_entities: usize,
         connection: &mut Connection,
     );
-    fn cleanup_after_frame(&mut self, num_entities: usize);
+    fn cleanup_after_frame(&mut self, num_entities: usize, oldest_cursor: &WorldTime) -> bool;
+    fn loop_back_events(&mut self, num_entities: usize, time: &WorldTime) -> bool;
+    fn update_locally_changed(&mut self, num_entities: usize, time: &WorldTime);
     fn mark_as_dirty(&mut self);
     fn swap_entity(&mut self, from: usize, to: usize);
+    // Adds a slot with default data for a new entity at the end of the storage.
+    fn push_entity(&mut self);
+    // Removes the slot of the last entity in the storage.
+    fn pop_entity(&mut self);
+    fn shrink_to_fit(&mut self);
+    fn create_empty(&self, world_time: &mut WorldTime) -> Box<ComponentStorageInterface<S>>;
+    fn move_entity_to(
+        &mut self,
+        from: usize,
+        to_storage: &mut Box<ComponentStorageInterface<S>>,
+        to: usize,
+    );
 }
 
 #[allow(dead_code)]
@@ -67,14 +86,8 @@ mod downcast {
 
 impl<S: GeneratedSchema, C: Component<S>> ComponentStorage<S, C> {
     fn new(world_time: &mut WorldTime) -> ComponentStorage<S, C> {
-        let mut storage_vec: Vec<ComponentDataEntry<S, C>> =
-            Vec::with_capacity(MAX_ENTITIES_PER_CHUNK);
-        for _ in 0..MAX_ENTITIES_PER_CHUNK {
-            storage_vec.push(Default::default());
-        }
-
         ComponentStorage {
-            data: storage_vec,
+            data: Vec::new(),
             authority: TagComponentArray::new(),
             last_updated: world_time.get_time(),
             has_events_this_frame: false,
@@ -89,6 +102,10 @@ impl<S: GeneratedSchema, C: Component<S>> ComponentStorage<S, C> {
         &mut self.data[entity_index]
     }
 
+    pub fn get_component_data(&self, entity_index: usize) -> &C::Data {
+        &self.data[entity_index].data
+    }
+
     pub fn get_authority(&mut self, entity_index: usize) -> Authority {
         if self.authority.get_tag(entity_index) {
             Authority::Authoritative
@@ -96,6 +113,30 @@ impl<S: GeneratedSchema, C: Component<S>> ComponentStorage<S, C> {
             Authority::NotAuthoritative
         }
     }
+
+    pub fn insert_data(
+        &mut self,
+        world_time: &mut WorldTime,
+        entity_index: usize,
+        data: C::Data,
+        authority: Authority,
+    ) {
+        let mut data_entry = ComponentDataEntry::<S, C> {
+            data,
+            last_updated: world_time.get_time(),
+        };
+        data_entry.data.stamp_changed_fields(&data_entry.last_updated);
+        self.data[entity_index] = data_entry;
+        self.last_updated = world_time.get_time();
+        self.authority
+            .set_tag(entity_index, authority != Authority::NotAuthoritative);
+    }
+}
+
+impl<S: 'static + GeneratedSchema, C: 'static + Component<S>> ComponentStorage<S, C> {
+    pub fn new_boxed(world_time: &mut WorldTime) -> Box<ComponentStorageInterface<S>> {
+        Box::new(ComponentStorage::<S, C>::new(world_time))
+    }
 }
 
 impl<C: Component<S> + 'static, S: 'static + GeneratedSchema> ComponentStorageInterface<S>
@@ -112,13 +153,12 @@ impl<C: Component<S> + 'static, S: 'static + GeneratedSchema> ComponentStorageIn
         data: S::ComponentData,
         authority: Authority,
     ) {
-        let data_entry = ComponentDataEntry::<S, C> {
-            data: C::extract_data(data).unwrap(),
-            last_updated: world_time.get_time(),
-        };
-        self.data[entity_index] = data_entry;
-        self.last_updated = world_time.get_time();
-        self.set_authority(entity_index, authority);
+        self.insert_data(
+            world_time,
+            entity_index,
+            C::extract_data(data).unwrap(),
+            authority,
+        );
     }
 
     // True if the update contains events
@@ -137,6 +177,10 @@ impl<C: Component<S> + 'static, S: 'static + GeneratedSchema> ComponentStorageIn
         let entry = &mut self.data[entity_index];
         C::apply_update_to_data(&mut entry.data, update);
         entry.last_updated = world_time.get_time();
+        entry.data.stamp_changed_fields(&entry.last_updated);
+        if contains_events {
+            entry.data.stamp_events(&entry.last_updated);
+        }
         self.last_updated = world_time.get_time();
 
         contains_events
@@ -162,8 +206,11 @@ impl<C: Component<S> + 'static, S: 'static + GeneratedSchema> ComponentStorageIn
                 let entry = self.get_component_data_entry(index);
                 let entity_id = entity_ids[index];
                 if entry.data.get_and_clear_dirty_bit() {
-                    let update = entry.data.serialise_update();
-                    connection.send_component_update(entity_id, C::component_id(), update);
+                    if is_local_entity(entity_id) {
+                        entry.data.discard_update();
+                    } else if let Some(update) = entry.data.serialise_update() {
+                        connection.send_component_update(entity_id, C::component_id(), update);
+                    }
                 }
             }
 
@@ -171,15 +218,43 @@ impl<C: Component<S> + 'static, S: 'static + GeneratedSchema> ComponentStorageIn
         }
     }
 
-    fn cleanup_after_frame(&mut self, num_entities: usize) {
+    fn cleanup_after_frame(&mut self, num_entities: usize, oldest_cursor: &WorldTime) -> bool {
         if self.has_events_this_frame {
+            let mut has_events = false;
+            for index in 0..num_entities {
+                let entry = self.get_component_data_entry(index);
+                has_events = entry.data.cleanup_after_frame(oldest_cursor) || has_events;
+            }
+
+            self.has_events_this_frame = has_events;
+        }
+        self.has_events_this_frame
+    }
+
+    // Only storages which have been written to can have been changed locally.
+    fn update_locally_changed(&mut self, num_entities: usize, time: &WorldTime) {
+        if self.is_dirty {
             for index in 0..num_entities {
                 let entry = self.get_component_data_entry(index);
-                entry.data.cleanup_after_frame();
+                if entry.data.stamp_changed_fields(time) {
+                    entry.last_updated = time.clone();
+                    self.last_updated = time.clone();
+                }
             }
+        }
+    }
 
-            self.has_events_this_frame = false;
+    // Only storages which have been written to can have newly triggered events.
+    fn loop_back_events(&mut self, num_entities: usize, time: &WorldTime) -> bool {
+        if self.is_dirty {
+            for index in 0..num_entities {
+                let entry = self.get_component_data_entry(index);
+                if entry.data.loop_back_events(time) {
+                    self.has_events_this_frame = true;
+                }
+            }
         }
+        self.has_events_this_frame
     }
 
     fn swap_entity(&mut self, from: usize, to: usize) {
@@ -189,13 +264,49 @@ impl<C: Component<S> + 'static, S: 'static + GeneratedSchema> ComponentStorageIn
         self.authority.set_tag(to, from_authority);
         self.authority.set_tag(from, to_authority);
     }
+
+    fn push_entity(&mut self) {
+        self.data.push(Default::default());
+    }
+
+    fn pop_entity(&mut self) {
+        if self.data.pop().is_some() {
+            let index = self.data.len();
+            self.authority.set_tag(index, false);
+        }
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+    }
+
+    fn create_empty(&self, world_time: &mut WorldTime) -> Box<ComponentStorageInterface<S>> {
+        ComponentStorage::<S, C>::new_boxed(world_time)
+    }
+
+    fn move_entity_to(
+        &mut self,
+        from: usize,
+        to_storage: &mut Box<ComponentStorageInterface<S>>,
+        to: usize,
+    ) {
+        let to_storage = to_storage
+            .downcast_mut::<ComponentStorage<S, C>>()
+            .unwrap();
+        to_storage.data[to] = mem::replace(&mut self.data[from], Default::default());
+        to_storage.authority.set_tag(to, self.authority.get_tag(from));
+        to_storage.is_dirty = to_storage.is_dirty || self.is_dirty;
+        to_storage.has_events_this_frame =
+            to_storage.has_events_this_frame || self.has_events_this_frame;
+    }
 }
 
 #[doc(hidden)]
 pub struct Chunk<S: GeneratedSchema> {
-    chunk_index: usize,
-    entities: Vec<Rc<RefCell<Entity<S>>>>,
-    entity_ids: [EntityId; MAX_ENTITIES_PER_CHUNK],
+    // The maximum number of entities in this chunk. The entity and component storage
+    // vectors grow as entities are added, up to this size.
+    capacity: usize,
+    entity_ids: Vec<EntityId>,
     data: HashMap<ComponentId, Box<ComponentStorageInterface<S>>>,
     num_entities: usize,
     is_dirty: bool,
@@ -217,7 +328,7 @@ impl<S: 'static + GeneratedSchema> DynamicComponentHandler<S> for Chunk<S> {
 
 impl<S: 'static + GeneratedSchema> Chunk<S> {
     pub fn new(
-        chunk_index: usize,
+        capacity: usize,
         world_time: &mut WorldTime,
         template_entity: &PartialEntity<S>,
     ) -> Chunk<S> {
@@ -227,22 +338,8 @@ impl<S: 'static + GeneratedSchema> Chunk<S> {
             .map(|v| *v)
             .collect::<HashSet<ComponentId>>();
 
-        let mut entities_vec: Vec<Rc<RefCell<Entity<S>>>> =
-            Vec::with_capacity(MAX_ENTITIES_PER_CHUNK);
-        for _ in 0..MAX_ENTITIES_PER_CHUNK {
-            entities_vec.push(Default::default());
-        }
-
-        let mut chunk = Chunk {
-            chunk_index,
-            entities: entities_vec,
-            entity_ids: [0; MAX_ENTITIES_PER_CHUNK],
-            data: HashMap::new(),
-            num_entities: 0,
-            is_dirty: false,
-            has_events_this_frame: false,
-            component_ids,
-        };
+        let mut chunk = Chunk::from_storages(capacity, HashMap::new());
+        chunk.component_ids = component_ids;
 
         S::run_dynamic_component_handler(&mut chunk);
 
@@ -253,6 +350,34 @@ impl<S: 'static + GeneratedSchema> Chunk<S> {
         chunk
     }
 
+    pub fn from_storages(
+        capacity: usize,
+        data: HashMap<ComponentId, Box<ComponentStorageInterface<S>>>,
+    ) -> Chunk<S> {
+        Chunk {
+            capacity,
+            entity_ids: Vec::new(),
+            component_ids: data.keys().map(|v| *v).collect(),
+            data,
+            num_entities: 0,
+            is_dirty: false,
+            has_events_this_frame: false,
+        }
+    }
+
+    // Creates empty storages for each component in this chunk which is also in `bit_field`.
+    pub fn create_empty_storages(
+        &self,
+        bit_field: &S::ComponentBitField,
+        world_time: &mut WorldTime,
+    ) -> HashMap<ComponentId, Box<ComponentStorageInterface<S>>> {
+        self.data
+            .iter()
+            .filter(|(component_id, _)| bit_field.has_component(**component_id))
+            .map(|(component_id, storage)| (*component_id, storage.create_empty(world_time)))
+            .collect()
+    }
+
     pub fn mark_component_storage_as_dirty<C: 'static + Component<S>>(&mut self) {
         self.is_dirty = true;
         self.get_component_storage_interface(C::component_id())
@@ -269,18 +394,50 @@ impl<S: 'static + GeneratedSchema> Chunk<S> {
         }
     }
 
-    pub fn cleanup_after_frame(&mut self) {
+    pub fn cleanup_after_frame(&mut self, oldest_cursor: &WorldTime) {
         if self.has_events_this_frame {
+            let mut has_events = false;
             for (_, mut storage) in &mut self.data {
-                storage.cleanup_after_frame(self.num_entities);
+                has_events =
+                    storage.cleanup_after_frame(self.num_entities, oldest_cursor) || has_events;
+            }
+
+            self.has_events_this_frame = has_events;
+        }
+    }
+
+    pub fn update_locally_changed(&mut self, time: &WorldTime) {
+        if self.is_dirty {
+            for (_, storage) in &mut self.data {
+                storage.update_locally_changed(self.num_entities, time);
             }
+        }
+    }
+
+    pub fn loop_back_events(&mut self, time: &WorldTime) {
+        if self.is_dirty {
+            for (_, storage) in &mut self.data {
+                if storage.loop_back_events(self.num_entities, time) {
+                    self.has_events_this_frame = true;
+                }
+            }
+        }
+    }
 
-            self.has_events_this_frame = false;
+    pub fn num_entities(&self) -> usize {
+        self.num_entities
+    }
+
+    // Frees any memory which was allocated for entities that have since been removed.
+    pub fn shrink_to_fit(&mut self) {
+        self.entity_ids.shrink_to_fit();
+        for (_, storage) in &mut self.data {
+            storage.shrink_to_fit();
         }
     }
 
     pub fn has_space(&self) -> bool {
-        self.num_entities < MAX_ENTITIES_PER_CHUNK
+        self.num_entities < self.capacity
     }
 
     pub fn entity_index_iter<'a>(&'a self) -> Box<Iterator<Item = usize> + 'a> {
@@ -291,29 +448,28 @@ impl<S: 'static + GeneratedSchema> Chunk<S> {
     where
         F: Fn(usize),
     {
-        (0..self.num_entities).into_par_iter().for_each(op);
+        match command_buffer::current_pass() {
+            Some(pass) => {
+                let entity_ids = &self.entity_ids;
+                (0..self.num_entities).into_par_iter().for_each(|index| {
+                    command_buffer::with_position(pass, Some(entity_ids[index]), || op(index))
+                });
+            }
+            None => (0..self.num_entities).into_par_iter().for_each(op),
+        }
     }
 
     pub fn get_entity_id(&self, index: usize) -> EntityId {
         self.entity_ids[index]
     }
 
+    // Adds the entity and its component data to the end of this chunk, returning its index.
     pub fn add_entity(
         &mut self,
         world_time: &mut WorldTime,
         mut entity: PartialEntity<S>,
-    ) -> Rc<RefCell<Entity<S>>> {
-        let entity_index = self.num_entities;
-        self.num_entities = self.num_entities + 1;
-
-        let new_entity = Rc::new(RefCell::new(Entity::new(
-            self.chunk_index,
-            entity_index,
-            entity.bit_field,
-        )));
-
-        self.entities[entity_index] = new_entity.clone();
-        self.entity_ids[entity_index] = entity.entity_id;
+    ) -> usize {
+        let entity_index = self.push_entity(entity.entity_id);
 
         for (component_id, component_data) in entity.component_data.drain() {
             let authority = entity
@@ -324,32 +480,63 @@ impl<S: 'static + GeneratedSchema> Chunk<S> {
                 .set_component_data(world_time, entity_index, component_data, *authority);
         }
 
-        new_entity
+        entity_index
+    }
+
+    // Gives the entity a slot at the end of this chunk without setting any component data.
+    pub fn push_entity(&mut self, entity_id: EntityId) -> usize {
+        let entity_index = self.num_entities;
+        self.num_entities = self.num_entities + 1;
+
+        self.entity_ids.push(entity_id);
+        for (_, storage) in &mut self.data {
+            storage.push_entity();
+        }
+
+        entity_index
+    }
+
+    // Moves the data of every component which both chunks share into the other chunk.
+    pub fn move_entity_data_to(&mut self, from: usize, other: &mut Chunk<S>, to: usize) {
+        for (component_id, to_storage) in other.data.iter_mut() {
+            if let Some(from_storage) = self.data.get_mut(component_id) {
+                from_storage.move_entity_to(from, to_storage, to);
+            }
+        }
+
+        other.is_dirty = other.is_dirty || self.is_dirty;
+        other.has_events_this_frame = other.has_events_this_frame || self.has_events_this_frame;
     }
 
-    pub fn remove_entity(&mut self, entity: &Entity<S>) {
+    // Returns the `EntityId` of the entity which was moved into the gap, if there was one.
+    pub fn remove_entity_at(&mut self, entity_index: usize) -> Option<EntityId> {
         // Swap last entity with the new gap
-        let entity_index = entity.index_in_chunk;
         let num_entities = self.num_entities;
 
-        self.move_entity(num_entities - 1, entity_index);
+        let moved = self.move_entity(num_entities - 1, entity_index);
+
+        self.entity_ids.pop();
+        for (_, storage) in &mut self.data {
+            storage.pop_entity();
+        }
 
         self.num_entities = self.num_entities - 1;
+
+        moved
     }
 
-    fn move_entity(&mut self, from: usize, to: usize) {
+    fn move_entity(&mut self, from: usize, to: usize) -> Option<EntityId> {
         if from == to {
-            return;
+            return None;
         }
 
-        self.entities.swap(from, to);
         self.entity_ids.swap(from, to);
 
-        self.entities[to].borrow_mut().index_in_chunk = to;
-
         for (_, mut storage) in &mut self.data {
             storage.swap_entity(from, to);
         }
+
+        Some(self.entity_ids[to])
     }
 
     fn get_component_storage_interface(
@@ -362,19 +549,28 @@ impl<S: 'static + GeneratedSchema> Chunk<S> {
     pub fn get_component_storage<C: 'static + Component<S>>(
         &mut self,
     ) -> Option<&mut ComponentStorage<S, C>> {
-        self.get_component_storage_interface(C::component_id())
-            .downcast_mut::<ComponentStorage<S, C>>()
+        self.data
+            .get_mut(&C::component_id())
+            .and_then(|storage| storage.downcast_mut::<ComponentStorage<S, C>>())
+    }
+
+    pub fn get_component_storage_ref<C: 'static + Component<S>>(
+        &self,
+    ) -> Option<&ComponentStorage<S, C>> {
+        self.data
+            .get(&C::component_id())
+            .and_then(|storage| storage.downcast_ref::<ComponentStorage<S, C>>())
     }
 
     pub fn apply_component_update(
         &mut self,
         component_id: ComponentId,
         world_time: &mut WorldTime,
-        entity: &Entity<S>,
+        entity_index: usize,
         update: S::ComponentUpdate,
     ) {
         let contains_events = self.get_component_storage_interface(component_id)
-            .apply_component_update(world_time, entity.index_in_chunk, &update);
+            .apply_component_update(world_time, entity_index, &update);
         if contains_events {
             self.has_events_this_frame = true;
         }
@@ -383,10 +579,79 @@ impl<S: 'static + GeneratedSchema> Chunk<S> {
     pub fn apply_authority(
         &mut self,
         component_id: ComponentId,
-        entity: &Entity<S>,
+        entity_index: usize,
         authority: Authority,
     ) {
         self.get_component_storage_interface(component_id)
-            .set_authority(entity.index_in_chunk, authority);
+            .set_authority(entity_index, authority);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schema::{partial_entity, ComponentData, Position, Schema};
+
+    fn position(x: i64) -> ComponentData {
+        ComponentData::Position(Position { x }.into_data())
+    }
+
+    fn new_chunk(capacity: usize, world_time: &mut WorldTime) -> Chunk<Schema> {
+        Chunk::new(capacity, world_time, &partial_entity(0, vec![position(0)]))
+    }
+
+    fn positions(chunk: &Chunk<Schema>) -> &Vec<ComponentDataEntry<Schema, Position>> {
+        &chunk.get_component_storage_ref::<Position>().unwrap().data
+    }
+
+    #[test]
+    fn chunks_only_allocate_for_the_entities_they_hold() {
+        let mut world_time = WorldTime::new();
+        let mut chunk = new_chunk(1024, &mut world_time);
+        assert_eq!(chunk.entity_ids.capacity(), 0);
+        assert_eq!(positions(&chunk).capacity(), 0);
+
+        for entity_id in 1..4 {
+            chunk.add_entity(&mut world_time, partial_entity(entity_id, vec![position(entity_id)]));
+        }
+        assert_eq!(chunk.num_entities(), 3);
+        assert_eq!(positions(&chunk).len(), 3);
+        assert!(chunk.entity_ids.capacity() < 1024);
+        assert!(positions(&chunk).capacity() < 1024);
+    }
+
+    #[test]
+    fn chunks_have_space_until_they_reach_their_capacity() {
+        let mut world_time = WorldTime::new();
+        let mut chunk = new_chunk(2, &mut world_time);
+
+        assert!(chunk.has_space());
+        chunk.add_entity(&mut world_time, partial_entity(1, vec![position(1)]));
+        assert!(chunk.has_space());
+        chunk.add_entity(&mut world_time, partial_entity(2, vec![position(2)]));
+        assert!(!chunk.has_space());
+
+        chunk.remove_entity_at(0);
+        assert!(chunk.has_space());
+    }
+
+    #[test]
+    fn shrinking_a_chunk_frees_removed_entities() {
+        let mut world_time = WorldTime::new();
+        let mut chunk = new_chunk(1024, &mut world_time);
+        for entity_id in 1..33 {
+            chunk.add_entity(&mut world_time, partial_entity(entity_id, vec![position(entity_id)]));
+        }
+
+        // Each removal moves the last entity into the gap.
+        for _ in 0..31 {
+            chunk.remove_entity_at(0);
+        }
+        chunk.shrink_to_fit();
+
+        assert_eq!(chunk.entity_ids, vec![2]);
+        assert_eq!(chunk.entity_ids.capacity(), 1);
+        assert_eq!(positions(&chunk).capacity(), 1);
+        assert_eq!(*positions(&chunk)[0].data.x, 2);
     }
 }
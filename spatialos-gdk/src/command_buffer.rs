@@ -0,0 +1,371 @@
+use boxfnonce::SendBoxFnOnce;
+use entity_template::EntityTemplate;
+use local_component::LocalComponent;
+use std::cell::Cell;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use worker::schema::{Command, Component, GeneratedSchema};
+use worker::{CommandStatus, EntityId, LogLevel};
+use world::World;
+
+type BufferedCommand<S> = SendBoxFnOnce<'static, (*mut World<S>,)>;
+
+// Identifies a recorder by where it was created, as the order they are dropped in is not
+// always the same. Recorders created within `Entities::par_for_each` are ordered by the call
+// they were created in and then by the entity being processed, so the order does not depend
+// on which thread processed that entity. An entity is only processed by one thread at a time,
+// so the sequence orders the recorders created while processing it. Recorders created outside
+// of `par_for_each` are ordered before any created by the next call.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct RecorderKey {
+    pass: usize,
+    processing: Option<EntityId>,
+    sequence: u64,
+}
+
+static NEXT_PASS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static POSITION: Cell<Option<(usize, Option<EntityId>)>> = Cell::new(None);
+    static NEXT_SEQUENCE: Cell<u64> = Cell::new(0);
+}
+
+impl RecorderKey {
+    fn next() -> RecorderKey {
+        let (pass, processing) = POSITION
+            .with(|position| position.get())
+            .unwrap_or_else(|| (NEXT_PASS.load(Ordering::Relaxed), None));
+
+        RecorderKey {
+            pass,
+            processing,
+            sequence: NEXT_SEQUENCE.with(|sequence| {
+                let next = sequence.get();
+                sequence.set(next + 1);
+                next
+            }),
+        }
+    }
+}
+
+// Starts a new `par_for_each` pass, which is played back after any recorders created before it.
+pub fn next_pass() -> usize {
+    NEXT_PASS.fetch_add(1, Ordering::Relaxed)
+}
+
+// The pass which the current thread is iterating over, if any.
+pub fn current_pass() -> Option<usize> {
+    POSITION.with(|position| position.get().map(|(pass, _)| pass))
+}
+
+// Calls `f`, with any recorders it creates ordered as part of the given pass and, if given, as
+// created while processing the given entity.
+pub fn with_position<F: FnOnce()>(pass: usize, processing: Option<EntityId>, f: F) {
+    let previous = POSITION.with(|position| position.replace(Some((pass, processing))));
+    f();
+    POSITION.with(|position| position.set(previous));
+}
+
+/// A thread-safe buffer of operations on the `World` which will be performed
+/// once the current system has finished updating.
+///
+/// This allows closures given to `Entities::par_for_each`, which have no access to the
+/// `World`, to create and delete entities, send commands and events, add and remove
+/// local components, and log.
+///
+/// Operations are recorded using a `CommandRecorder`, which is created for the entity
+/// being processed. After each system's `on_update`, the buffer is played back in order
+/// of the recording entity's `EntityId`, and then in a deterministic order for the
+/// recorders of that entity: recorders created by an earlier `par_for_each` call come
+/// first, then recorders created within the same call are ordered by the `EntityId` of the
+/// entity being processed when they were created, and then by the order they were created
+/// in while processing it. Recorders created outside of `par_for_each` come after those
+/// from earlier calls and before those from later ones. Finally, operations are played
+/// back in the order each recorder recorded them. This means the playback order does not
+/// depend on how the iteration was split between threads.
+///
+/// ## Example
+///
+/// ```
+/// let command_buffer = world.command_buffer();
+/// entities.par_for_each::<HealthData, _>(|entity| {
+///     if *entity.health.current <= 0 {
+///         command_buffer
+///             .record(entity.entity_id)
+///             .delete_entity(entity.entity_id, |_world, _entity_id| {}, |_world, _status, _message| {});
+///     }
+/// });
+/// ```
+pub struct CommandBuffer<S: GeneratedSchema> {
+    commands: Arc<Mutex<Vec<(EntityId, RecorderKey, Vec<BufferedCommand<S>>)>>>,
+}
+
+impl<S: GeneratedSchema> Clone for CommandBuffer<S> {
+    fn clone(&self) -> CommandBuffer<S> {
+        CommandBuffer {
+            commands: self.commands.clone(),
+        }
+    }
+}
+
+impl<S: 'static + GeneratedSchema> CommandBuffer<S> {
+    #[doc(hidden)]
+    pub fn new() -> CommandBuffer<S> {
+        CommandBuffer {
+            commands: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Creates a `CommandRecorder` for operations caused by the given entity. The
+    /// operations are added to this buffer when the recorder is dropped.
+    pub fn record(&self, entity_id: EntityId) -> CommandRecorder<S> {
+        CommandRecorder {
+            buffer: self,
+            entity_id,
+            key: RecorderKey::next(),
+            commands: Vec::new(),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn play_back(&self, world: &mut World<S>) {
+        let mut commands = mem::replace(&mut *self.commands.lock().unwrap(), Vec::new());
+
+        commands.sort_by_key(|&(entity_id, key, _)| (entity_id, key));
+
+        let world_ptr = world as *mut World<S>;
+        for (_, _, entity_commands) in commands {
+            for command in entity_commands {
+                command.call(world_ptr);
+            }
+        }
+    }
+}
+
+/// Records operations into a `CommandBuffer` on behalf of a single entity.
+///
+/// The operations have the same behaviour as the `World` methods of the same name,
+/// but are only performed once the current system has finished updating.
+pub struct CommandRecorder<'a, S: 'static + GeneratedSchema> {
+    buffer: &'a CommandBuffer<S>,
+    entity_id: EntityId,
+    key: RecorderKey,
+    commands: Vec<BufferedCommand<S>>,
+}
+
+impl<'a, S: 'static + GeneratedSchema> CommandRecorder<'a, S> {
+    /// Records a call to `World::create_entity`.
+    pub fn create_entity<A: 'static + Send, F: 'static + Send>(
+        &mut self,
+        entity_template: EntityTemplate,
+        success: A,
+        failure: F,
+    ) -> &mut CommandRecorder<'a, S>
+    where
+        A: FnOnce(&mut World<S>, EntityId),
+        F: FnOnce(&mut World<S>, CommandStatus, String),
+    {
+        self.push(move |world| world.create_entity(entity_template, success, failure))
+    }
+
+    /// Records a call to `World::delete_entity`.
+    pub fn delete_entity<A: 'static + Send, F: 'static + Send>(
+        &mut self,
+        entity_id: EntityId,
+        success: A,
+        failure: F,
+    ) -> &mut CommandRecorder<'a, S>
+    where
+        A: FnOnce(&mut World<S>, EntityId),
+        F: FnOnce(&mut World<S>, CommandStatus, String),
+    {
+        self.push(move |world| world.delete_entity(entity_id, success, failure))
+    }
+
+    /// Records a call to `World::send_command`.
+    pub fn send_command<C: 'static + Command<S>, A: 'static + Send, F: 'static + Send>(
+        &mut self,
+        command: C,
+        entity_id: EntityId,
+        request: C::Request,
+        success: A,
+        failure: F,
+    ) -> &mut CommandRecorder<'a, S>
+    where
+        C: Send,
+        C::Request: Send,
+        A: FnOnce(&mut World<S>, EntityId, &C::Response),
+        F: FnOnce(&mut World<S>, CommandStatus, String),
+    {
+        self.push(move |world| world.send_command(command, entity_id, request, success, failure))
+    }
+
+    /// Triggers events on component `C` of the given entity. The closure is given
+    /// the component data, and is not called if the entity doesn't have the component
+    /// or this worker is not authoritative over it, in which case the data is not
+    /// marked as changed.
+    ///
+    /// ```
+    /// recorder.send_event::<Health, _>(target_id, |health| {
+    ///     health.got_hit.trigger(Hit { damage: 10 });
+    /// });
+    /// ```
+    pub fn send_event<C: 'static + Component<S>, F: 'static + Send>(
+        &mut self,
+        entity_id: EntityId,
+        trigger: F,
+    ) -> &mut CommandRecorder<'a, S>
+    where
+        F: FnOnce(&mut C::Data),
+    {
+        self.push(move |world| {
+            if let Some(mut data) = world.get_mut_component::<C>(entity_id) {
+                trigger(&mut data);
+            }
+        })
+    }
+
+    /// Records a call to `World::add_local_component`.
+    pub fn add_local_component<C: LocalComponent + Send>(
+        &mut self,
+        entity_id: EntityId,
+        component: C,
+    ) -> &mut CommandRecorder<'a, S> {
+        self.push(move |world| {
+            world.add_local_component(entity_id, component);
+        })
+    }
+
+    /// Records a call to `World::remove_local_component`.
+    pub fn remove_local_component<C: LocalComponent>(
+        &mut self,
+        entity_id: EntityId,
+    ) -> &mut CommandRecorder<'a, S> {
+        self.push(move |world| {
+            world.remove_local_component::<C>(entity_id);
+        })
+    }
+
+    /// Records a call to `World::log`.
+    pub fn log(
+        &mut self,
+        level: LogLevel,
+        logger_name: &str,
+        message: &str,
+    ) -> &mut CommandRecorder<'a, S> {
+        let logger_name = String::from(logger_name);
+        let message = String::from(message);
+        self.push(move |world| world.log(level, &logger_name, &message))
+    }
+
+    fn push<F: 'static + Send>(&mut self, command: F) -> &mut CommandRecorder<'a, S>
+    where
+        F: FnOnce(&mut World<S>),
+    {
+        self.commands
+            .push(SendBoxFnOnce::from(move |world_ptr: *mut World<S>| {
+                let world = unsafe { &mut (*world_ptr) };
+                command(world);
+            }));
+        self
+    }
+}
+
+impl<'a, S: 'static + GeneratedSchema> Drop for CommandRecorder<'a, S> {
+    fn drop(&mut self) {
+        if !self.commands.is_empty() {
+            let commands = mem::replace(&mut self.commands, Vec::new());
+            self.buffer
+                .commands
+                .lock()
+                .unwrap()
+                .push((self.entity_id, self.key, commands));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schema::{add_remote_entity, new_world, ComponentData, Health, Position, Schema};
+    use worker::schema::Component;
+    use {LocalEntityTemplate, Read, WorldTime};
+
+    #[derive(ComponentGroup)]
+    struct Positioned<'a> {
+        id: EntityId,
+        position: Read<'a, Schema, Position>,
+    }
+
+    fn spawn_target(world: &mut World<Schema>) -> EntityId {
+        world.spawn_local(LocalEntityTemplate::new().with_component(Health {
+            current: 1,
+            maximum: 10,
+        }))
+    }
+
+    // Records an event on `target` which adds `value` to `order` when it is played back.
+    fn record_value(
+        buffer: &CommandBuffer<Schema>,
+        target: EntityId,
+        order: &Arc<Mutex<Vec<i64>>>,
+        value: i64,
+    ) {
+        let order = order.clone();
+        buffer.record(target).send_event::<Health, _>(target, move |_health| {
+            order.lock().unwrap().push(value);
+        });
+    }
+
+    #[test]
+    fn recorders_are_played_back_in_order_of_the_recording_entity() {
+        let mut world = new_world();
+        let first = spawn_target(&mut world);
+        let second = spawn_target(&mut world);
+        let buffer = world.command_buffer();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        record_value(&buffer, first, &order, 1);
+        record_value(&buffer, second, &order, 2);
+        record_value(&buffer, first, &order, 3);
+        buffer.play_back(&mut world);
+
+        // Local entity IDs decrease, so `second` is played back first.
+        assert_eq!(*order.lock().unwrap(), vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn recorders_from_par_for_each_are_played_back_in_a_deterministic_order() {
+        let mut world = new_world();
+        world.set_max_entities_per_chunk(8);
+        for entity_id in 1..201 {
+            let position = ComponentData::Position(Position { x: entity_id }.into_data());
+            add_remote_entity(&mut world, entity_id, vec![position], &[]);
+        }
+        let target = spawn_target(&mut world);
+        let buffer = world.command_buffer();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let time = WorldTime::new();
+            let entities = world.entities_since(&time);
+            record_value(&buffer, target, &order, -1);
+            entities.par_for_each::<Positioned, _>(|entity| {
+                record_value(&buffer, target, &order, entity.id * 10);
+                record_value(&buffer, target, &order, entity.id * 10 + 1);
+            });
+            record_value(&buffer, target, &order, 10000);
+            entities.par_for_each::<Positioned, _>(|entity| {
+                record_value(&buffer, target, &order, 20000 + entity.id);
+            });
+        }
+        buffer.play_back(&mut world);
+
+        let mut expected = vec![-1];
+        expected.extend((1..201).flat_map(|entity_id| vec![entity_id * 10, entity_id * 10 + 1]));
+        expected.push(10000);
+        expected.extend((1..201).map(|entity_id| 20000 + entity_id));
+        assert_eq!(*order.lock().unwrap(), expected);
+    }
+}
@@ -1,4 +1,4 @@
-use boxfnonce::BoxFnOnce;
+use boxfnonce::SendBoxFnOnce;
 use entity_template::EntityTemplate;
 use std::any::Any;
 use std::collections::HashMap;
@@ -7,8 +7,9 @@ use worker::schema::{Command, CommandRequestInterface, CommandResponseInterface,
 use worker::{CommandStatus, ComponentId, Connection, EntityId, RequestId};
 use world::World;
 
-type Callback<S, T> = BoxFnOnce<'static, (*mut World<S>, T, CommandStatus, String)>;
-type CommandHandler<S> = Box<FnMut(&mut World<S>, &mut Connection, RequestId, EntityId, Box<Any>)>;
+type Callback<S, T> = SendBoxFnOnce<'static, (*mut World<S>, T, CommandStatus, String)>;
+type CommandHandler<S> =
+    Box<FnMut(&mut World<S>, &mut Connection, RequestId, EntityId, Box<Any>) + Send>;
 
 pub struct Commands<S: GeneratedSchema> {
     entity_command_handlers: HashMap<(ComponentId, u32), CommandHandler<S>>,
@@ -27,7 +28,7 @@ impl<S: 'static + GeneratedSchema> Commands<S> {
         }
     }
 
-    pub fn register_handler<C: 'static + Command<S>, H: 'static>(&mut self, handler: H)
+    pub fn register_handler<C: 'static + Command<S>, H: 'static + Send>(&mut self, handler: H)
     where
         H: Fn(&mut World<S>, EntityId, &C::Request) -> C::Response,
     {
@@ -65,7 +66,7 @@ impl<S: 'static + GeneratedSchema> Commands<S> {
         }
     }
 
-    pub fn send_command<C: 'static + Command<S>, A: 'static, F: 'static>(
+    pub fn send_command<C: 'static + Command<S>, A: 'static + Send, F: 'static + Send>(
         &mut self,
         connection: &mut Connection,
         entity_id: EntityId,
@@ -118,7 +119,7 @@ impl<S: 'static + GeneratedSchema> Commands<S> {
         }
     }
 
-    pub fn create_entity<A: 'static, F: 'static>(
+    pub fn create_entity<A: 'static + Send, F: 'static + Send>(
         &mut self,
         connection: &mut Connection,
         mut entity_template: EntityTemplate,
@@ -160,7 +161,7 @@ impl<S: 'static + GeneratedSchema> Commands<S> {
         }
     }
 
-    pub fn delete_entity<A: 'static, F: 'static>(
+    pub fn delete_entity<A: 'static + Send, F: 'static + Send>(
         &mut self,
         connection: &mut Connection,
         entity_id: EntityId,
@@ -200,12 +201,12 @@ impl<S: 'static + GeneratedSchema> Commands<S> {
         success: A,
         failure: F,
     ) where
-        A: 'static + FnOnce(&mut World<S>, T),
-        F: 'static + FnOnce(&mut World<S>, CommandStatus, String),
+        A: 'static + Send + FnOnce(&mut World<S>, T),
+        F: 'static + Send + FnOnce(&mut World<S>, CommandStatus, String),
     {
         callbacks.insert(
             request_id,
-            BoxFnOnce::from(move |world_ptr: *mut World<S>, object, status, message| {
+            SendBoxFnOnce::from(move |world_ptr: *mut World<S>, object, status, message| {
                 let world = unsafe { &mut (*world_ptr) };
                 if status == CommandStatus::Success {
                     success(world, object);
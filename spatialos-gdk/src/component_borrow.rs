@@ -0,0 +1,103 @@
+use ComponentBitField;
+use FIELD_SIZE_BITS;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicIsize, Ordering};
+use worker::ComponentId;
+use worker::schema::GeneratedSchema;
+
+// Tracks which components are being read or written by `ComponentLookup`s and iterators
+// over `ComponentGroup`s, in the same way as `RefCell`, so that `Entities` can give out
+// several of them at once. The borrow state of each component is the number of borrows
+// reading it, or -1 if it is being written to, indexed by the component's unique index.
+pub struct ComponentBorrows<S: GeneratedSchema> {
+    borrows: Vec<AtomicIsize>,
+    _schema: PhantomData<S>,
+}
+
+// A borrow of some components, which is released when this is dropped.
+pub struct ComponentBorrow<'a> {
+    borrows: &'a [AtomicIsize],
+    read: Vec<usize>,
+    written: Vec<usize>,
+}
+
+impl<'a> Drop for ComponentBorrow<'a> {
+    fn drop(&mut self) {
+        for index in &self.read {
+            self.borrows[*index].fetch_sub(1, Ordering::Release);
+        }
+        for index in &self.written {
+            self.borrows[*index].store(0, Ordering::Release);
+        }
+    }
+}
+
+impl<S: GeneratedSchema> ComponentBorrows<S> {
+    pub fn new() -> ComponentBorrows<S> {
+        let size = S::ComponentBitField::NUMBER_OF_FIELDS * FIELD_SIZE_BITS;
+        ComponentBorrows {
+            borrows: (0..size).map(|_| AtomicIsize::new(0)).collect(),
+            _schema: PhantomData,
+        }
+    }
+
+    // Borrows the components in `written` mutably, and any other components in `read`
+    // immutably. Returns `None` if any of them are already borrowed in a way which conflicts.
+    pub fn borrow(
+        &self,
+        read: &S::ComponentBitField,
+        written: &S::ComponentBitField,
+    ) -> Option<ComponentBorrow> {
+        // Anything borrowed so far is released if this returns early.
+        let mut borrow = ComponentBorrow {
+            borrows: &self.borrows,
+            read: Vec::new(),
+            written: Vec::new(),
+        };
+
+        for index in written.unique_indices() {
+            if self.borrows[index]
+                .compare_exchange(0, -1, Ordering::Acquire, Ordering::Acquire)
+                .is_err()
+            {
+                return None;
+            }
+            borrow.written.push(index);
+        }
+
+        for index in read.unique_indices() {
+            if borrow.written.contains(&index) {
+                continue;
+            }
+            let mut state = self.borrows[index].load(Ordering::Acquire);
+            loop {
+                if state < 0 {
+                    return None;
+                }
+                match self.borrows[index].compare_exchange_weak(
+                    state,
+                    state + 1,
+                    Ordering::Acquire,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => state = current,
+                }
+            }
+            borrow.read.push(index);
+        }
+
+        Some(borrow)
+    }
+
+    pub fn is_borrowed(&self, component_id: ComponentId) -> bool {
+        S::ComponentBitField::get_unique_index(component_id)
+            .map_or(false, |index| self.borrows[index].load(Ordering::Acquire) != 0)
+    }
+
+    pub fn is_any_borrowed(&self) -> bool {
+        self.borrows
+            .iter()
+            .any(|borrow| borrow.load(Ordering::Acquire) != 0)
+    }
+}
@@ -1,8 +1,8 @@
 use chunk::Chunk;
-use std::marker::Sized;
+use std::marker::{PhantomData, Sized};
 use std::ops::{Deref, DerefMut};
 use worker::schema::ComponentDataInterface;
-use worker::schema::{Component, GeneratedSchema};
+use worker::schema::{Component, ComponentEvent, ComponentField, GeneratedSchema};
 use world::WorldTime;
 
 #[doc(hidden)]
@@ -59,6 +59,128 @@ impl<'a, S: GeneratedSchema, C: 'static + Component<S>> DerefMut for Write<'a, S
     }
 }
 
+/// The events of type `E` on component `C` which the current system has not seen yet.
+///
+/// This can be used in a `ComponentGroup` to match only entities which have received
+/// at least one of these events since the system last ran. Each system reads events
+/// independently, so an event is seen exactly once by every system, even if it
+/// arrives after some systems have already run in the current tick.
+///
+/// ```
+/// #[derive(ComponentGroup)]
+/// pub struct HitData<'a> {
+///     pub health: Write<'a, Schema, Health>,
+///     pub hits: Events<'a, Schema, Health, HealthGotHit>,
+/// }
+///
+/// for mut entity in entities.get::<HitData>() {
+///     for hit in entity.hits.iter() {
+///         *entity.health.current -= hit.damage;
+///     }
+/// }
+/// ```
+pub struct Events<'a, S: 'static + GeneratedSchema, C: 'static + Component<S>, E>
+where
+    E: 'static + ComponentEvent<S, Component = C>,
+{
+    events: &'a [E::Value],
+    _schema: PhantomData<S>,
+    _component: PhantomData<C>,
+    _event: PhantomData<E>,
+}
+impl<'a, S: GeneratedSchema, C: 'static + Component<S>, E> Events<'a, S, C, E>
+where
+    E: 'static + ComponentEvent<S, Component = C>,
+{
+    pub fn new(data: &'a <C as Component<S>>::Data, from_time: &WorldTime) -> Events<'a, S, C, E> {
+        Events {
+            events: E::get_event(data).events_after(from_time),
+            _schema: PhantomData,
+            _component: PhantomData,
+            _event: PhantomData,
+        }
+    }
+}
+impl<'a, S: GeneratedSchema, C: 'static + Component<S>, E> Deref for Events<'a, S, C, E>
+where
+    E: 'static + ComponentEvent<S, Component = C>,
+{
+    type Target = [E::Value];
+
+    fn deref(&self) -> &Self::Target {
+        self.events
+    }
+}
+
+/// An immutable reference to component data, which only matches entities where
+/// field `F` of component `C` has changed since the current system last ran.
+///
+/// Unlike `ModifiedRead`, changes to the component's other fields are ignored. A field
+/// counts as changed when it is received from SpatialOS, or when it is edited locally.
+///
+/// ```
+/// #[derive(ComponentGroup)]
+/// pub struct MovedData<'a> {
+///     pub position: ModifiedField<'a, Schema, Position, PositionCoords>,
+/// }
+/// ```
+pub struct ModifiedField<'a, S: 'static + GeneratedSchema, C: 'static + Component<S>, F>
+where
+    F: 'static + ComponentField<S, Component = C>,
+{
+    data: &'a <C as Component<S>>::Data,
+    _field: PhantomData<F>,
+}
+impl<'a, S: GeneratedSchema, C: 'static + Component<S>, F> ModifiedField<'a, S, C, F>
+where
+    F: 'static + ComponentField<S, Component = C>,
+{
+    pub fn new(data: &'a <C as Component<S>>::Data) -> ModifiedField<'a, S, C, F> {
+        ModifiedField {
+            data,
+            _field: PhantomData,
+        }
+    }
+}
+impl<'a, S: GeneratedSchema, C: 'static + Component<S>, F> Deref for ModifiedField<'a, S, C, F>
+where
+    F: 'static + ComponentField<S, Component = C>,
+{
+    type Target = <C as Component<S>>::Data;
+
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+/// A marker which can be used in a `ComponentGroup` to match only entities
+/// that do not contain the given component.
+///
+/// Optional components can be matched by wrapping a `Read` or `Write` in an `Option`.
+/// These will match entities whether or not they contain the component. An optional
+/// `Write` will be `None` if this worker is not authoritative over the component.
+///
+/// ```
+/// #[derive(ComponentGroup)]
+/// pub struct UnarmedData<'a> {
+///     pub position: Read<'a, Schema, Position>,
+///     pub health: Option<Write<'a, Schema, Health>>,
+///     pub no_weapon: Without<Schema, Weapon>,
+/// }
+/// ```
+pub struct Without<S: 'static + GeneratedSchema, C: 'static + Component<S>> {
+    _schema: PhantomData<S>,
+    _component: PhantomData<C>,
+}
+impl<S: GeneratedSchema, C: 'static + Component<S>> Without<S, C> {
+    pub fn new() -> Without<S, C> {
+        Without {
+            _schema: PhantomData,
+            _component: PhantomData,
+        }
+    }
+}
+
 /// Like `Read`, however this will only match entities where the component data
 /// has changed since the last frame.
 pub type ModifiedRead<'a, S, C> = Read<'a, S, C>;
@@ -67,12 +189,26 @@ pub type ModifiedRead<'a, S, C> = Read<'a, S, C>;
 /// has changed since the last frame.
 pub type ModifiedWrite<'a, S, C> = Write<'a, S, C>;
 
+/// Like `Read`, however this will only match entities where this worker is
+/// authoritative over the component.
+pub type ReadAuthoritative<'a, S, C> = Read<'a, S, C>;
+
+/// Like `Read`, however this will only match entities where this worker is not
+/// authoritative over the component, for example to interpolate entities which
+/// are simulated by another worker.
+pub type ReadNotAuthoritative<'a, S, C> = Read<'a, S, C>;
+
 #[doc(hidden)]
 pub trait ComponentGroup<'a, S: GeneratedSchema>
 where
     Self: Sized,
 {
-    fn add_to_bit_field(bit_field: &mut S::ComponentBitField);
+    fn add_to_bit_field(
+        bit_field: &mut S::ComponentBitField,
+        excluded_bit_field: &mut S::ComponentBitField,
+    );
+    fn add_to_written_bit_field(bit_field: &mut S::ComponentBitField);
+    fn add_to_read_bit_field(bit_field: &mut S::ComponentBitField);
     fn get_iterator(
         chunk: &'a mut Chunk<S>,
         from_time: &'a WorldTime,
@@ -81,3 +217,163 @@ where
     where
         F: Fn(&mut Self);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity_template::LocalEntityTemplate;
+    use schema::{add_remote_entity, new_world, ComponentData, Health, Position, Schema};
+    use std::sync::Mutex;
+    use worker::schema::Component;
+    use worker::{Authority, Dispatcher, EntityId};
+
+    #[derive(ComponentGroup)]
+    struct MaybeHealth<'a> {
+        id: EntityId,
+        position: Read<'a, Schema, Position>,
+        health: Option<Read<'a, Schema, Health>>,
+    }
+
+    #[derive(ComponentGroup)]
+    struct NoHealth<'a> {
+        id: EntityId,
+        position: Read<'a, Schema, Position>,
+        no_health: Without<Schema, Health>,
+    }
+
+    #[derive(ComponentGroup)]
+    struct Owned<'a> {
+        id: EntityId,
+        position: ReadAuthoritative<'a, Schema, Position>,
+    }
+
+    #[derive(ComponentGroup)]
+    struct Remote<'a> {
+        id: EntityId,
+        position: ReadNotAuthoritative<'a, Schema, Position>,
+    }
+
+    #[derive(ComponentGroup)]
+    struct Moving<'a> {
+        id: EntityId,
+        position: Write<'a, Schema, Position>,
+        health: Option<Write<'a, Schema, Health>>,
+    }
+
+    fn position(x: i64) -> ComponentData {
+        ComponentData::Position(Position { x }.into_data())
+    }
+
+    fn health(current: i32) -> ComponentData {
+        ComponentData::Health(Health { current, maximum: 10 }.into_data())
+    }
+
+    #[test]
+    fn authority_filters_match_owned_or_remote_entities() {
+        let mut world = new_world();
+        add_remote_entity(&mut world, 1, vec![position(1)], &[Position::component_id()]);
+        add_remote_entity(&mut world, 2, vec![position(2)], &[]);
+        let local = world.spawn_local(
+            LocalEntityTemplate::new().with_component(Position { x: 3 }),
+        );
+
+        let time = WorldTime::new();
+        let entities = world.entities_since(&time);
+        let mut owned: Vec<EntityId> = entities.get::<Owned>().map(|entity| entity.id).collect();
+        let remote: Vec<EntityId> = entities.get::<Remote>().map(|entity| entity.id).collect();
+        owned.sort();
+        assert_eq!(owned, vec![local, 1]);
+        assert_eq!(remote, vec![2]);
+
+        let par_owned = Mutex::new(Vec::new());
+        let par_remote = Mutex::new(Vec::new());
+        entities.par_for_each::<Owned, _>(|entity| par_owned.lock().unwrap().push(entity.id));
+        entities.par_for_each::<Remote, _>(|entity| par_remote.lock().unwrap().push(entity.id));
+        let mut par_owned = par_owned.into_inner().unwrap();
+        par_owned.sort();
+        assert_eq!(par_owned, owned);
+        assert_eq!(par_remote.into_inner().unwrap(), remote);
+    }
+
+    #[test]
+    fn writes_only_match_entities_with_authority() {
+        let mut world = new_world();
+        add_remote_entity(
+            &mut world,
+            1,
+            vec![position(1), health(10)],
+            &[Position::component_id()],
+        );
+        add_remote_entity(&mut world, 2, vec![position(2), health(20)], &[]);
+
+        {
+            let time = WorldTime::new();
+            let entities = world.entities_since(&time);
+            let found: Vec<(EntityId, bool)> = entities
+                .get::<Moving>()
+                .map(|entity| (entity.id, entity.health.is_some()))
+                .collect();
+            assert_eq!(found, vec![(1, false)]);
+        }
+
+        world.on_authority_change(1, Health::component_id(), Authority::AuthorityLossImminent);
+        let time = WorldTime::new();
+        let entities = world.entities_since(&time);
+        let found = Mutex::new(Vec::new());
+        entities.par_for_each::<Moving, _>(|entity| {
+            found.lock().unwrap().push((entity.id, entity.health.is_some()));
+        });
+        assert_eq!(found.into_inner().unwrap(), vec![(1, true)]);
+    }
+
+    #[test]
+    fn optional_fields_match_entities_with_or_without_the_component() {
+        let mut world = new_world();
+        let without = world.spawn_local(
+            LocalEntityTemplate::new().with_component(Position { x: 1 }),
+        );
+        let with = world.spawn_local(
+            LocalEntityTemplate::new()
+                .with_component(Position { x: 2 })
+                .with_component(Health { current: 7, maximum: 10 }),
+        );
+        world.spawn_local(
+            LocalEntityTemplate::new().with_component(Health {
+                current: 8,
+                maximum: 10,
+            }),
+        );
+
+        let time = WorldTime::new();
+        let entities = world.entities_since(&time);
+        let mut found: Vec<(EntityId, i64, Option<i32>)> = entities
+            .get::<MaybeHealth>()
+            .map(|entity| {
+                let health = entity.health.map(|health| *health.current);
+                (entity.id, *entity.position.x, health)
+            })
+            .collect();
+        found.sort();
+
+        assert_eq!(found, vec![(with, 2, Some(7)), (without, 1, None)]);
+    }
+
+    #[test]
+    fn without_fields_exclude_entities_with_the_component() {
+        let mut world = new_world();
+        let without = world.spawn_local(
+            LocalEntityTemplate::new().with_component(Position { x: 1 }),
+        );
+        world.spawn_local(
+            LocalEntityTemplate::new()
+                .with_component(Position { x: 2 })
+                .with_component(Health { current: 7, maximum: 10 }),
+        );
+
+        let time = WorldTime::new();
+        let entities = world.entities_since(&time);
+        let found: Vec<EntityId> = entities.get::<NoHealth>().map(|entity| entity.id).collect();
+
+        assert_eq!(found, vec![without]);
+    }
+}
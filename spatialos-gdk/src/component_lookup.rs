@@ -0,0 +1,203 @@
+use component_borrow::ComponentBorrow;
+use component_group::Read;
+use entity_collection::EntityCollection;
+use std::marker::PhantomData;
+use worker::EntityId;
+use worker::schema::{Component, GeneratedSchema};
+
+/// Read-only random access to component `C` of any entity in the worker's local view.
+///
+/// A `ComponentLookup` is created with `Entities::lookup`, and can be used while iterating
+/// over a `ComponentGroup`, including from within `Entities::par_for_each`. For example,
+/// it can be used to read the `Position` of another entity which is being targeted.
+///
+/// Writing to `C` while a lookup of `C` exists would alias the component data, so
+/// iterating over a `ComponentGroup` which contains a `Write` of `C`, or calling
+/// `World::get_mut_component` for `C`, will panic. The lookup also holds references into
+/// the entities' storage, so adding, removing or moving any entity, such as with
+/// `World::add_local_component` or `World::spawn_local`, will panic while it exists.
+///
+/// ## Example
+///
+/// ```
+/// let positions = entities.lookup::<Position>();
+/// entities.par_for_each::<TargetData, _>(|entity| {
+///     if let Some(target) = positions.get(entity.target.entity_id) {
+///         println!("Target is at {:?}", target.coords);
+///     }
+/// });
+/// ```
+pub struct ComponentLookup<'a, S: 'static + GeneratedSchema, C: 'static + Component<S>> {
+    entities: &'a EntityCollection<S>,
+    _borrow: ComponentBorrow<'a>,
+    _component: PhantomData<C>,
+}
+
+// A lookup only reads component `C` and the location of each entity, and the borrow stops
+// anything from writing to `C` or moving entities while it exists.
+unsafe impl<'a, S: 'static + GeneratedSchema, C: 'static + Component<S>> Sync
+    for ComponentLookup<'a, S, C>
+where
+    C::Data: Sync,
+{
+}
+
+impl<'a, S: 'static + GeneratedSchema, C: 'static + Component<S>> ComponentLookup<'a, S, C> {
+    #[doc(hidden)]
+    pub fn new(
+        entities: &'a EntityCollection<S>,
+        borrow: ComponentBorrow<'a>,
+    ) -> ComponentLookup<'a, S, C> {
+        ComponentLookup {
+            entities,
+            _borrow: borrow,
+            _component: PhantomData,
+        }
+    }
+
+    /// Gets the component data for the given `EntityId`, if it has component `C`.
+    pub fn get(&self, entity_id: EntityId) -> Option<Read<S, C>> {
+        self.entities
+            .get_component_ref::<C>(entity_id)
+            .map(|data| Read::new(data))
+    }
+
+    /// Returns true if the given `EntityId` has component `C`.
+    pub fn contains(&self, entity_id: EntityId) -> bool {
+        self.entities.get_component_ref::<C>(entity_id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use entity_template::LocalEntityTemplate;
+    use schema::{new_world, Health, Position, Schema};
+    use std::sync::Mutex;
+    use worker::EntityId;
+    use world::{World, WorldTime};
+    use {Read, Write};
+
+    #[derive(ComponentGroup)]
+    struct Positioned<'a> {
+        id: EntityId,
+        position: Read<'a, Schema, Position>,
+    }
+
+    #[derive(ComponentGroup)]
+    struct Moving<'a> {
+        position: Write<'a, Schema, Position>,
+    }
+
+    #[derive(ComponentGroup)]
+    struct Hurt<'a> {
+        id: EntityId,
+        health: Write<'a, Schema, Health>,
+    }
+
+    fn spawn(world: &mut World<Schema>, x: i64) -> EntityId {
+        world.spawn_local(
+            LocalEntityTemplate::new()
+                .with_component(Position { x })
+                .with_component(Health { current: 10, maximum: 10 }),
+        )
+    }
+
+    #[test]
+    fn lookups_find_entities_in_any_chunk() {
+        let mut world = new_world();
+        world.set_max_entities_per_chunk(2);
+        let ids: Vec<EntityId> = (0..5).map(|x| spawn(&mut world, x)).collect();
+        let without_position = world.spawn_local(LocalEntityTemplate::new());
+        // Moves the last entity into the removed entity's place.
+        assert!(world.despawn_local(ids[0]));
+
+        let time = WorldTime::new();
+        let entities = world.entities_since(&time);
+        let positions = entities.lookup::<Position>();
+        for (x, entity_id) in ids.iter().enumerate().skip(1) {
+            assert_eq!(*positions.get(*entity_id).unwrap().x, x as i64);
+            assert!(positions.contains(*entity_id));
+        }
+        assert!(positions.get(ids[0]).is_none());
+        assert!(!positions.contains(without_position));
+    }
+
+    #[test]
+    fn lookups_can_be_used_while_iterating() {
+        let mut world = new_world();
+        let first = spawn(&mut world, 1);
+        let second = spawn(&mut world, 2);
+
+        let time = WorldTime::new();
+        let entities = world.entities_since(&time);
+        let positions = entities.lookup::<Position>();
+        for entity in entities.get::<Positioned>() {
+            assert_eq!(*positions.get(entity.id).unwrap().x, *entity.position.x);
+        }
+
+        let seen = Mutex::new(Vec::new());
+        entities.par_for_each::<Hurt, _>(|entity| {
+            let other = if entity.id == first { second } else { first };
+            *entity.health.current = *positions.get(other).unwrap().x as i32;
+            seen.lock().unwrap().push(entity.id);
+        });
+        assert_eq!(seen.into_inner().unwrap().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "while a ComponentLookup or another iterator")]
+    fn writing_to_a_looked_up_component_panics() {
+        let mut world = new_world();
+        spawn(&mut world, 1);
+
+        let time = WorldTime::new();
+        let entities = world.entities_since(&time);
+        let _positions = entities.lookup::<Position>();
+        entities.get::<Moving>();
+    }
+
+    #[test]
+    #[should_panic(expected = "while a ComponentLookup or another iterator")]
+    fn writing_to_a_looked_up_component_in_parallel_panics() {
+        let mut world = new_world();
+        spawn(&mut world, 1);
+
+        let time = WorldTime::new();
+        let entities = world.entities_since(&time);
+        let _positions = entities.lookup::<Position>();
+        entities.par_for_each::<Moving, _>(|_entity| {});
+    }
+
+    #[test]
+    #[should_panic(expected = "while an iterator is writing to it")]
+    fn looking_up_a_component_which_is_being_written_to_panics() {
+        let mut world = new_world();
+        spawn(&mut world, 1);
+
+        let time = WorldTime::new();
+        let entities = world.entities_since(&time);
+        let _moving = entities.get::<Moving>();
+        entities.lookup::<Position>();
+    }
+
+    #[test]
+    fn components_can_be_written_once_the_lookup_is_dropped() {
+        let mut world = new_world();
+        let entity_id = spawn(&mut world, 1);
+
+        {
+            let time = WorldTime::new();
+            let entities = world.entities_since(&time);
+            {
+                let positions = entities.lookup::<Position>();
+                assert!(positions.contains(entity_id));
+            }
+            for mut entity in entities.get::<Moving>() {
+                *entity.position.x = 5;
+            }
+        }
+
+        *world.get_mut_component::<Position>(entity_id).unwrap().x += 1;
+        assert_eq!(*world.get_component::<Position>(entity_id).unwrap().x, 6);
+    }
+}
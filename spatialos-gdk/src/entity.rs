@@ -1,5 +1,16 @@
+use worker::EntityId;
 use worker::schema::GeneratedSchema;
 
+// SpatialOS only gives out positive entity IDs, so local entities count down from -1.
+pub const FIRST_LOCAL_ENTITY_ID: EntityId = -1;
+
+/// Returns true if the given `EntityId` belongs to a local entity created with
+/// `World::spawn_local`, rather than a SpatialOS entity.
+pub fn is_local_entity(entity_id: EntityId) -> bool {
+    entity_id < 0
+}
+
+// The location of an entity's component data within the `EntityCollection`.
 #[derive(Debug, Default)]
 pub struct Entity<S: GeneratedSchema> {
     pub bit_field: S::ComponentBitField,
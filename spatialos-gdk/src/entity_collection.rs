@@ -1,12 +1,20 @@
 use ComponentBitField;
-use chunk::Chunk;
+use command_buffer;
+use component_borrow::{ComponentBorrow, ComponentBorrows};
+use chunk::{Chunk, ComponentStorageInterface, StorageConstructor,
+            DEFAULT_MAX_ENTITIES_PER_CHUNK};
 use component_group::ComponentGroup;
+use component_lookup::ComponentLookup;
 use component_group::UnsafeSendablePointer;
 use entity::Entity;
 use rayon::prelude::*;
+use std::any::type_name;
+use std::cmp::Reverse;
 use std::collections::HashMap;
-use worker::Connection;
-use worker::schema::GeneratedSchema;
+use std::marker::PhantomData;
+use std::mem;
+use worker::{ComponentId, Connection, EntityId};
+use worker::schema::{Component, GeneratedSchema};
 use world::PartialEntity;
 use world::WorldTime;
 
@@ -17,10 +25,22 @@ use world::WorldTime;
 ///
 /// This iteration can be done sequentially or in parallel.
 pub struct Entities<'a, S: 'a + GeneratedSchema> {
-    entities: &'a mut EntityCollection<S>,
+    // `get`, `par_for_each` and `lookup` only need `&self`, so that lookups can be used while
+    // iterating. Which components each of them reads and writes is checked at runtime by
+    // `ComponentBorrows` instead.
+    entities: *mut EntityCollection<S>,
+    _entities: PhantomData<&'a mut EntityCollection<S>>,
     from_time: WorldTime,
 }
 
+// `Entities` acts like the `&mut EntityCollection` it was created from. It isn't `Sync`, as
+// `get` and `par_for_each` can write to the entities through `&self`.
+unsafe impl<'a, S: 'a + GeneratedSchema> Send for Entities<'a, S>
+where
+    EntityCollection<S>: Send,
+{
+}
+
 impl<'a, S: 'static + GeneratedSchema> Entities<'a, S> {
     #[doc(hidden)]
     pub fn entities_from_time(
@@ -29,6 +49,7 @@ impl<'a, S: 'static + GeneratedSchema> Entities<'a, S> {
     ) -> Entities<'a, S> {
         Entities {
             entities,
+            _entities: PhantomData,
             from_time: from_time.clone(),
         }
     }
@@ -36,6 +57,9 @@ impl<'a, S: 'static + GeneratedSchema> Entities<'a, S> {
     /// Gets an iterator over all entities in the worker's local view which
     /// match the `ComponentGroup` `G`.
     ///
+    /// Panics if `G` writes to a component which is being read by a `ComponentLookup` or
+    /// another iterator, or reads a component which another iterator is writing to.
+    ///
     /// ## Example
     ///
     /// ```
@@ -47,16 +71,22 @@ impl<'a, S: 'static + GeneratedSchema> Entities<'a, S> {
     ///         entity.position.coords.x);
     /// }
     /// ```
-    pub fn get<'b, G: 'b + ComponentGroup<'b, S>>(&'b mut self) -> Box<Iterator<Item = G> + 'b> {
+    pub fn get<'b, G: 'b + ComponentGroup<'b, S>>(&'b self) -> Box<Iterator<Item = G> + 'b> {
+        let borrow = self.collection().borrow_group::<G>();
+
         let mut group_bit_field = S::ComponentBitField::new();
-        G::add_to_bit_field(&mut group_bit_field);
+        let mut excluded_bit_field = S::ComponentBitField::new();
+        G::add_to_bit_field(&mut group_bit_field, &mut excluded_bit_field);
         let from_time = &self.from_time;
 
-        Box::new(
-            self.entities
-                .get_chunks_with_components(group_bit_field)
+        // The borrow guarantees that nothing else reads the components which `G` writes.
+        let entities = unsafe { &mut *self.entities };
+        Box::new(BorrowedIterator {
+            iterator: entities
+                .get_chunks_with_components(group_bit_field, excluded_bit_field)
                 .flat_map(move |chunk| G::get_iterator(chunk, from_time)),
-        )
+            _borrow: borrow,
+        })
     }
 
     /// Executes the given closure for each entity in the worker's local view
@@ -66,6 +96,8 @@ impl<'a, S: 'static + GeneratedSchema> Entities<'a, S> {
     /// to execute them in series, that will be done. Please see the [rayon](https://docs.rs/rayon/1.0.2/rayon)
     /// documentation for more details.
     ///
+    /// Panics in the same cases as `get`.
+    ///
     /// ## Example
     ///
     /// ```
@@ -73,24 +105,69 @@ impl<'a, S: 'static + GeneratedSchema> Entities<'a, S> {
     ///     entity.position.coords.x = rand::thread_rng().gen::<f64>();
     /// });
     /// ```
-    pub fn par_for_each<'b, G: 'b + ComponentGroup<'b, S>, F: Send + Sync>(&'b mut self, cb: F)
+    pub fn par_for_each<'b, G: 'b + ComponentGroup<'b, S>, F: Send + Sync>(&'b self, cb: F)
     where
         F: Fn(&mut G),
     {
+        let _borrow = self.collection().borrow_group::<G>();
+
         let mut group_bit_field = S::ComponentBitField::new();
-        G::add_to_bit_field(&mut group_bit_field);
+        let mut excluded_bit_field = S::ComponentBitField::new();
+        G::add_to_bit_field(&mut group_bit_field, &mut excluded_bit_field);
         let from_time = &self.from_time;
 
-        self.entities
-            .par_for_each_chunks_with_components(group_bit_field, |chunk| {
+        // The borrow guarantees that nothing else reads the components which `G` writes.
+        let entities = unsafe { &mut *self.entities };
+        entities.par_for_each_chunks_with_components(
+            group_bit_field,
+            excluded_bit_field,
+            |chunk| {
                 G::par_for_each(chunk, from_time, &cb);
-            })
+            },
+        )
+    }
+
+    /// Creates a `ComponentLookup` which can be used to read component `C` of any
+    /// entity, including while iterating over entities.
+    ///
+    /// Panics if an iterator is writing to `C`.
+    pub fn lookup<'b, C: 'static + Component<S>>(&'b self) -> ComponentLookup<'b, S, C> {
+        let entities = self.collection();
+        ComponentLookup::new(entities, entities.borrow_component(C::component_id()))
+    }
+
+    fn collection(&self) -> &EntityCollection<S> {
+        unsafe { &*self.entities }
+    }
+}
+
+// Keeps the components of an iteration borrowed until the iterator is dropped.
+struct BorrowedIterator<'a, I> {
+    iterator: I,
+    _borrow: ComponentBorrow<'a>,
+}
+
+impl<'a, I: Iterator> Iterator for BorrowedIterator<'a, I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        self.iterator.next()
     }
 }
 
 pub struct EntityCollection<S: GeneratedSchema> {
     chunks: Vec<Chunk<S>>,
     map: HashMap<S::ComponentBitField, Vec<usize>>,
+    // Every bit field in `map`, in the order that they were added. Queries iterate over
+    // archetypes in this order, so that the iteration order is reproducible.
+    archetypes: Vec<S::ComponentBitField>,
+    // The indices into `archetypes` which match each pair of required and excluded
+    // components. This only needs to be cleared when a new archetype is added.
+    query_cache: HashMap<(S::ComponentBitField, S::ComponentBitField), Vec<usize>>,
+    // The location of every entity in `chunks`.
+    locations: HashMap<EntityId, Entity<S>>,
+    borrows: ComponentBorrows<S>,
+    max_entities_per_chunk: usize,
 }
 
 impl<S: 'static + GeneratedSchema> EntityCollection<S> {
@@ -98,84 +175,405 @@ impl<S: 'static + GeneratedSchema> EntityCollection<S> {
         EntityCollection {
             chunks: Vec::new(),
             map: HashMap::new(),
+            archetypes: Vec::new(),
+            query_cache: HashMap::new(),
+            locations: HashMap::new(),
+            borrows: ComponentBorrows::new(),
+            max_entities_per_chunk: DEFAULT_MAX_ENTITIES_PER_CHUNK,
         }
     }
 
+    // Only affects chunks which are created after this is called.
+    pub fn set_max_entities_per_chunk(&mut self, max_entities_per_chunk: usize) {
+        self.max_entities_per_chunk = max_entities_per_chunk;
+    }
+
     pub fn replicate(&mut self, connection: &mut Connection) {
         for chunk in self.chunks.iter_mut() {
             chunk.replicate(connection);
         }
     }
 
-    pub fn cleanup_after_frame(&mut self) {
+    pub fn cleanup_after_frame(&mut self, oldest_cursor: &WorldTime) {
         for chunk in self.chunks.iter_mut() {
-            chunk.cleanup_after_frame();
+            chunk.cleanup_after_frame(oldest_cursor);
         }
     }
 
-    pub fn get_chunk_for_entity(&mut self, entity: &Entity<S>) -> &mut Chunk<S> {
-        &mut self.chunks[entity.chunk_index]
+    // Advances `last_updated` for components which have been changed by this worker,
+    // so that they are matched by `ModifiedRead` and `ModifiedWrite`.
+    pub fn update_locally_changed(&mut self, world_time: &mut WorldTime) {
+        let time = world_time.get_time();
+        for chunk in self.chunks.iter_mut() {
+            chunk.update_locally_changed(&time);
+        }
+    }
+
+    pub fn loop_back_events(&mut self, world_time: &mut WorldTime) {
+        let time = world_time.get_time();
+        for chunk in self.chunks.iter_mut() {
+            chunk.loop_back_events(&time);
+        }
     }
 
-    pub fn get_free_chunk(
+    pub fn get_entity(&self, entity_id: EntityId) -> Option<&Entity<S>> {
+        self.locations.get(&entity_id)
+    }
+
+    // Gets the chunk containing the given entity, and the entity's index in that chunk.
+    pub fn get_chunk_for_entity(
         &mut self,
-        bit_field: &S::ComponentBitField,
-        world_time: &mut WorldTime,
-        template_entity: &PartialEntity<S>,
-    ) -> &mut Chunk<S> {
-        if let Some(indices) = self.map.get_mut(bit_field) {
+        entity_id: EntityId,
+    ) -> Option<(&mut Chunk<S>, usize)> {
+        match self.locations.get(&entity_id) {
+            Some(entity) => Some((&mut self.chunks[entity.chunk_index], entity.index_in_chunk)),
+            None => None,
+        }
+    }
+
+    pub fn add_entity(&mut self, world_time: &mut WorldTime, entity: PartialEntity<S>) {
+        self.check_not_any_borrowed("add entities");
+
+        let chunk_index = match self.find_free_chunk_index(&entity.bit_field) {
+            Some(index) => index,
+            // No space
+            None => self.add_chunk(&entity.bit_field, world_time, &entity),
+        };
+
+        let entity_id = entity.entity_id;
+        let bit_field = entity.bit_field;
+        let index_in_chunk = self.chunks[chunk_index].add_entity(world_time, entity);
+        self.locations.insert(
+            entity_id,
+            Entity::new(chunk_index, index_in_chunk, bit_field),
+        );
+    }
+
+    // Adds an entity without any component data, returning its chunk and index so that
+    // the data can be inserted. `create_storages` is used if a new chunk is needed.
+    pub fn push_entity_with_storages<F>(
+        &mut self,
+        entity_id: EntityId,
+        bit_field: S::ComponentBitField,
+        create_storages: F,
+    ) -> (&mut Chunk<S>, usize)
+    where
+        F: FnOnce() -> HashMap<ComponentId, Box<ComponentStorageInterface<S>>>,
+    {
+        self.check_not_any_borrowed("add entities");
+
+        let chunk_index = match self.find_free_chunk_index(&bit_field) {
+            Some(index) => index,
+            None => self.add_chunk_with_storages(&bit_field, create_storages()),
+        };
+
+        let index_in_chunk = self.chunks[chunk_index].push_entity(entity_id);
+        self.locations.insert(
+            entity_id,
+            Entity::new(chunk_index, index_in_chunk, bit_field),
+        );
+        (&mut self.chunks[chunk_index], index_in_chunk)
+    }
+
+    // Returns false if the entity does not exist.
+    pub fn remove_entity(&mut self, entity_id: EntityId) -> bool {
+        self.check_not_any_borrowed("remove entities");
+
+        let entity = match self.locations.remove(&entity_id) {
+            Some(entity) => entity,
+            None => return false,
+        };
+
+        let chunk = &mut self.chunks[entity.chunk_index];
+        if let Some(moved) = chunk.remove_entity_at(entity.index_in_chunk) {
+            self.locations.get_mut(&moved).unwrap().index_in_chunk = entity.index_in_chunk;
+        }
+        true
+    }
+
+    fn find_free_chunk_index(&self, bit_field: &S::ComponentBitField) -> Option<usize> {
+        if let Some(indices) = self.map.get(bit_field) {
             for index in indices {
-                if (&self.chunks[*index]).has_space() {
-                    return &mut self.chunks[*index];
+                if self.chunks[*index].has_space() {
+                    return Some(*index);
                 }
             }
         }
 
-        // No space
-        self.add_chunk(bit_field, world_time, template_entity)
+        None
+    }
+
+    /// Moves an existing entity into a chunk with the components given in `bit_field`.
+    ///
+    /// The data of each component which is in both the old and the new chunk is moved
+    /// across. If a new chunk needs to be created, `new_storage` is used to create the
+    /// storage for a component which is not in the entity's current chunk.
+    pub fn move_entity(
+        &mut self,
+        entity_id: EntityId,
+        bit_field: S::ComponentBitField,
+        world_time: &mut WorldTime,
+        new_storage: Option<(ComponentId, StorageConstructor<S>)>,
+    ) {
+        self.check_not_any_borrowed("add or remove components");
+
+        let (from_chunk_index, from_index) = {
+            let entity = &self.locations[&entity_id];
+            (entity.chunk_index, entity.index_in_chunk)
+        };
+
+        let to_chunk_index = match self.find_free_chunk_index(&bit_field) {
+            Some(index) => index,
+            None => {
+                let mut storages =
+                    self.chunks[from_chunk_index].create_empty_storages(&bit_field, world_time);
+                if let Some((component_id, constructor)) = new_storage {
+                    storages.insert(component_id, constructor(world_time));
+                }
+                self.add_chunk_with_storages(&bit_field, storages)
+            }
+        };
+
+        self.transfer_entity(from_chunk_index, from_index, to_chunk_index);
+
+        self.locations.get_mut(&entity_id).unwrap().bit_field = bit_field;
+    }
+
+    // Moves an entity and the data of every component which both chunks share into
+    // the end of another chunk.
+    fn transfer_entity(
+        &mut self,
+        from_chunk_index: usize,
+        from_index: usize,
+        to_chunk_index: usize,
+    ) {
+        let (entity_id, to_index, moved) = {
+            let (from_chunk, to_chunk) = self.get_chunk_pair(from_chunk_index, to_chunk_index);
+            let entity_id = from_chunk.get_entity_id(from_index);
+            let to_index = to_chunk.push_entity(entity_id);
+            from_chunk.move_entity_data_to(from_index, to_chunk, to_index);
+            (entity_id, to_index, from_chunk.remove_entity_at(from_index))
+        };
+
+        {
+            let entity = self.locations.get_mut(&entity_id).unwrap();
+            entity.chunk_index = to_chunk_index;
+            entity.index_in_chunk = to_index;
+        }
+        if let Some(moved) = moved {
+            self.locations.get_mut(&moved).unwrap().index_in_chunk = from_index;
+        }
+    }
+
+    /// Merges sparsely filled chunks which have the same components, and then frees
+    /// any chunks which are left empty.
+    ///
+    /// This changes the chunk of many entities, so it must not be called while iterating.
+    pub fn compact(&mut self) {
+        self.check_not_any_borrowed("compact entities");
+
+        let bit_fields = self.archetypes.clone();
+        for bit_field in bit_fields.iter() {
+            self.merge_chunks(bit_field);
+        }
+
+        self.remove_empty_chunks();
+    }
+
+    // Moves entities out of the emptiest chunks into the fullest chunks which have space.
+    fn merge_chunks(&mut self, bit_field: &S::ComponentBitField) {
+        let mut indices = self.map[bit_field].clone();
+        indices.sort_by_key(|index| Reverse(self.chunks[*index].num_entities()));
+
+        if indices.len() < 2 {
+            return;
+        }
+
+        let mut to = 0;
+        let mut from = indices.len() - 1;
+        while to < from {
+            if !self.chunks[indices[to]].has_space() {
+                to = to + 1;
+            } else if self.chunks[indices[from]].num_entities() == 0 {
+                from = from - 1;
+            } else {
+                let from_index = self.chunks[indices[from]].num_entities() - 1;
+                self.transfer_entity(indices[from], from_index, indices[to]);
+            }
+        }
+    }
+
+    fn remove_empty_chunks(&mut self) {
+        let chunks = mem::replace(&mut self.chunks, Vec::new());
+
+        // The new index of each chunk, or `None` if it was removed.
+        let mut new_indices = Vec::with_capacity(chunks.len());
+        for mut chunk in chunks.into_iter() {
+            if chunk.num_entities() > 0 {
+                let index = self.chunks.len();
+                for entity_index in chunk.entity_index_iter() {
+                    let entity_id = chunk.get_entity_id(entity_index);
+                    self.locations.get_mut(&entity_id).unwrap().chunk_index = index;
+                }
+                chunk.shrink_to_fit();
+                self.chunks.push(chunk);
+                new_indices.push(Some(index));
+            } else {
+                new_indices.push(None);
+            }
+        }
+
+        // Archetypes are kept even if all of their chunks were removed, so that the
+        // query cache stays valid.
+        for indices in self.map.values_mut() {
+            *indices = indices
+                .iter()
+                .filter_map(|index| new_indices[*index])
+                .collect();
+        }
+    }
+
+    fn get_chunk_pair(&mut self, first: usize, second: usize) -> (&mut Chunk<S>, &mut Chunk<S>) {
+        if first < second {
+            let (left, right) = self.chunks.split_at_mut(second);
+            (&mut left[first], &mut right[0])
+        } else {
+            let (left, right) = self.chunks.split_at_mut(first);
+            (&mut right[0], &mut left[second])
+        }
+    }
+
+    // Gets the indices of every chunk which contains all of the components in
+    // `component_bit_field` and none of the components in `excluded_bit_field`.
+    fn query_chunk_indices(
+        &mut self,
+        component_bit_field: S::ComponentBitField,
+        excluded_bit_field: S::ComponentBitField,
+    ) -> Vec<usize> {
+        let archetypes = &self.archetypes;
+        let archetype_indices = self.query_cache
+            .entry((component_bit_field, excluded_bit_field))
+            .or_insert_with(|| {
+                archetypes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, bit_field)| {
+                        bit_field.is_subset(&component_bit_field)
+                            && bit_field.is_disjoint(&excluded_bit_field)
+                    })
+                    .map(|(index, _)| index)
+                    .collect()
+            });
+
+        let map = &self.map;
+        archetype_indices
+            .iter()
+            .flat_map(|index| map[&archetypes[*index]].iter().cloned())
+            .collect()
     }
 
     pub fn get_chunks_with_components<'a>(
         &'a mut self,
         component_bit_field: S::ComponentBitField,
+        excluded_bit_field: S::ComponentBitField,
     ) -> Box<Iterator<Item = &mut Chunk<S>> + 'a> {
+        let chunk_indices = self.query_chunk_indices(component_bit_field, excluded_bit_field);
         let chunks: *mut Vec<Chunk<S>> = &mut self.chunks;
 
         Box::new(
-            self.map
-                .iter_mut()
-                .filter(move |(bit_field, _)| bit_field.is_subset(&component_bit_field))
-                .flat_map(move |(_, chunk_indices)| {
-                    chunk_indices
-                        .iter()
-                        .map(move |index| unsafe { &mut (*chunks)[*index] })
-                }),
+            chunk_indices
+                .into_iter()
+                .map(move |index| unsafe { &mut (*chunks)[index] }),
         )
     }
 
+    // Borrows the components which `G` reads and writes, panicking if any of them are
+    // already borrowed in a way which conflicts.
+    pub fn borrow_group<'b, G: ComponentGroup<'b, S>>(&self) -> ComponentBorrow {
+        let mut read_bit_field = S::ComponentBitField::new();
+        let mut written_bit_field = S::ComponentBitField::new();
+        G::add_to_read_bit_field(&mut read_bit_field);
+        G::add_to_written_bit_field(&mut written_bit_field);
+
+        match self.borrows.borrow(&read_bit_field, &written_bit_field) {
+            Some(borrow) => borrow,
+            None => panic!(
+                "Cannot iterate over {} while a ComponentLookup or another iterator is using \
+                 the same components.",
+                type_name::<G>()
+            ),
+        }
+    }
+
+    // Borrows the given component to be read by a `ComponentLookup`, panicking if an
+    // iterator is writing to it.
+    pub fn borrow_component(&self, component_id: ComponentId) -> ComponentBorrow {
+        let mut read_bit_field = S::ComponentBitField::new();
+        read_bit_field.add_component(component_id);
+
+        match self.borrows.borrow(&read_bit_field, &S::ComponentBitField::new()) {
+            Some(borrow) => borrow,
+            None => panic!(
+                "Cannot look up component {} while an iterator is writing to it.",
+                component_id
+            ),
+        }
+    }
+
+    // Panics if a `ComponentLookup` or an iterator is currently using the given component.
+    pub fn check_not_borrowed(&self, component_id: ComponentId) {
+        if self.borrows.is_borrowed(component_id) {
+            panic!(
+                "Cannot write to component {} while a ComponentLookup or an iterator is using it.",
+                component_id
+            );
+        }
+    }
+
+    // Panics if any `ComponentLookup` or iterator exists. They hold references into the
+    // chunks, so nothing may add, remove or move an entity while one exists.
+    pub fn check_not_any_borrowed(&self, action: &str) {
+        if self.borrows.is_any_borrowed() {
+            panic!(
+                "Cannot {} while a ComponentLookup or an iterator over entities exists.",
+                action
+            );
+        }
+    }
+
+    // Gets component `C` of the given entity, if it has it.
+    pub fn get_component_ref<C: 'static + Component<S>>(
+        &self,
+        entity_id: EntityId,
+    ) -> Option<&C::Data> {
+        let entity = self.locations.get(&entity_id)?;
+        self.chunks[entity.chunk_index]
+            .get_component_storage_ref::<C>()
+            .map(|storage| storage.get_component_data(entity.index_in_chunk))
+    }
+
     pub fn par_for_each_chunks_with_components<'b, F: Send + Sync>(
         &'b mut self,
         component_bit_field: S::ComponentBitField,
+        excluded_bit_field: S::ComponentBitField,
         cb: F,
     ) where
         F: Fn(&'b mut Chunk<S>),
     {
+        let chunk_indices = self.query_chunk_indices(component_bit_field, excluded_bit_field);
         let chunks =
             UnsafeSendablePointer::<Vec<Chunk<S>>>((&mut self.chunks) as *mut Vec<Chunk<S>>);
+        let pass = command_buffer::next_pass();
 
-        self.map
-            .par_iter_mut()
-            .filter(|(bit_field, _)| bit_field.is_subset(&component_bit_field))
-            .flat_map(|(_, chunk_indices)| {
-                chunk_indices.par_iter().map(|index| unsafe {
-                    let chunks_ref = &mut *(chunks.0);
-                    let chunk = &mut chunks_ref[*index];
-                    UnsafeSendablePointer::<Chunk<S>>(chunk)
-                })
+        chunk_indices
+            .par_iter()
+            .map(|index| unsafe {
+                let chunks_ref = &mut *(chunks.0);
+                let chunk = &mut chunks_ref[*index];
+                UnsafeSendablePointer::<Chunk<S>>(chunk)
             })
             .for_each(|chunk_ptr| unsafe {
-                let chunk = &mut (*chunk_ptr.0);
-                cb(chunk);
+                command_buffer::with_position(pass, None, || cb(&mut *chunk_ptr.0));
             });
     }
 
@@ -184,11 +582,236 @@ impl<S: 'static + GeneratedSchema> EntityCollection<S> {
         bit_field: &S::ComponentBitField,
         world_time: &mut WorldTime,
         template_entity: &PartialEntity<S>,
-    ) -> &mut Chunk<S> {
+    ) -> usize {
         let index = self.chunks.len();
-        let chunk = Chunk::new(index, world_time, template_entity);
+        let chunk = Chunk::new(self.max_entities_per_chunk, world_time, template_entity);
         self.chunks.push(chunk);
+        self.add_chunk_to_archetype(bit_field, index);
+        index
+    }
+
+    fn add_chunk_with_storages(
+        &mut self,
+        bit_field: &S::ComponentBitField,
+        storages: HashMap<ComponentId, Box<ComponentStorageInterface<S>>>,
+    ) -> usize {
+        let index = self.chunks.len();
+        self.chunks
+            .push(Chunk::from_storages(self.max_entities_per_chunk, storages));
+        self.add_chunk_to_archetype(bit_field, index);
+        index
+    }
+
+    fn add_chunk_to_archetype(&mut self, bit_field: &S::ComponentBitField, index: usize) {
+        if !self.map.contains_key(bit_field) {
+            self.archetypes.push(*bit_field);
+            self.query_cache.clear();
+        }
         self.map.entry(*bit_field).or_insert(Vec::new()).push(index);
-        &mut self.chunks[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chunk::ComponentStorage;
+    use schema::{partial_entity, ComponentData, Health, Position, Schema};
+
+    fn position(x: i64) -> ComponentData {
+        ComponentData::Position(Position { x }.into_data())
+    }
+
+    fn health(current: i32) -> ComponentData {
+        ComponentData::Health(
+            Health {
+                current,
+                maximum: 10,
+            }.into_data(),
+        )
+    }
+
+    fn add(entities: &mut EntityCollection<Schema>, entity_id: EntityId, data: Vec<ComponentData>) {
+        entities.add_entity(&mut WorldTime::new(), partial_entity(entity_id, data));
+    }
+
+    fn chunk_sizes(entities: &EntityCollection<Schema>) -> Vec<usize> {
+        entities.chunks.iter().map(|chunk| chunk.num_entities()).collect()
+    }
+
+    // Checks that every entity's location points at that entity, and that every chunk
+    // belongs to exactly one archetype.
+    fn check_locations(entities: &EntityCollection<Schema>) {
+        for (entity_id, entity) in entities.locations.iter() {
+            let chunk = &entities.chunks[entity.chunk_index];
+            assert_eq!(chunk.get_entity_id(entity.index_in_chunk), *entity_id);
+        }
+        let num_entities: usize = chunk_sizes(entities).iter().sum();
+        assert_eq!(num_entities, entities.locations.len());
+
+        let mut indices: Vec<usize> = entities.map.values().flat_map(|v| v.clone()).collect();
+        indices.sort();
+        assert_eq!(indices, (0..entities.chunks.len()).collect::<Vec<usize>>());
+    }
+
+    fn bit_field(component_ids: &[ComponentId]) -> <Schema as GeneratedSchema>::ComponentBitField {
+        let mut bit_field = <Schema as GeneratedSchema>::ComponentBitField::new();
+        for component_id in component_ids {
+            bit_field.add_component(*component_id);
+        }
+        bit_field
+    }
+
+    #[test]
+    fn the_query_cache_is_invalidated_when_an_archetype_is_added() {
+        let mut entities = EntityCollection::<Schema>::new();
+        let with_position = bit_field(&[Position::component_id()]);
+        let none = bit_field(&[]);
+        add(&mut entities, 1, vec![position(1)]);
+
+        assert_eq!(entities.query_chunk_indices(with_position, none), vec![0]);
+        assert_eq!(entities.query_cache.len(), 1);
+
+        add(&mut entities, 2, vec![position(2), health(5)]);
+        assert!(entities.query_cache.is_empty());
+        assert_eq!(entities.query_chunk_indices(with_position, none), vec![0, 1]);
+    }
+
+    #[test]
+    fn the_query_cache_is_kept_when_chunks_are_added_to_existing_archetypes() {
+        let mut entities = EntityCollection::<Schema>::new();
+        entities.set_max_entities_per_chunk(1);
+        let with_position = bit_field(&[Position::component_id()]);
+        let none = bit_field(&[]);
+        add(&mut entities, 1, vec![position(1)]);
+
+        assert_eq!(entities.query_chunk_indices(with_position, none), vec![0]);
+
+        add(&mut entities, 2, vec![position(2)]);
+        assert_eq!(entities.chunks.len(), 2);
+        assert_eq!(entities.query_cache.len(), 1);
+        assert_eq!(entities.query_chunk_indices(with_position, none), vec![0, 1]);
+    }
+
+    #[test]
+    fn queries_return_chunks_in_the_order_that_their_archetypes_were_added() {
+        let mut entities = EntityCollection::<Schema>::new();
+        entities.set_max_entities_per_chunk(1);
+        add(&mut entities, 1, vec![position(1), health(1)]);
+        add(&mut entities, 2, vec![position(2)]);
+        add(&mut entities, 3, vec![position(3), health(3)]);
+
+        let with_position = bit_field(&[Position::component_id()]);
+        let with_health = bit_field(&[Health::component_id()]);
+        let none = bit_field(&[]);
+        for _ in 0..3 {
+            assert_eq!(entities.query_chunk_indices(with_position, none), vec![0, 2, 1]);
+        }
+        assert_eq!(entities.query_chunk_indices(with_health, none), vec![0, 2]);
+        assert_eq!(entities.query_chunk_indices(with_position, with_health), vec![1]);
+    }
+
+    #[test]
+    fn removing_an_entity_updates_the_location_of_the_entity_moved_into_its_place() {
+        let mut entities = EntityCollection::<Schema>::new();
+        for entity_id in 1..4 {
+            add(&mut entities, entity_id, vec![position(entity_id)]);
+        }
+
+        assert!(entities.remove_entity(1));
+        assert!(!entities.remove_entity(1));
+
+        assert!(entities.get_entity(1).is_none());
+        assert_eq!(entities.get_entity(3).unwrap().index_in_chunk, 0);
+        assert_eq!(*entities.get_component_ref::<Position>(3).unwrap().x, 3);
+        check_locations(&entities);
+    }
+
+    #[test]
+    fn moving_an_entity_to_another_chunk_updates_every_location() {
+        let mut entities = EntityCollection::<Schema>::new();
+        for entity_id in 1..4 {
+            add(&mut entities, entity_id, vec![position(entity_id)]);
+        }
+        let mut world_time = WorldTime::new();
+
+        let mut bit_field = entities.get_entity(1).unwrap().bit_field;
+        bit_field.add_component(Health::component_id());
+        let storage: StorageConstructor<Schema> = ComponentStorage::<Schema, Health>::new_boxed;
+        let new_storage = Some((Health::component_id(), storage));
+        entities.move_entity(1, bit_field, &mut world_time, new_storage);
+
+        let entity = entities.get_entity(1).unwrap();
+        assert_eq!((entity.chunk_index, entity.index_in_chunk), (1, 0));
+        assert!(entity.bit_field.has_component(Health::component_id()));
+        assert_eq!(*entities.get_component_ref::<Position>(1).unwrap().x, 1);
+        assert!(entities.get_component_ref::<Health>(1).is_some());
+        assert_eq!(entities.get_entity(3).unwrap().index_in_chunk, 0);
+        check_locations(&entities);
+    }
+
+    #[test]
+    fn compacting_merges_partly_full_chunks_and_frees_empty_ones() {
+        let mut entities = EntityCollection::<Schema>::new();
+        entities.set_max_entities_per_chunk(4);
+        for entity_id in 1..13 {
+            add(&mut entities, entity_id, vec![position(entity_id)]);
+        }
+        add(&mut entities, 13, vec![position(13), health(1)]);
+        add(&mut entities, 14, vec![position(14), health(1)]);
+        for entity_id in [1, 2, 5, 6, 7, 9, 10, 11, 12].iter() {
+            assert!(entities.remove_entity(*entity_id));
+        }
+        assert_eq!(chunk_sizes(&entities), vec![2, 1, 0, 2]);
+
+        entities.compact();
+
+        assert_eq!(chunk_sizes(&entities), vec![3, 2]);
+        check_locations(&entities);
+        for entity_id in [3, 4, 8, 13, 14].iter() {
+            let position = entities.get_component_ref::<Position>(*entity_id).unwrap();
+            assert_eq!(*position.x, *entity_id);
+        }
+        assert!(entities.get_component_ref::<Position>(1).is_none());
+
+        // Entities are added to the merged chunk while it has space.
+        add(&mut entities, 15, vec![position(15)]);
+        add(&mut entities, 16, vec![position(16)]);
+        assert_eq!(chunk_sizes(&entities), vec![4, 2, 1]);
+        check_locations(&entities);
+    }
+
+    #[test]
+    fn compacting_keeps_archetypes_whose_chunks_were_all_freed() {
+        let mut entities = EntityCollection::<Schema>::new();
+        add(&mut entities, 1, vec![position(1)]);
+        add(&mut entities, 2, vec![health(2)]);
+        assert!(entities.remove_entity(1));
+
+        entities.compact();
+        assert_eq!(chunk_sizes(&entities), vec![1]);
+        check_locations(&entities);
+
+        add(&mut entities, 3, vec![position(3)]);
+        assert_eq!(chunk_sizes(&entities), vec![1, 1]);
+        check_locations(&entities);
+        assert_eq!(*entities.get_component_ref::<Position>(3).unwrap().x, 3);
+    }
+
+    #[test]
+    fn new_chunks_hold_at_most_the_maximum_number_of_entities() {
+        let mut entities = EntityCollection::<Schema>::new();
+        add(&mut entities, 1, vec![position(1)]);
+        entities.set_max_entities_per_chunk(2);
+        for entity_id in 2..7 {
+            add(&mut entities, entity_id, vec![position(entity_id)]);
+        }
+
+        // The first chunk was created before the maximum was lowered.
+        assert_eq!(chunk_sizes(&entities), vec![6]);
+
+        add(&mut entities, 7, vec![position(7), health(1)]);
+        add(&mut entities, 8, vec![position(8), health(1)]);
+        add(&mut entities, 9, vec![position(9), health(1)]);
+        assert_eq!(chunk_sizes(&entities), vec![6, 2, 1]);
     }
 }
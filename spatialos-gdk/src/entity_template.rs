@@ -1,7 +1,12 @@
+use ComponentBitField;
+use chunk::{Chunk, ComponentStorage, ComponentStorageInterface};
 use std::collections::HashMap;
+use std::marker::PhantomData;
+use unknown_component::UnknownComponent;
 use worker::ffi::Schema_ComponentData;
-use worker::schema::{Component, GeneratedSchema};
-use worker::{ComponentId, EntityId};
+use worker::schema::{Component, GeneratedSchema, SchemaComponent};
+use worker::{Authority, ComponentId, EntityId};
+use world::WorldTime;
 
 /// A worker constraint for an Entity's ACL.
 pub enum Worker<'a> {
@@ -95,7 +100,10 @@ impl EntityTemplate {
     }
 
     /// Adds a new component to this entity, with the given write access and initial data.
-    pub fn with_component<S: GeneratedSchema, C: Component<S>>(
+    ///
+    /// Only components defined in schema can be sent to SpatialOS, so a `LocalComponent`
+    /// can't be added here. Use `World::add_local_component` once the entity exists instead.
+    pub fn with_component<S: GeneratedSchema, C: SchemaComponent<S>>(
         mut self,
         write_access: Worker,
         data: C,
@@ -107,4 +115,134 @@ impl EntityTemplate {
             .insert(C::component_id(), data.serialise_snapshot());
         self
     }
+
+    /// Adds a copy of a component which is not part of the generated schema, such as
+    /// one returned by `World::get_unknown_components`, with the given write access.
+    pub fn with_unknown_component(
+        mut self,
+        write_access: Worker,
+        component: &UnknownComponent,
+    ) -> EntityTemplate {
+        if let Some(attribute) = write_access.get_attribute_string() {
+            self.write_access
+                .insert(component.component_id(), attribute);
+        }
+        self.data
+            .insert(component.component_id(), component.copy_data());
+        self
+    }
+}
+
+/// A template of a local entity, which only exists in this worker.
+///
+/// Local entities are created immediately using `World::spawn_local`, and live in the same
+/// chunks as SpatialOS entities, so they are returned by `ComponentGroup` queries in the same
+/// way. Changes to their components are never sent to SpatialOS, and this worker is always
+/// authoritative over all of their components.
+///
+/// ```
+/// let entity_id = world.spawn_local(
+///     LocalEntityTemplate::new()
+///         .with_component(Position {
+///             coords: Coordinates { x: 0.1, y: 0.2, z: 0.3 },
+///         })
+///         .with_component(AiState::default()),
+/// );
+/// ```
+pub struct LocalEntityTemplate<S: GeneratedSchema> {
+    components: HashMap<ComponentId, Box<LocalEntityComponent<S>>>,
+}
+
+impl<S: 'static + GeneratedSchema> LocalEntityTemplate<S> {
+    /// Creates a new `LocalEntityTemplate` with no components.
+    pub fn new() -> LocalEntityTemplate<S> {
+        LocalEntityTemplate {
+            components: HashMap::new(),
+        }
+    }
+
+    /// Adds a new component to this entity with the given initial data. This can be
+    /// either a schema component or a `LocalComponent`.
+    pub fn with_component<C: 'static + Component<S>>(mut self, data: C) -> LocalEntityTemplate<S> {
+        self.components.insert(
+            C::component_id(),
+            Box::new(LocalEntityComponentData::<S, C> {
+                data,
+                _schema: PhantomData,
+            }),
+        );
+        self
+    }
+
+    #[doc(hidden)]
+    pub fn get_bit_field(&self) -> S::ComponentBitField {
+        let mut bit_field = S::ComponentBitField::new();
+        for component_id in self.components.keys() {
+            bit_field.add_component(*component_id);
+        }
+        bit_field
+    }
+
+    #[doc(hidden)]
+    pub fn create_storages(
+        &self,
+        world_time: &mut WorldTime,
+    ) -> HashMap<ComponentId, Box<ComponentStorageInterface<S>>> {
+        self.components
+            .iter()
+            .map(|(component_id, component)| (*component_id, component.create_storage(world_time)))
+            .collect()
+    }
+
+    #[doc(hidden)]
+    pub fn insert_into_chunk(
+        self,
+        chunk: &mut Chunk<S>,
+        world_time: &mut WorldTime,
+        entity_index: usize,
+    ) {
+        for (_, component) in self.components {
+            component.insert_into_chunk(chunk, world_time, entity_index);
+        }
+    }
+}
+
+// Keeps hold of the concrete type of each component in a `LocalEntityTemplate`, so
+// that its storage can be created and its data inserted once the entity is spawned.
+trait LocalEntityComponent<S: GeneratedSchema> {
+    fn create_storage(&self, world_time: &mut WorldTime) -> Box<ComponentStorageInterface<S>>;
+
+    fn insert_into_chunk(
+        self: Box<Self>,
+        chunk: &mut Chunk<S>,
+        world_time: &mut WorldTime,
+        entity_index: usize,
+    );
+}
+
+struct LocalEntityComponentData<S: GeneratedSchema, C: Component<S>> {
+    data: C,
+    _schema: PhantomData<S>,
+}
+
+impl<S: 'static + GeneratedSchema, C: 'static + Component<S>> LocalEntityComponent<S>
+    for LocalEntityComponentData<S, C>
+{
+    fn create_storage(&self, world_time: &mut WorldTime) -> Box<ComponentStorageInterface<S>> {
+        ComponentStorage::<S, C>::new_boxed(world_time)
+    }
+
+    fn insert_into_chunk(
+        self: Box<Self>,
+        chunk: &mut Chunk<S>,
+        world_time: &mut WorldTime,
+        entity_index: usize,
+    ) {
+        chunk.get_component_storage::<C>().unwrap().insert_data(
+            world_time,
+            entity_index,
+            self.data.into_data(),
+            Authority::Authoritative,
+        );
+    }
 }
@@ -1,34 +1,66 @@
 extern crate boxfnonce;
+#[macro_use]
+extern crate lazy_static;
 extern crate libc;
 extern crate rayon;
 #[macro_use]
 extern crate downcast_rs;
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+pub extern crate serde;
+// `ComponentGroup` can be derived in tests, as the derive refers to `::spatialos_gdk`
+// and `::schema::Schema`.
+#[cfg(test)]
+extern crate self as spatialos_gdk;
+#[cfg(test)]
+#[macro_use]
+extern crate spatialos_gdk_derive;
 
 mod chunk;
+mod command_buffer;
 mod commands;
+mod component_borrow;
 mod component_group;
+mod component_lookup;
 mod entity;
 mod entity_collection;
 mod entity_template;
+mod local_component;
 mod shared_resources;
 mod snapshot;
 mod system;
+#[cfg(test)]
+mod test_schema;
+#[cfg(test)]
+use test_schema as schema;
+mod unknown_component;
 mod world;
 
 #[doc(hidden)]
 pub mod worker;
 
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+pub mod serde_support;
+
 pub use self::chunk::{Chunk, ComponentStorage};
-pub use self::component_group::{ComponentGroup, ModifiedRead, ModifiedWrite, Read,
-                                UnsafeSendablePointer, Write};
+pub use self::command_buffer::{CommandBuffer, CommandRecorder};
+pub use self::component_group::{ComponentGroup, Events, ModifiedField, ModifiedRead,
+                                ModifiedWrite, Read, ReadAuthoritative, ReadNotAuthoritative,
+                                UnsafeSendablePointer, Without, Write};
+pub use self::component_lookup::ComponentLookup;
 pub use self::entity_collection::Entities;
-pub use self::entity_template::{EntityTemplate, Worker};
+pub use self::entity_template::{EntityTemplate, LocalEntityTemplate, Worker};
+pub use self::local_component::{local_component_unique_index, LocalComponent,
+                                LocalComponentUpdate, MAX_LOCAL_COMPONENTS};
+pub use self::shared_resources::{Resource, ResourceError, ResourceMut};
 pub use self::snapshot::Snapshot;
 pub use self::system::System;
+pub use self::unknown_component::UnknownComponent;
+pub use self::worker::schema::reflection;
 pub use self::worker::{Connection, ConnectionParameters, EntityId, LogLevel};
 pub use self::world::{World, WorldError, WorldTime};
 
-use chunk::MAX_ENTITIES_PER_CHUNK;
 use worker::ComponentId;
 
 #[doc(hidden)]
@@ -59,6 +91,40 @@ pub trait ComponentBitField: Default {
         }
     }
 
+    fn remove_component(&mut self, component_id: ComponentId) -> bool {
+        if let Some(unique_index) = Self::get_unique_index(component_id) {
+            let field_index = unique_index / FIELD_SIZE_BITS;
+            let field_offset = unique_index % FIELD_SIZE_BITS;
+
+            let field = self.get_field_mut(field_index);
+            *field &= !(1 << field_offset);
+
+            true
+        } else {
+            false
+        }
+    }
+
+    fn has_component(&self, component_id: ComponentId) -> bool {
+        if let Some(unique_index) = Self::get_unique_index(component_id) {
+            let field_index = unique_index / FIELD_SIZE_BITS;
+            let field_offset = unique_index % FIELD_SIZE_BITS;
+
+            (*self.get_field(field_index) >> field_offset) & 1 == 1
+        } else {
+            false
+        }
+    }
+
+    // The unique index of every component in this bit field, in ascending order.
+    fn unique_indices(&self) -> Vec<usize> {
+        (0..Self::NUMBER_OF_FIELDS * FIELD_SIZE_BITS)
+            .filter(|index| {
+                (*self.get_field(index / FIELD_SIZE_BITS) >> (index % FIELD_SIZE_BITS)) & 1 == 1
+            })
+            .collect()
+    }
+
     fn is_subset(&self, subset: &Self) -> bool {
         for i in 0..Self::NUMBER_OF_FIELDS {
             if (!(*self.get_field(i))) & (*(subset.get_field(i))) != 0 {
@@ -67,25 +133,38 @@ pub trait ComponentBitField: Default {
         }
         true
     }
-}
 
-const NUMBER_OF_TAG_FIELDS: usize = 1 + (MAX_ENTITIES_PER_CHUNK / 64);
+    fn is_disjoint(&self, other: &Self) -> bool {
+        for i in 0..Self::NUMBER_OF_FIELDS {
+            if *self.get_field(i) & *other.get_field(i) != 0 {
+                return false;
+            }
+        }
+        true
+    }
+}
 
+// Grows as tags are set, so that small chunks only use as much memory as they need.
 struct TagComponentArray {
-    pub fields: [u64; NUMBER_OF_TAG_FIELDS],
+    pub fields: Vec<u64>,
 }
 
 impl TagComponentArray {
     fn new() -> TagComponentArray {
-        TagComponentArray {
-            fields: [0; NUMBER_OF_TAG_FIELDS],
-        }
+        TagComponentArray { fields: Vec::new() }
     }
 
     fn set_tag(&mut self, index: usize, has_tag: bool) {
         let field_index = index / 64;
         let field_offset = index % 64;
 
+        if field_index >= self.fields.len() {
+            if !has_tag {
+                return;
+            }
+            self.fields.resize(field_index + 1, 0);
+        }
+
         let field = &mut self.fields[field_index];
         if has_tag {
             *field |= 1 << field_offset;
@@ -98,7 +177,10 @@ impl TagComponentArray {
         let field_index = index / 64;
         let field_offset = index % 64;
 
-        let field = &self.fields[field_index];
+        let field = match self.fields.get(field_index) {
+            Some(field) => field,
+            None => return false,
+        };
         let value = (*field >> field_offset) & 1;
         value == 1
     }
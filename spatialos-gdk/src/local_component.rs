@@ -0,0 +1,318 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use worker::ComponentId;
+use worker::ffi::Schema_ComponentUpdate;
+use worker::schema::{Component, ComponentDataInterface, ComponentUpdateInterface, GeneratedSchema};
+use world::WorldTime;
+
+/// SpatialOS component IDs can be at most `2^29 - 1`, so any ID from here upwards
+/// can never collide with a schema component.
+pub const LOCAL_COMPONENT_ID_OFFSET: ComponentId = 1 << 29;
+
+/// The maximum number of distinct `LocalComponent` types which can be registered for a schema.
+pub const MAX_LOCAL_COMPONENTS: usize = 64;
+
+// Local component IDs are assigned per schema, in the order the components are registered.
+#[derive(Default)]
+struct LocalComponentRegistry {
+    ids: HashMap<(TypeId, TypeId), ComponentId>,
+    counts: HashMap<TypeId, usize>,
+}
+
+lazy_static! {
+    static ref LOCAL_COMPONENT_IDS: RwLock<LocalComponentRegistry> =
+        RwLock::new(LocalComponentRegistry::default());
+}
+
+/// A component which only exists in this worker's local view of an entity.
+///
+/// Local components are stored in the same chunks as schema components, so they
+/// can be used in a `ComponentGroup` with `Read` and `Write` like any other component.
+/// They are never sent to SpatialOS, and this worker is always considered to be
+/// authoritative over them.
+///
+/// Each local component type must be registered with `World::register_local_component`
+/// before it is used. IDs are assigned in registration order, so a worker which registers
+/// its local components in the same order always gives them the same IDs. Using a local
+/// component which has not been registered will panic.
+///
+/// They can be added to and removed from entities using `World::add_local_component`
+/// and `World::remove_local_component`. They can't be added to an `EntityTemplate` or
+/// a snapshot, as they have no schema to be serialised with.
+///
+/// Local components must be `Send`, so that the `World` which stores them can be
+/// moved to another thread.
+///
+/// ## Example
+///
+/// ```
+/// #[derive(Default)]
+/// pub struct AiState {
+///     pub target: Option<EntityId>,
+/// }
+///
+/// impl LocalComponent for AiState {}
+///
+/// world.register_local_component::<AiState>().unwrap();
+///
+/// #[derive(ComponentGroup)]
+/// pub struct AiData<'a> {
+///     pub position: Read<'a, Schema, Position>,
+///     pub ai_state: Write<'a, Schema, AiState>,
+/// }
+/// ```
+pub trait LocalComponent: 'static + Default + Send {}
+
+// Returns the ID of `C` for the schema `S`, assigning it the next free ID if it has not been
+// registered yet, or `None` if `MAX_LOCAL_COMPONENTS` have already been registered.
+#[doc(hidden)]
+pub fn register_local_component<S: 'static, C: LocalComponent>() -> Option<ComponentId> {
+    let key = (TypeId::of::<S>(), TypeId::of::<C>());
+    let mut registry = LOCAL_COMPONENT_IDS.write().unwrap();
+    if let Some(id) = registry.ids.get(&key) {
+        return Some(*id);
+    }
+
+    let next_index = registry.counts.get(&key.0).cloned().unwrap_or(0);
+    if next_index >= MAX_LOCAL_COMPONENTS {
+        return None;
+    }
+
+    let id = LOCAL_COMPONENT_ID_OFFSET + next_index as ComponentId;
+    registry.counts.insert(key.0, next_index + 1);
+    registry.ids.insert(key, id);
+    Some(id)
+}
+
+#[doc(hidden)]
+pub fn local_component_id<S: 'static, C: LocalComponent>() -> Option<ComponentId> {
+    LOCAL_COMPONENT_IDS
+        .read()
+        .unwrap()
+        .ids
+        .get(&(TypeId::of::<S>(), TypeId::of::<C>()))
+        .cloned()
+}
+
+#[doc(hidden)]
+pub fn is_local_component(component_id: ComponentId) -> bool {
+    component_id >= LOCAL_COMPONENT_ID_OFFSET
+        && ((component_id - LOCAL_COMPONENT_ID_OFFSET) as usize) < MAX_LOCAL_COMPONENTS
+}
+
+// Local components are given bits after all of the schema components.
+#[doc(hidden)]
+pub fn local_component_unique_index(
+    component_id: ComponentId,
+    number_of_schema_components: usize,
+) -> Option<usize> {
+    if is_local_component(component_id) {
+        Some(number_of_schema_components + (component_id - LOCAL_COMPONENT_ID_OFFSET) as usize)
+    } else {
+        None
+    }
+}
+
+#[doc(hidden)]
+pub struct LocalComponentUpdate;
+
+impl<S: GeneratedSchema> ComponentUpdateInterface<S> for LocalComponentUpdate {
+    fn contains_events(&self) -> bool {
+        false
+    }
+}
+
+impl<S: GeneratedSchema, C: LocalComponent> Component<S> for C {
+    type Data = C;
+    type Update = LocalComponentUpdate;
+
+    fn component_id() -> ComponentId {
+        match local_component_id::<S, C>() {
+            Some(id) => id,
+            None => panic!(
+                "Local component {} must be registered with \
+                 `World::register_local_component` before it is used.",
+                ::std::any::type_name::<C>()
+            ),
+        }
+    }
+
+    fn apply_update_to_data(_data: &mut Self::Data, _update: &Self::Update) {}
+
+    fn extract_data_borrow(_data: &S::ComponentData) -> Option<&Self::Data> {
+        None
+    }
+
+    fn extract_data(_data: S::ComponentData) -> Option<Self::Data> {
+        None
+    }
+
+    fn extract_update(_update: &S::ComponentUpdate) -> Option<&Self::Update> {
+        None
+    }
+
+    fn into_data(self) -> Self::Data {
+        self
+    }
+}
+
+// The data of a local component is the component itself. It is never dirty and
+// never has an update to send, so it is never replicated to SpatialOS.
+impl<S: GeneratedSchema, C: LocalComponent> ComponentDataInterface<S> for C {
+    fn serialise_update(&mut self) -> Option<Box<Schema_ComponentUpdate>> {
+        None
+    }
+
+    fn discard_update(&mut self) {}
+
+    fn get_and_clear_dirty_bit(&mut self) -> bool {
+        false
+    }
+
+    fn make_dirty(&mut self) {}
+
+    fn stamp_events(&mut self, _time: &WorldTime) {}
+
+    fn stamp_changed_fields(&mut self, _time: &WorldTime) -> bool {
+        false
+    }
+
+    fn changed_fields_since(&self, _time: &WorldTime) -> u64 {
+        0
+    }
+
+    fn loop_back_events(&mut self, _time: &WorldTime) -> bool {
+        false
+    }
+
+    fn cleanup_after_frame(&mut self, _oldest_cursor: &WorldTime) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity_template::LocalEntityTemplate;
+    use std::marker::PhantomData;
+    use schema::{new_world, Position, Schema};
+
+    struct SchemaA;
+    struct SchemaB;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct AiState {
+        target: i64,
+    }
+    impl LocalComponent for AiState {}
+
+    #[derive(Default)]
+    struct Selected;
+    impl LocalComponent for Selected {}
+
+    #[derive(Default)]
+    struct Unregistered;
+    impl LocalComponent for Unregistered {}
+
+    // A distinct local component type for each `T`.
+    struct Numbered<T>(PhantomData<T>);
+    impl<T> Default for Numbered<T> {
+        fn default() -> Numbered<T> {
+            Numbered(PhantomData)
+        }
+    }
+    impl<T: 'static + Send> LocalComponent for Numbered<T> {}
+
+    macro_rules! register_numbered {
+        ($schema:ty; $($n:tt)*) => {
+            vec![$(register_local_component::<$schema, Numbered<[u8; $n]>>()),*]
+        };
+    }
+
+    #[test]
+    fn ids_are_assigned_in_registration_order_for_each_schema() {
+        assert_eq!(
+            register_local_component::<SchemaA, AiState>(),
+            Some(LOCAL_COMPONENT_ID_OFFSET)
+        );
+        assert_eq!(
+            register_local_component::<SchemaA, Selected>(),
+            Some(LOCAL_COMPONENT_ID_OFFSET + 1)
+        );
+        assert_eq!(
+            register_local_component::<SchemaB, Selected>(),
+            Some(LOCAL_COMPONENT_ID_OFFSET)
+        );
+
+        assert_eq!(
+            register_local_component::<SchemaA, AiState>(),
+            Some(LOCAL_COMPONENT_ID_OFFSET)
+        );
+        assert_eq!(local_component_id::<SchemaB, AiState>(), None);
+    }
+
+    #[test]
+    fn registering_more_than_the_maximum_fails() {
+        struct SchemaC;
+        let ids = register_numbered!(SchemaC;
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31
+            32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49 50 51 52 53 54 55 56 57 58 59 60
+            61 62 63 64);
+
+        assert_eq!(ids.len(), MAX_LOCAL_COMPONENTS + 1);
+        for (index, id) in ids[..MAX_LOCAL_COMPONENTS].iter().enumerate() {
+            assert_eq!(*id, Some(LOCAL_COMPONENT_ID_OFFSET + index as ComponentId));
+            assert!(is_local_component(id.unwrap()));
+        }
+        assert_eq!(ids[MAX_LOCAL_COMPONENTS], None);
+        assert_eq!(register_local_component::<SchemaC, AiState>(), None);
+    }
+
+    #[test]
+    fn local_components_never_have_an_update_to_send() {
+        let mut state = AiState { target: 3 };
+        ComponentDataInterface::<Schema>::make_dirty(&mut state);
+
+        assert!(!ComponentDataInterface::<Schema>::get_and_clear_dirty_bit(&mut state));
+        assert!(ComponentDataInterface::<Schema>::serialise_update(&mut state).is_none());
+    }
+
+    #[test]
+    fn local_components_can_be_added_to_and_removed_from_entities() {
+        let mut world = new_world();
+        world.register_local_component::<AiState>().unwrap();
+        let entity_id = world.spawn_local(
+            LocalEntityTemplate::new().with_component(Position { x: 1 }),
+        );
+
+        assert!(world.add_local_component(entity_id, AiState { target: 4 }));
+        assert_eq!(world.get_component::<AiState>(entity_id).unwrap().target, 4);
+        assert_eq!(*world.get_component::<Position>(entity_id).unwrap().x, 1);
+
+        assert_eq!(
+            world.remove_local_component::<AiState>(entity_id),
+            Some(AiState { target: 4 })
+        );
+        assert!(world.get_component::<AiState>(entity_id).is_none());
+        assert!(!world.add_local_component(entity_id - 1, AiState::default()));
+    }
+
+    #[test]
+    fn registering_a_local_component_twice_does_nothing() {
+        let mut world = new_world();
+        world.register_local_component::<Selected>().unwrap();
+        let id = <Selected as Component<Schema>>::component_id();
+
+        world.register_local_component::<Selected>().unwrap();
+        assert_eq!(<Selected as Component<Schema>>::component_id(), id);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be registered with `World::register_local_component`")]
+    fn unregistered_local_components_panic_when_used() {
+        let mut world = new_world();
+        let entity_id = world.spawn_local(LocalEntityTemplate::new());
+        world.add_local_component(entity_id, Unregistered);
+    }
+}
@@ -0,0 +1,78 @@
+//! Used by the generated code when the `serde` feature is enabled.
+//!
+//! Schema maps can have keys of any type, but many formats (such as JSON) only
+//! allow string keys. So maps are represented as a list of `{ "key": .., "value": .. }`
+//! entries instead.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[derive(Serialize)]
+struct EntryRef<'a, K: 'a, V: 'a> {
+    key: &'a K,
+    value: &'a V,
+}
+
+#[derive(Deserialize)]
+struct Entry<K, V> {
+    key: K,
+    value: V,
+}
+
+pub mod map {
+    use super::*;
+
+    pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize + Eq + Hash,
+        V: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(map.iter().map(|(key, value)| EntryRef { key, value }))
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let entries: Vec<Entry<K, V>> = Vec::deserialize(deserializer)?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| (entry.key, entry.value))
+            .collect())
+    }
+}
+
+pub mod option_map {
+    use super::*;
+
+    pub fn serialize<K, V, S>(map: &Option<HashMap<K, V>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize + Eq + Hash,
+        V: Serialize,
+        S: Serializer,
+    {
+        match *map {
+            Some(ref map) => super::map::serialize(map, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<Option<HashMap<K, V>>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let entries: Option<Vec<Entry<K, V>>> = Option::deserialize(deserializer)?;
+        Ok(entries.map(|entries| {
+            entries
+                .into_iter()
+                .map(|entry| (entry.key, entry.value))
+                .collect()
+        }))
+    }
+}
@@ -1,9 +1,96 @@
 use std::any::Any;
 use std::any::TypeId;
+use std::any::type_name;
+use std::cell::UnsafeCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+/// Possible errors when borrowing a shared resource.
+#[derive(Debug)]
+pub enum ResourceError {
+    /// No resource of the given type has been added to the `World`.
+    Missing(&'static str),
+
+    /// The resource is already mutably borrowed, or is already borrowed and
+    /// a mutable borrow was requested.
+    AlreadyBorrowed(&'static str),
+}
+
+impl fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ResourceError::Missing(name) => write!(
+                f,
+                "The resource {} is missing. Add it with `World::set_shared_resource`.",
+                name
+            ),
+            &ResourceError::AlreadyBorrowed(name) => {
+                write!(f, "The resource {} is already borrowed.", name)
+            }
+        }
+    }
+}
+
+// The borrow state is the number of immutable borrows, or -1 if mutably borrowed.
+struct ResourceCell {
+    borrow: AtomicIsize,
+    value: UnsafeCell<Box<Any + Send>>,
+}
+
+/// An immutable borrow of a shared resource, returned by `World::resource`.
+///
+/// If `R` is `Sync`, this can be used from within `Entities::par_for_each`.
+pub struct Resource<'a, R: 'static> {
+    borrow: &'a AtomicIsize,
+    value: &'a R,
+}
+
+impl<'a, R: 'static> Deref for Resource<'a, R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.value
+    }
+}
+
+impl<'a, R: 'static> Drop for Resource<'a, R> {
+    fn drop(&mut self) {
+        self.borrow.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// A mutable borrow of a shared resource, returned by `World::resource_mut`.
+///
+/// If `R` is `Sync`, this can be read from within `Entities::par_for_each`.
+pub struct ResourceMut<'a, R: 'static> {
+    borrow: &'a AtomicIsize,
+    value: &'a mut R,
+}
+
+impl<'a, R: 'static> Deref for ResourceMut<'a, R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        self.value
+    }
+}
+
+impl<'a, R: 'static> DerefMut for ResourceMut<'a, R> {
+    fn deref_mut(&mut self) -> &mut R {
+        self.value
+    }
+}
+
+impl<'a, R: 'static> Drop for ResourceMut<'a, R> {
+    fn drop(&mut self) {
+        self.borrow.store(0, Ordering::Release);
+    }
+}
 
 pub struct SharedResources {
-    resources: HashMap<TypeId, Box<Any>>,
+    resources: HashMap<TypeId, ResourceCell>,
 }
 
 impl SharedResources {
@@ -13,13 +100,140 @@ impl SharedResources {
         }
     }
 
-    pub fn add<R: 'static>(&mut self, resource: R) {
-        self.resources.insert(TypeId::of::<R>(), Box::new(resource));
+    pub fn add<R: 'static + Send>(&mut self, resource: R) {
+        self.resources.insert(
+            TypeId::of::<R>(),
+            ResourceCell {
+                borrow: AtomicIsize::new(0),
+                value: UnsafeCell::new(Box::new(resource)),
+            },
+        );
     }
 
     pub fn get<R: 'static>(&mut self) -> Option<&mut R> {
         self.resources
             .get_mut(&TypeId::of::<R>())
-            .map(|resource| resource.downcast_mut::<R>().unwrap())
+            .map(|resource| unsafe { (*resource.value.get()).downcast_mut::<R>().unwrap() })
+    }
+
+    pub fn borrow<R: 'static>(&self) -> Result<Resource<R>, ResourceError> {
+        let cell = self.get_cell::<R>()?;
+
+        let mut borrow = cell.borrow.load(Ordering::Acquire);
+        loop {
+            if borrow < 0 {
+                return Err(ResourceError::AlreadyBorrowed(type_name::<R>()));
+            }
+            match cell.borrow.compare_exchange_weak(
+                borrow,
+                borrow + 1,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(current) => borrow = current,
+            }
+        }
+
+        // The borrow state guarantees that there is no mutable borrow of this value.
+        let value = unsafe { (*cell.value.get()).downcast_ref::<R>().unwrap() };
+        Ok(Resource {
+            borrow: &cell.borrow,
+            value,
+        })
+    }
+
+    pub fn borrow_mut<R: 'static>(&self) -> Result<ResourceMut<R>, ResourceError> {
+        let cell = self.get_cell::<R>()?;
+
+        if cell.borrow
+            .compare_exchange(0, -1, Ordering::Acquire, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(ResourceError::AlreadyBorrowed(type_name::<R>()));
+        }
+
+        // The borrow state guarantees that there are no other borrows of this value.
+        let value = unsafe { (*cell.value.get()).downcast_mut::<R>().unwrap() };
+        Ok(ResourceMut {
+            borrow: &cell.borrow,
+            value,
+        })
+    }
+
+    fn get_cell<R: 'static>(&self) -> Result<&ResourceCell, ResourceError> {
+        self.resources
+            .get(&TypeId::of::<R>())
+            .ok_or(ResourceError::Missing(type_name::<R>()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Score {
+        total: u32,
+    }
+
+    #[test]
+    fn missing_resources_cannot_be_borrowed() {
+        let resources = SharedResources::new();
+
+        match resources.borrow::<Score>() {
+            Err(ResourceError::Missing(name)) => assert!(name.ends_with("Score")),
+            _ => panic!("Expected the resource to be missing."),
+        }
+        assert!(resources.borrow_mut::<Score>().is_err());
+    }
+
+    #[test]
+    fn resources_can_be_borrowed_immutably_many_times() {
+        let mut resources = SharedResources::new();
+        resources.add(Score { total: 3 });
+
+        let first = resources.borrow::<Score>().unwrap();
+        let second = resources.borrow::<Score>().unwrap();
+        assert_eq!(first.total + second.total, 6);
+        match resources.borrow_mut::<Score>() {
+            Err(ResourceError::AlreadyBorrowed(_)) => (),
+            _ => panic!("Expected the resource to already be borrowed."),
+        };
+    }
+
+    #[test]
+    fn mutable_borrows_are_exclusive() {
+        let mut resources = SharedResources::new();
+        resources.add(Score { total: 0 });
+
+        let mut score = resources.borrow_mut::<Score>().unwrap();
+        score.total += 1;
+        assert!(resources.borrow::<Score>().is_err());
+        assert!(resources.borrow_mut::<Score>().is_err());
+    }
+
+    #[test]
+    fn borrows_are_released_when_dropped() {
+        let mut resources = SharedResources::new();
+        resources.add(Score { total: 0 });
+
+        resources.borrow_mut::<Score>().unwrap().total += 1;
+        {
+            let _score = resources.borrow::<Score>().unwrap();
+        }
+        resources.borrow_mut::<Score>().unwrap().total += 1;
+
+        assert_eq!(resources.borrow::<Score>().unwrap().total, 2);
+        assert_eq!(resources.get::<Score>().unwrap().total, 2);
+    }
+
+    #[test]
+    fn adding_a_resource_replaces_the_old_one() {
+        let mut resources = SharedResources::new();
+        resources.add(Score { total: 1 });
+        resources.add(Score { total: 2 });
+
+        assert_eq!(resources.get::<Score>().unwrap().total, 2);
+        assert!(resources.get::<u32>().is_none());
     }
 }
@@ -0,0 +1,517 @@
+// A small hand-written schema for the runtime's unit tests. Generated schemas refer to this
+// crate as `spatialos_gdk`, so they can't be used from inside it. Anything which would need
+// the Worker SDK is left unimplemented, as tests never connect to SpatialOS.
+
+#![allow(dead_code)]
+
+use ComponentBitField;
+use reflection::{SchemaInfo, SchemaValue};
+use std::any::Any;
+use std::collections::HashMap;
+use worker::ffi::{Schema_CommandRequest, Schema_CommandResponse, Schema_ComponentData,
+                  Schema_ComponentUpdate};
+use worker::schema::{Command, CommandRequestInterface, CommandResponseInterface, Component,
+                     ComponentDataInterface, ComponentEvent, ComponentField,
+                     ComponentUpdateInterface, DynamicComponentHandler, Event, GeneratedSchema,
+                     GlobalComponentDataInterface, GlobalComponentUpdateInterface, Property};
+use worker::{Authority, ComponentId, Connection, Dispatcher, EntityId};
+use world::{PartialEntity, World, WorldTime};
+use {local_component_unique_index, MAX_LOCAL_COMPONENTS, FIELD_SIZE_BITS};
+
+const NUMBER_OF_COMPONENTS: usize = 2;
+const NUMBER_OF_FIELDS: usize =
+    1 + (NUMBER_OF_COMPONENTS + MAX_LOCAL_COMPONENTS - 1) / FIELD_SIZE_BITS;
+
+#[derive(Default)]
+pub struct Schema;
+
+pub fn new_world() -> Box<World<Schema>> {
+    World::new(Connection::disconnected())
+}
+
+// Adds an entity as if it had been received from SpatialOS, with authority over the
+// components in `authoritative`.
+pub fn add_remote_entity(
+    world: &mut World<Schema>,
+    entity_id: EntityId,
+    components: Vec<ComponentData>,
+    authoritative: &[ComponentId],
+) {
+    world.on_critical_section(true);
+    world.on_add_entity(entity_id);
+    for data in components {
+        let component_id = data.component_id();
+        world.on_add_component(entity_id, component_id, data);
+        if authoritative.contains(&component_id) {
+            world.on_authority_change(entity_id, component_id, Authority::Authoritative);
+        }
+    }
+    world.on_critical_section(false);
+}
+
+// An entity with the given components, as it is added to the `World`'s entities.
+pub fn partial_entity(
+    entity_id: EntityId,
+    components: Vec<ComponentData>,
+) -> PartialEntity<Schema> {
+    let mut bit_field = TestComponentBitField::new();
+    let mut component_data = HashMap::new();
+    for data in components {
+        bit_field.add_component(data.component_id());
+        component_data.insert(data.component_id(), data);
+    }
+
+    PartialEntity {
+        entity_id,
+        bit_field,
+        component_data,
+        write_authority: HashMap::new(),
+    }
+}
+
+impl GeneratedSchema for Schema {
+    const NUMBER_OF_COMPONENTS: usize = NUMBER_OF_COMPONENTS;
+    type ComponentData = ComponentData;
+    type ComponentUpdate = ComponentUpdate;
+    type ComponentBitField = TestComponentBitField;
+
+    fn serialise_entity_acl(
+        _read: Vec<String>,
+        _write: HashMap<ComponentId, String>,
+    ) -> (ComponentId, Box<Schema_ComponentData>) {
+        unimplemented!()
+    }
+
+    fn run_dynamic_component_handler<D: DynamicComponentHandler<Self>>(handler: &mut D) {
+        handler.register_component::<Position>();
+        handler.register_component::<Health>();
+    }
+
+    unsafe fn deserialise_command_request(
+        _component_id: ComponentId,
+        _command_index: u32,
+        _request: Box<Schema_CommandRequest>,
+    ) -> Option<Box<Any>> {
+        unimplemented!()
+    }
+
+    unsafe fn deserialise_command_response(
+        _component_id: ComponentId,
+        _command_index: u32,
+        _response: Box<Schema_CommandResponse>,
+    ) -> Option<Box<Any>> {
+        unimplemented!()
+    }
+
+    fn schema_info() -> &'static SchemaInfo {
+        unimplemented!()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Hash, Debug, Default)]
+pub struct TestComponentBitField {
+    fields: [u64; NUMBER_OF_FIELDS],
+}
+
+impl ComponentBitField for TestComponentBitField {
+    const NUMBER_OF_FIELDS: usize = NUMBER_OF_FIELDS;
+
+    fn new() -> Self {
+        TestComponentBitField {
+            fields: [0; NUMBER_OF_FIELDS],
+        }
+    }
+
+    fn get_field(&self, field_index: usize) -> &u64 {
+        &self.fields[field_index]
+    }
+
+    fn get_field_mut(&mut self, field_index: usize) -> &mut u64 {
+        &mut self.fields[field_index]
+    }
+
+    fn get_unique_index(component_id: ComponentId) -> Option<usize> {
+        match component_id {
+            Position::COMPONENT_ID => Some(0),
+            Health::COMPONENT_ID => Some(1),
+            _ => local_component_unique_index(component_id, NUMBER_OF_COMPONENTS),
+        }
+    }
+}
+
+pub enum ComponentData {
+    Position(PositionData),
+    Health(HealthData),
+}
+
+pub enum ComponentUpdate {
+    Position(PositionUpdate),
+    Health(HealthUpdate),
+}
+
+impl GlobalComponentDataInterface<Schema> for ComponentData {
+    fn deserialise(
+        _component_id: ComponentId,
+        _data: Box<Schema_ComponentData>,
+    ) -> Option<ComponentData> {
+        unimplemented!()
+    }
+
+    fn serialise(&self) -> Box<Schema_ComponentData> {
+        unimplemented!()
+    }
+
+    fn apply_update(&mut self, update: &ComponentUpdate) {
+        match (self, update) {
+            (&mut ComponentData::Position(ref mut data), &ComponentUpdate::Position(ref x)) => {
+                Position::apply_update_to_data(data, x)
+            }
+            (&mut ComponentData::Health(ref mut data), &ComponentUpdate::Health(ref x)) => {
+                Health::apply_update_to_data(data, x)
+            }
+            _ => (),
+        }
+    }
+
+    fn component_id(&self) -> ComponentId {
+        match self {
+            &ComponentData::Position(_) => Position::COMPONENT_ID,
+            &ComponentData::Health(_) => Health::COMPONENT_ID,
+        }
+    }
+
+    fn to_schema_value(&self) -> SchemaValue {
+        unimplemented!()
+    }
+}
+
+impl GlobalComponentUpdateInterface<Schema> for ComponentUpdate {
+    fn deserialise(
+        _component_id: ComponentId,
+        _update: Box<Schema_ComponentUpdate>,
+    ) -> Option<ComponentUpdate> {
+        unimplemented!()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Position {
+    pub x: i64,
+}
+
+impl Position {
+    pub const COMPONENT_ID: ComponentId = 54;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PositionData {
+    is_dirty: bool,
+    pub x: Property<i64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PositionUpdate {
+    pub x: Option<i64>,
+}
+
+pub struct PositionX;
+
+impl ComponentField<Schema> for PositionX {
+    type Component = Position;
+
+    fn mask() -> u64 {
+        1
+    }
+}
+
+impl Component<Schema> for Position {
+    type Data = PositionData;
+    type Update = PositionUpdate;
+
+    fn component_id() -> ComponentId {
+        Position::COMPONENT_ID
+    }
+
+    fn apply_update_to_data(data: &mut Self::Data, update: &Self::Update) {
+        if let Some(x) = update.x {
+            data.x = Property::new(x);
+        }
+    }
+
+    fn extract_data_borrow(data: &ComponentData) -> Option<&Self::Data> {
+        match data {
+            &ComponentData::Position(ref data) => Some(data),
+            _ => None,
+        }
+    }
+
+    fn extract_data(data: ComponentData) -> Option<Self::Data> {
+        match data {
+            ComponentData::Position(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    fn extract_update(update: &ComponentUpdate) -> Option<&Self::Update> {
+        match update {
+            &ComponentUpdate::Position(ref update) => Some(update),
+            _ => None,
+        }
+    }
+
+    fn into_data(self) -> Self::Data {
+        PositionData {
+            is_dirty: false,
+            x: self.x.into(),
+        }
+    }
+}
+
+impl ComponentDataInterface<Schema> for PositionData {
+    fn serialise_update(&mut self) -> Option<Box<Schema_ComponentUpdate>> {
+        self.discard_update();
+        None
+    }
+
+    fn discard_update(&mut self) {
+        self.x.get_and_clear_dirty_bit();
+    }
+
+    fn get_and_clear_dirty_bit(&mut self) -> bool {
+        let dirty = self.is_dirty;
+        self.is_dirty = false;
+        dirty
+    }
+
+    fn make_dirty(&mut self) {
+        self.is_dirty = true;
+    }
+
+    fn stamp_events(&mut self, _time: &WorldTime) {}
+
+    fn stamp_changed_fields(&mut self, time: &WorldTime) -> bool {
+        self.x.stamp_changed(time)
+    }
+
+    fn changed_fields_since(&self, time: &WorldTime) -> u64 {
+        if self.x.changed_after(time) {
+            PositionX::mask()
+        } else {
+            0
+        }
+    }
+
+    fn loop_back_events(&mut self, _time: &WorldTime) -> bool {
+        false
+    }
+
+    fn cleanup_after_frame(&mut self, _oldest_cursor: &WorldTime) -> bool {
+        false
+    }
+}
+
+impl ComponentUpdateInterface<Schema> for PositionUpdate {
+    fn contains_events(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Health {
+    pub current: i32,
+    pub maximum: i32,
+}
+
+impl Health {
+    pub const COMPONENT_ID: ComponentId = 1001;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HealthData {
+    is_dirty: bool,
+    pub current: Property<i32>,
+    pub maximum: Property<i32>,
+    pub got_hit: Event<i32>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HealthUpdate {
+    pub current: Option<i32>,
+    pub maximum: Option<i32>,
+    pub got_hit: Vec<i32>,
+}
+
+pub struct HealthCurrent;
+
+impl ComponentField<Schema> for HealthCurrent {
+    type Component = Health;
+
+    fn mask() -> u64 {
+        1
+    }
+}
+
+pub struct HealthMaximum;
+
+impl ComponentField<Schema> for HealthMaximum {
+    type Component = Health;
+
+    fn mask() -> u64 {
+        2
+    }
+}
+
+pub struct HealthGotHit;
+
+impl ComponentEvent<Schema> for HealthGotHit {
+    type Component = Health;
+    type Value = i32;
+
+    fn get_event(data: &HealthData) -> &Event<i32> {
+        &data.got_hit
+    }
+}
+
+pub struct HealthPing;
+
+pub struct PingRequest;
+
+pub struct PingResponse;
+
+impl Command<Schema> for HealthPing {
+    type Component = Health;
+    type Request = PingRequest;
+    type Response = PingResponse;
+
+    fn command_index() -> u32 {
+        1
+    }
+}
+
+impl CommandRequestInterface for PingRequest {
+    fn deserialise_request(_request: Box<Schema_CommandRequest>) -> PingRequest {
+        unimplemented!()
+    }
+
+    fn serialise_request(&self) -> Box<Schema_CommandRequest> {
+        unimplemented!()
+    }
+}
+
+impl CommandResponseInterface for PingResponse {
+    fn deserialise_response(_response: Box<Schema_CommandResponse>) -> PingResponse {
+        unimplemented!()
+    }
+
+    fn serialise_response(&self) -> Box<Schema_CommandResponse> {
+        unimplemented!()
+    }
+}
+
+impl Component<Schema> for Health {
+    type Data = HealthData;
+    type Update = HealthUpdate;
+
+    fn component_id() -> ComponentId {
+        Health::COMPONENT_ID
+    }
+
+    fn apply_update_to_data(data: &mut Self::Data, update: &Self::Update) {
+        if let Some(current) = update.current {
+            data.current = Property::new(current);
+        }
+        if let Some(maximum) = update.maximum {
+            data.maximum = Property::new(maximum);
+        }
+        for hit in &update.got_hit {
+            data.got_hit.add_event(*hit);
+        }
+    }
+
+    fn extract_data_borrow(data: &ComponentData) -> Option<&Self::Data> {
+        match data {
+            &ComponentData::Health(ref data) => Some(data),
+            _ => None,
+        }
+    }
+
+    fn extract_data(data: ComponentData) -> Option<Self::Data> {
+        match data {
+            ComponentData::Health(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    fn extract_update(update: &ComponentUpdate) -> Option<&Self::Update> {
+        match update {
+            &ComponentUpdate::Health(ref update) => Some(update),
+            _ => None,
+        }
+    }
+
+    fn into_data(self) -> Self::Data {
+        HealthData {
+            is_dirty: false,
+            current: self.current.into(),
+            maximum: self.maximum.into(),
+            got_hit: Event::new(),
+        }
+    }
+}
+
+impl ComponentDataInterface<Schema> for HealthData {
+    fn serialise_update(&mut self) -> Option<Box<Schema_ComponentUpdate>> {
+        self.discard_update();
+        None
+    }
+
+    fn discard_update(&mut self) {
+        self.current.get_and_clear_dirty_bit();
+        self.maximum.get_and_clear_dirty_bit();
+        self.got_hit.clear_staged_events();
+    }
+
+    fn get_and_clear_dirty_bit(&mut self) -> bool {
+        let dirty = self.is_dirty;
+        self.is_dirty = false;
+        dirty
+    }
+
+    fn make_dirty(&mut self) {
+        self.is_dirty = true;
+    }
+
+    fn stamp_events(&mut self, time: &WorldTime) {
+        self.got_hit.stamp_events(time);
+    }
+
+    fn stamp_changed_fields(&mut self, time: &WorldTime) -> bool {
+        let current = self.current.stamp_changed(time);
+        self.maximum.stamp_changed(time) || current
+    }
+
+    fn changed_fields_since(&self, time: &WorldTime) -> u64 {
+        let mut mask = 0;
+        if self.current.changed_after(time) {
+            mask |= HealthCurrent::mask();
+        }
+        if self.maximum.changed_after(time) {
+            mask |= HealthMaximum::mask();
+        }
+        mask
+    }
+
+    fn loop_back_events(&mut self, time: &WorldTime) -> bool {
+        self.got_hit.loop_back_staged_events(time)
+    }
+
+    fn cleanup_after_frame(&mut self, oldest_cursor: &WorldTime) -> bool {
+        self.got_hit.clear_before(oldest_cursor)
+    }
+}
+
+impl ComponentUpdateInterface<Schema> for HealthUpdate {
+    fn contains_events(&self) -> bool {
+        self.got_hit.len() > 0
+    }
+}
@@ -0,0 +1,72 @@
+use worker::ComponentId;
+use worker::ffi::{self, Schema_ComponentData, Schema_ComponentUpdate, Schema_Object};
+use worker::schema::reflection::{SchemaValue, ToSchemaValue};
+
+/// A component which is not part of the generated schema.
+///
+/// Workers which were built against an older version of the schema may see components
+/// which they don't know about. Rather than being dropped, their data is kept as an
+/// opaque schema object, and updates to them are applied to it. This means that they
+/// can still be inspected by tools which know their schema, and copied into an
+/// `EntityTemplate` using `EntityTemplate::with_unknown_component`, so that they are
+/// not lost when an entity is recreated or written to a snapshot.
+///
+/// The unknown components of an entity can be found using `World::get_unknown_components`.
+/// Their fields can't be described by reflection, so `to_schema_value` gives
+/// `SchemaValue::Unknown` with only the component ID.
+pub struct UnknownComponent {
+    component_id: ComponentId,
+    // An owned copy, as the data given in an op is freed along with the op list.
+    data: *mut Schema_ComponentData,
+}
+
+// The data is only ever accessed through this struct, which owns it.
+unsafe impl Send for UnknownComponent {}
+
+impl UnknownComponent {
+    #[doc(hidden)]
+    pub fn new(component_id: ComponentId, data: &Schema_ComponentData) -> UnknownComponent {
+        UnknownComponent {
+            component_id,
+            data: unsafe { ffi::Schema_CopyComponentData(data) },
+        }
+    }
+
+    pub fn component_id(&self) -> ComponentId {
+        self.component_id
+    }
+
+    /// The fields of the component, which can be read using the schema functions in
+    /// `spatialos_gdk::worker::ffi`.
+    pub fn fields(&self) -> *mut Schema_Object {
+        unsafe { ffi::Schema_GetComponentDataFields(self.data) }
+    }
+
+    /// Creates a copy of the component's data, which can be sent to SpatialOS.
+    pub fn copy_data(&self) -> Box<Schema_ComponentData> {
+        unsafe { Box::from_raw(ffi::Schema_CopyComponentData(self.data)) }
+    }
+
+    #[doc(hidden)]
+    pub fn apply_update(&mut self, update: Box<Schema_ComponentUpdate>) {
+        unsafe {
+            ffi::Schema_ApplyComponentUpdateToData(&*update, self.data);
+        }
+    }
+}
+
+impl ToSchemaValue for UnknownComponent {
+    fn to_schema_value(&self) -> SchemaValue {
+        SchemaValue::Unknown {
+            component_id: self.component_id,
+        }
+    }
+}
+
+impl Drop for UnknownComponent {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::Schema_DestroyComponentData(self.data);
+        }
+    }
+}
@@ -141,8 +141,15 @@ pub struct Connection {
     pointer: *mut ffi::Worker_Connection,
 }
 
+// The connection is only ever used from one thread at a time, but it may be moved
+// between threads along with the `World` which owns it.
+unsafe impl Send for Connection {}
+
 impl Drop for Connection {
     fn drop(&mut self) {
+        if self.pointer.is_null() {
+            return;
+        }
         unsafe {
             ffi::Worker_Connection_Destroy(self.pointer);
         }
@@ -150,6 +157,15 @@ impl Drop for Connection {
 }
 
 impl Connection {
+    // A connection which was never made, so that a `World` can be created in tests.
+    // Nothing which talks to SpatialOS can be called on it.
+    #[cfg(test)]
+    pub fn disconnected() -> Connection {
+        Connection {
+            pointer: ptr::null_mut(),
+        }
+    }
+
     pub fn default_vtable() -> Box<ffi::Worker_ComponentVtable> {
         unsafe { Box::new(mem::zeroed()) }
     }
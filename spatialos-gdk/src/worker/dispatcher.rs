@@ -1,6 +1,8 @@
 use std::any::Any;
 use std::ffi::CStr;
-use worker::ffi::{Schema_GetCommandRequestCommandIndex, Schema_GetCommandResponseCommandIndex};
+use unknown_component::UnknownComponent;
+use worker::ffi::{Schema_ComponentUpdate, Schema_GetCommandRequestCommandIndex,
+                  Schema_GetCommandResponseCommandIndex};
 use worker::schema::{GeneratedSchema, GlobalComponentDataInterface, GlobalComponentUpdateInterface};
 use worker::{Authority, CommandStatus, ComponentId, EntityId, FFIEnum, Op, OpList, RequestId};
 
@@ -39,7 +41,14 @@ pub trait Dispatcher<S: GeneratedSchema> {
                     unsafe {
                         let component_data = Box::from_raw((*op).data.schema_type);
 
-                        if let Some(data) =
+                        // Components which aren't in the schema are kept as they are,
+                        // so that their data isn't lost.
+                        if S::schema_info().component(component_id).is_none() {
+                            self.on_add_unknown_component(
+                                entity_id,
+                                UnknownComponent::new(component_id, &*component_data),
+                            );
+                        } else if let Some(data) =
                             S::ComponentData::deserialise(component_id, component_data)
                         {
                             self.on_add_component(entity_id, component_id, data);
@@ -60,7 +69,13 @@ pub trait Dispatcher<S: GeneratedSchema> {
                     unsafe {
                         let component_update = Box::from_raw((*op).update.schema_type);
 
-                        if let Some(update) =
+                        if S::schema_info().component(component_id).is_none() {
+                            self.on_unknown_component_update(
+                                entity_id,
+                                component_id,
+                                component_update,
+                            );
+                        } else if let Some(update) =
                             S::ComponentUpdate::deserialise(component_id, component_update)
                         {
                             self.on_component_update(entity_id, component_id, update);
@@ -143,6 +158,14 @@ pub trait Dispatcher<S: GeneratedSchema> {
         update: S::ComponentUpdate,
     ) {
     }
+    fn on_add_unknown_component(&mut self, entity_id: EntityId, component: UnknownComponent) {}
+    fn on_unknown_component_update(
+        &mut self,
+        entity_id: EntityId,
+        component_id: ComponentId,
+        update: Box<Schema_ComponentUpdate>,
+    ) {
+    }
     fn on_authority_change(
         &mut self,
         entity_id: EntityId,
@@ -1,18 +1,22 @@
 use worker::ffi::{Schema_CommandRequest, Schema_CommandResponse, Schema_ComponentData,
                   Schema_ComponentUpdate};
 
+pub mod reflection;
+
+use self::reflection::{SchemaInfo, SchemaValue};
 use ComponentBitField;
 use std::any::Any;
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Debug;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
 use worker::ComponentId;
+use world::WorldTime;
 
 pub type FieldId = u32;
 
-pub trait GeneratedSchema: Sized + Default {
+pub trait GeneratedSchema: 'static + Sized + Default {
     const NUMBER_OF_COMPONENTS: usize;
     type ComponentData: GlobalComponentDataInterface<Self>;
     type ComponentUpdate: GlobalComponentUpdateInterface<Self>;
@@ -43,6 +47,8 @@ pub trait GeneratedSchema: Sized + Default {
         command_index: u32,
         response: Box<Schema_CommandResponse>,
     ) -> Option<Box<Any>>;
+    /// Information about every component, type and enum in the schema.
+    fn schema_info() -> &'static SchemaInfo;
 }
 
 pub trait GlobalComponentDataInterface<S: GeneratedSchema> {
@@ -52,6 +58,9 @@ pub trait GlobalComponentDataInterface<S: GeneratedSchema> {
     ) -> Option<S::ComponentData>;
     fn serialise(&self) -> Box<Schema_ComponentData>;
     fn apply_update(&mut self, update: &S::ComponentUpdate);
+    fn component_id(&self) -> ComponentId;
+    /// The value of every field of the component.
+    fn to_schema_value(&self) -> SchemaValue;
 }
 
 pub trait GlobalComponentUpdateInterface<S: GeneratedSchema> {
@@ -62,7 +71,7 @@ pub trait GlobalComponentUpdateInterface<S: GeneratedSchema> {
 }
 
 pub trait Component<S: GeneratedSchema>: Default {
-    type Data: ComponentDataInterface<S> + Default;
+    type Data: ComponentDataInterface<S> + Default + Send;
     type Update: ComponentUpdateInterface<S>;
 
     fn component_id() -> ComponentId;
@@ -70,25 +79,56 @@ pub trait Component<S: GeneratedSchema>: Default {
     fn extract_data_borrow(data: &S::ComponentData) -> Option<&Self::Data>;
     fn extract_data(data: S::ComponentData) -> Option<Self::Data>;
     fn extract_update(update: &S::ComponentUpdate) -> Option<&Self::Update>;
+    fn into_data(self) -> Self::Data;
+}
+
+/// A component defined in schema, which can be sent to SpatialOS.
+///
+/// This is implemented by every generated component, but not by `LocalComponent`s, so
+/// that local components can't be added to an `EntityTemplate`.
+pub trait SchemaComponent<S: GeneratedSchema>: Component<S> {
     fn serialise_snapshot(self) -> Box<Schema_ComponentData>;
 }
 
 pub trait ComponentDataInterface<S: GeneratedSchema>: Sized {
-    fn deserialise_data(update: Box<Schema_ComponentData>) -> S::ComponentData;
-    fn serialise_data(&self) -> Box<Schema_ComponentData>;
     // fn mark_as_dirty(&mut self, field_index: usize);
-    fn serialise_update(&mut self) -> Box<Schema_ComponentUpdate>;
+    // Returns `None` for components which are never sent to SpatialOS.
+    fn serialise_update(&mut self) -> Option<Box<Schema_ComponentUpdate>>;
+    // Clears the dirty state in the same way as `serialise_update`, without serialising anything.
+    fn discard_update(&mut self);
     fn get_and_clear_dirty_bit(&mut self) -> bool;
     // fn is_dirty(&self) -> bool;
     fn make_dirty(&mut self);
-    fn cleanup_after_frame(&mut self);
+    // Records the time at which any newly received events arrived.
+    fn stamp_events(&mut self, time: &WorldTime);
+    // Records the given time against any fields which have changed since this was last
+    // called. Returns true if there were any.
+    fn stamp_changed_fields(&mut self, time: &WorldTime) -> bool;
+    // A mask of the fields which changed after the given time, where bit `i` is the
+    // `i`th field of the component.
+    fn changed_fields_since(&self, time: &WorldTime) -> u64;
+    // Makes any newly triggered events visible locally. Returns true if there were any.
+    fn loop_back_events(&mut self, time: &WorldTime) -> bool;
+    // Removes events received before `oldest_cursor`, which every system has already
+    // seen. Returns true if any events remain.
+    fn cleanup_after_frame(&mut self, oldest_cursor: &WorldTime) -> bool;
+}
+
+// The data of a component defined in schema.
+pub trait SchemaComponentData<S: GeneratedSchema>: ComponentDataInterface<S> {
+    fn deserialise_data(data: Box<Schema_ComponentData>) -> S::ComponentData;
+    fn serialise_data(&self) -> Box<Schema_ComponentData>;
 }
 
 pub trait ComponentUpdateInterface<S: GeneratedSchema>: Sized {
-    fn deserialise_update(update: Box<Schema_ComponentUpdate>) -> S::ComponentUpdate;
     fn contains_events(&self) -> bool;
 }
 
+// An update to a component defined in schema.
+pub trait SchemaComponentUpdate<S: GeneratedSchema>: ComponentUpdateInterface<S> {
+    fn deserialise_update(update: Box<Schema_ComponentUpdate>) -> S::ComponentUpdate;
+}
+
 pub trait Command<S: GeneratedSchema> {
     type Component: Component<S>;
     type Request: CommandRequestInterface;
@@ -107,6 +147,28 @@ pub trait CommandResponseInterface {
     fn serialise_response(&self) -> Box<Schema_CommandResponse>;
 }
 
+/// An event of a schema component, which can be read with an `Events` group field.
+///
+/// A struct implementing this is generated for each event, named after the component
+/// and the event. For example, the `got_hit` event of `Health` is `HealthGotHit`.
+pub trait ComponentEvent<S: GeneratedSchema> {
+    type Component: Component<S>;
+    type Value;
+
+    fn get_event(data: &<Self::Component as Component<S>>::Data) -> &Event<Self::Value>;
+}
+
+/// A field of a schema component, which can be used with a `ModifiedField` group field.
+///
+/// A struct implementing this is generated for each field, named after the component
+/// and the field. For example, the `coords` field of `Position` is `PositionCoords`.
+pub trait ComponentField<S: GeneratedSchema> {
+    type Component: Component<S>;
+
+    /// The bit of this field in `changed_fields_since`.
+    fn mask() -> u64;
+}
+
 pub trait DynamicComponentHandler<S: GeneratedSchema> {
     fn register_component<C: 'static + Component<S>>(&mut self);
 }
@@ -114,28 +176,85 @@ pub trait DynamicComponentHandler<S: GeneratedSchema> {
 #[derive(Clone, Debug, Default)]
 pub struct Event<T> {
     events: Vec<T>,
+    // The time each event in `events` was received. Events which have been added
+    // but not stamped yet are at the end of `events`.
+    received: Vec<WorldTime>,
+    // Events before this index were kept from previous ticks, for systems which
+    // haven't read them yet.
+    first_this_frame: usize,
     staged_events: Vec<T>,
+    // The number of staged events which have already been looped back into `events`.
+    looped_back: usize,
 }
 
 impl<T> Event<T> {
     pub fn new() -> Event<T> {
         Event {
             events: Vec::new(),
+            received: Vec::new(),
+            first_this_frame: 0,
             staged_events: Vec::new(),
+            looped_back: 0,
         }
     }
 
+    /// Calls the given closure for each event received in the current tick, including
+    /// events looped back from systems which have already run.
+    ///
+    /// Events are kept for longer than one tick, so use `events_after`, or an `Events`
+    /// group field, to see each event exactly once in a system.
     pub fn process<F>(&self, mut cb: F)
     where
         F: FnMut(&T),
     {
-        for event in &self.events {
+        for event in self.events_this_frame() {
             cb(event);
         }
     }
 
+    /// Gets the events which were received in the current tick.
+    pub fn events_this_frame(&self) -> &[T] {
+        &self.events[self.first_this_frame..]
+    }
+
     pub fn clear(&mut self) {
         self.events.clear();
+        self.received.clear();
+        self.first_this_frame = 0;
+    }
+
+    /// Gets the events which were received after the given time.
+    pub fn events_after(&self, time: &WorldTime) -> &[T] {
+        let first_new = self.received
+            .iter()
+            .position(|received| received.occured_after(time))
+            .unwrap_or(self.received.len());
+        &self.events[first_new..]
+    }
+
+    /// Returns true if any events were received after the given time.
+    pub fn has_events_after(&self, time: &WorldTime) -> bool {
+        self.events_after(time).len() > 0
+    }
+
+    #[doc(hidden)]
+    pub fn stamp_events(&mut self, time: &WorldTime) {
+        while self.received.len() < self.events.len() {
+            self.received.push(time.clone());
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn clear_before(&mut self, time: &WorldTime) -> bool {
+        let first_new = self.received
+            .iter()
+            .position(|received| received.occured_after(time))
+            .unwrap_or(self.received.len());
+        self.events.drain(..first_new);
+        self.received.drain(..first_new);
+        // This is called at the end of each tick, so every event left is now old.
+        self.first_this_frame = self.events.len();
+        self.events.len() > 0
     }
 
     pub fn trigger(&mut self, value: T) {
@@ -151,15 +270,45 @@ impl<T> Event<T> {
     }
 
     pub fn clear_staged_events(&mut self) {
-        self.staged_events.clear()
+        self.staged_events.clear();
+        self.looped_back = 0;
     }
 }
 
+impl<T: Clone> Event<T> {
+    #[doc(hidden)]
+    pub fn loop_back_staged_events(&mut self, time: &WorldTime) -> bool {
+        if self.looped_back == self.staged_events.len() {
+            return false;
+        }
+
+        for value in &self.staged_events[self.looped_back..] {
+            self.events.push(value.clone());
+            self.received.push(time.clone());
+        }
+        self.looped_back = self.staged_events.len();
+        true
+    }
+}
+
+// Two events are equal if they contain the same received and staged events,
+// regardless of when they were received.
+impl<T: PartialEq> PartialEq for Event<T> {
+    fn eq(&self, other: &Event<T>) -> bool {
+        self.events == other.events && self.staged_events == other.staged_events
+    }
+}
+
+impl<T: Eq> Eq for Event<T> {}
+
+// Iterating over an `Event` gives the events received in the current tick, as with
+// `process`.
 impl<T> IntoIterator for Event<T> {
     type Item = T;
     type IntoIter = ::std::vec::IntoIter<T>;
 
-    fn into_iter(self) -> Self::IntoIter {
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.events.drain(..self.first_this_frame);
         self.events.into_iter()
     }
 }
@@ -169,13 +318,16 @@ impl<'a, T> IntoIterator for &'a Event<T> {
     type IntoIter = ::std::slice::Iter<'a, T>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.events.iter()
+        self.events_this_frame().iter()
     }
 }
 
 #[derive(Clone, Default)]
 pub struct Property<T: fmt::Debug> {
     is_dirty: bool,
+    // Set whenever the value changes, and cleared once `changed_at` has been updated.
+    is_changed: bool,
+    changed_at: WorldTime,
     value: T,
 }
 
@@ -183,6 +335,8 @@ impl<T: fmt::Debug> From<T> for Property<T> {
     fn from(value: T) -> Property<T> {
         Property {
             is_dirty: true,
+            is_changed: true,
+            changed_at: WorldTime::new(),
             value,
         }
     }
@@ -194,10 +348,27 @@ impl<T: fmt::Debug> fmt::Debug for Property<T> {
     }
 }
 
+// Properties are compared by value, ignoring whether they are dirty or changed.
+impl<T: fmt::Debug + PartialEq> PartialEq for Property<T> {
+    fn eq(&self, other: &Property<T>) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: fmt::Debug + Eq> Eq for Property<T> {}
+
+impl<T: fmt::Debug + Hash> Hash for Property<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+    }
+}
+
 impl<T: fmt::Debug> Property<T> {
     pub fn new(value: T) -> Property<T> {
         Property {
             is_dirty: false,
+            is_changed: true,
+            changed_at: WorldTime::new(),
             value,
         }
     }
@@ -211,6 +382,21 @@ impl<T: fmt::Debug> Property<T> {
         self.is_dirty = false;
         dirty
     }
+
+    /// Returns true if this field changed after the given time.
+    pub fn changed_after(&self, time: &WorldTime) -> bool {
+        self.changed_at.occured_after(time)
+    }
+
+    #[doc(hidden)]
+    pub fn stamp_changed(&mut self, time: &WorldTime) -> bool {
+        let changed = self.is_changed;
+        if changed {
+            self.changed_at = time.clone();
+            self.is_changed = false;
+        }
+        changed
+    }
 }
 
 impl<T: fmt::Debug> Deref for Property<T> {
@@ -224,6 +410,7 @@ impl<T: fmt::Debug> Deref for Property<T> {
 impl<T: fmt::Debug> DerefMut for Property<T> {
     fn deref_mut(&mut self) -> &mut T {
         self.is_dirty = true;
+        self.is_changed = true;
         &mut self.value
     }
 }
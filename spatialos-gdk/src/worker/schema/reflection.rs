@@ -0,0 +1,224 @@
+//! Information about the schema which is available at runtime.
+//!
+//! The code generator emits a `SchemaInfo` describing every component, type and enum,
+//! which can be accessed with `GeneratedSchema::schema_info`. Together with `SchemaValue`,
+//! this allows tools such as inspectors to work with any component without any code
+//! specific to it.
+//!
+//! ## Example
+//!
+//! ```
+//! fn print_component(data: &<Schema as GeneratedSchema>::ComponentData) {
+//!     let info = Schema::schema_info().component(data.component_id()).unwrap();
+//!     println!("{}: {:?}", info.qualified_name, data.to_schema_value());
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use worker::ComponentId;
+use worker::schema::{FieldId, Property};
+
+/// The type of a single value in schema.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    Bool,
+    Uint32,
+    Uint64,
+    Int32,
+    Int64,
+    Sint32,
+    Sint64,
+    Fixed32,
+    Fixed64,
+    Sfixed32,
+    Sfixed64,
+    Float,
+    Double,
+    String,
+    Bytes,
+    EntityId,
+    /// An enum, with its qualified name.
+    Enum(&'static str),
+    /// A type defined in schema, with its qualified name.
+    Type(&'static str),
+}
+
+/// The type of a field in schema.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FieldType {
+    Singular(ValueType),
+    Option(ValueType),
+    List(ValueType),
+    Map(ValueType, ValueType),
+}
+
+#[derive(Debug)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub number: FieldId,
+    pub field_type: FieldType,
+}
+
+#[derive(Debug)]
+pub struct TypeInfo {
+    pub qualified_name: &'static str,
+    pub fields: &'static [FieldInfo],
+}
+
+#[derive(Debug)]
+pub struct EnumValueInfo {
+    pub name: &'static str,
+    pub value: u32,
+}
+
+#[derive(Debug)]
+pub struct EnumInfo {
+    pub qualified_name: &'static str,
+    pub values: &'static [EnumValueInfo],
+}
+
+#[derive(Debug)]
+pub struct EventInfo {
+    pub name: &'static str,
+    pub index: u32,
+    /// The qualified name of the type of the event.
+    pub event_type: &'static str,
+}
+
+#[derive(Debug)]
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub index: u32,
+    /// The qualified name of the request type.
+    pub request_type: &'static str,
+    /// The qualified name of the response type.
+    pub response_type: &'static str,
+}
+
+#[derive(Debug)]
+pub struct ComponentInfo {
+    pub qualified_name: &'static str,
+    pub id: ComponentId,
+    pub fields: &'static [FieldInfo],
+    pub events: &'static [EventInfo],
+    pub commands: &'static [CommandInfo],
+}
+
+/// Every component, type and enum in the schema.
+#[derive(Debug)]
+pub struct SchemaInfo {
+    pub components: &'static [ComponentInfo],
+    pub types: &'static [TypeInfo],
+    pub enums: &'static [EnumInfo],
+}
+
+impl SchemaInfo {
+    pub fn component(&self, component_id: ComponentId) -> Option<&'static ComponentInfo> {
+        self.components
+            .iter()
+            .find(|component| component.id == component_id)
+    }
+
+    pub fn component_by_name(&self, qualified_name: &str) -> Option<&'static ComponentInfo> {
+        self.components
+            .iter()
+            .find(|component| component.qualified_name == qualified_name)
+    }
+
+    pub fn type_info(&self, qualified_name: &str) -> Option<&'static TypeInfo> {
+        self.types
+            .iter()
+            .find(|type_info| type_info.qualified_name == qualified_name)
+    }
+
+    pub fn enum_info(&self, qualified_name: &str) -> Option<&'static EnumInfo> {
+        self.enums
+            .iter()
+            .find(|enum_info| enum_info.qualified_name == qualified_name)
+    }
+}
+
+/// A schema value of any type.
+///
+/// Schema types which have the same Rust type share a variant, so for example
+/// `sint32` values are `Int32`, and `EntityId` values are `Int64`. The exact type
+/// can be found in the `FieldInfo` of the field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SchemaValue {
+    Bool(bool),
+    Uint32(u32),
+    Uint64(u64),
+    Int32(i32),
+    Int64(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Enum(u32),
+    Option(Option<Box<SchemaValue>>),
+    List(Vec<SchemaValue>),
+    Map(Vec<(SchemaValue, SchemaValue)>),
+    /// The fields of a type or component, in the order they are defined in schema.
+    Object(Vec<(&'static str, SchemaValue)>),
+    /// A component which is not part of the generated schema, so its fields can't be
+    /// described. See `UnknownComponent`.
+    Unknown { component_id: ComponentId },
+}
+
+/// Converts a value into a `SchemaValue`. This is implemented for every generated type.
+pub trait ToSchemaValue {
+    fn to_schema_value(&self) -> SchemaValue;
+}
+
+macro_rules! impl_to_schema_value {
+    ($rust_type:ty, $variant:ident) => {
+        impl ToSchemaValue for $rust_type {
+            fn to_schema_value(&self) -> SchemaValue {
+                SchemaValue::$variant(self.clone())
+            }
+        }
+    };
+}
+
+impl_to_schema_value!(bool, Bool);
+impl_to_schema_value!(u32, Uint32);
+impl_to_schema_value!(u64, Uint64);
+impl_to_schema_value!(i32, Int32);
+impl_to_schema_value!(i64, Int64);
+impl_to_schema_value!(f32, Float);
+impl_to_schema_value!(f64, Double);
+impl_to_schema_value!(String, String);
+impl_to_schema_value!(Vec<u8>, Bytes);
+
+impl<T: ToSchemaValue> ToSchemaValue for Option<T> {
+    fn to_schema_value(&self) -> SchemaValue {
+        SchemaValue::Option(
+            self.as_ref()
+                .map(|value| Box::new(value.to_schema_value())),
+        )
+    }
+}
+
+impl<T: ToSchemaValue> ToSchemaValue for Vec<T> {
+    fn to_schema_value(&self) -> SchemaValue {
+        SchemaValue::List(self.iter().map(|value| value.to_schema_value()).collect())
+    }
+}
+
+impl<K: ToSchemaValue + Eq + Hash, V: ToSchemaValue> ToSchemaValue for HashMap<K, V> {
+    fn to_schema_value(&self) -> SchemaValue {
+        SchemaValue::Map(
+            self.iter()
+                .map(|(key, value)| (key.to_schema_value(), value.to_schema_value()))
+                .collect(),
+        )
+    }
+}
+
+impl<T: ToSchemaValue + fmt::Debug> ToSchemaValue for Property<T> {
+    fn to_schema_value(&self) -> SchemaValue {
+        (**self).to_schema_value()
+    }
+}
@@ -1,29 +1,36 @@
 use ComponentBitField;
-use chunk::Chunk;
+use chunk::ComponentStorage;
+use command_buffer::CommandBuffer;
 use commands::Commands;
 use component_group::{Read, Write};
-use entity::Entity;
+use entity::{is_local_entity, FIRST_LOCAL_ENTITY_ID};
 use entity_collection::{Entities, EntityCollection};
-use entity_template::EntityTemplate;
-use shared_resources::SharedResources;
+use entity_template::{EntityTemplate, LocalEntityTemplate};
+use local_component::{self, LocalComponent};
+use shared_resources::{Resource, ResourceError, ResourceMut, SharedResources};
 use std::any::Any;
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::mem;
 use system::System;
+use unknown_component::UnknownComponent;
+use worker::ffi::Schema_ComponentUpdate;
 use worker::schema::{Command, Component, GeneratedSchema, GlobalComponentDataInterface};
 use worker::{Authority, CommandStatus, ComponentId, Connection, Dispatcher, EntityId, LogLevel,
              RequestId};
 
 /// Possible errors which can be thrown by the `World`.
+#[derive(Debug)]
 pub enum WorldError {
     /// We tried to perform an operation which required a connection to SpatialOS, but
     /// the connection to SpatialOS is closed.
     ConnectionLost,
+    /// We tried to register a local component, but `MAX_LOCAL_COMPONENTS` have already
+    /// been registered for this schema.
+    TooManyLocalComponents,
 }
 
 #[doc(hidden)]
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug)]
 pub struct WorldTime {
     timestamp: u64,
 }
@@ -46,6 +53,14 @@ impl WorldTime {
         }
     }
 
+    pub fn min_time<'a>(&'a self, b: &'a WorldTime) -> &'a WorldTime {
+        if self.occured_after(b) {
+            b
+        } else {
+            self
+        }
+    }
+
     pub fn get_time(&mut self) -> WorldTime {
         let time = WorldTime {
             timestamp: self.timestamp,
@@ -63,7 +78,7 @@ pub struct PartialEntity<S: GeneratedSchema> {
 }
 
 pub struct SystemData<S> {
-    system: Box<System<S>>,
+    system: Box<System<S> + Send>,
     last_update: WorldTime,
 }
 
@@ -74,24 +89,56 @@ pub struct SystemData<S> {
 /// * Send and receive commands
 /// * Manage shared resources
 /// * Create and delete entities
+/// * Add and remove local components
+/// * Spawn and despawn local entities
 ///
 /// The `World` is also responsible for processing each system and each
 /// SpatialOS operation. To tick the worker, you must call `process` for each tick.
 ///
+/// The `World` is `Send`, so it can be created on one thread and then ticked on
+/// another. For this reason systems, shared resources and command callbacks must
+/// all be `Send` too.
+///
+/// ## Event loopback
+///
+/// By default, events triggered by this worker are only sent to SpatialOS. If loopback
+/// is enabled using `set_event_loopback`, they are also added to the component's
+/// events straight after the system which triggered them has finished updating, so
+/// that later systems see them in the same way as events received from SpatialOS.
+///
 /// ## Shared resources
 ///
 /// You can set and retrieve shared objects of any type using `get_shared_resource`
 /// and `set_shared_resource`. These objects can be used to store global information
-/// or information which must be shared between systems.
+/// or information which must be shared between systems. As the `World` is `Send`,
+/// shared resources must be `Send` too.
+///
+/// Resources can also be borrowed using `resource` and `resource_mut`, which check
+/// borrows at runtime in the same way as a `RefCell`. This allows several resources
+/// to be borrowed at once, and resources which are `Sync` to be used from within
+/// `Entities::par_for_each`.
+///
+/// ```
+/// let config = world.resource::<Config>();
+/// let mut score = world.resource_mut::<Score>();
+/// entities.par_for_each::<MovementData, _>(|entity| {
+///     entity.position.coords.x += config.speed;
+/// });
+/// score.total += 1;
+/// ```
 pub struct World<S: GeneratedSchema> {
     connection: Connection,
     entities: EntityCollection<S>,
     added_this_cs: HashMap<EntityId, PartialEntity<S>>,
-    entity_ids: HashMap<EntityId, Rc<RefCell<Entity<S>>>>,
     systems: Vec<SystemData<S>>,
     world_time: WorldTime,
     commands: Commands<S>,
     shared_resources: SharedResources,
+    next_local_entity_id: EntityId,
+    command_buffer: CommandBuffer<S>,
+    event_loopback: bool,
+    // Components which aren't part of the generated schema, by entity.
+    unknown_components: HashMap<EntityId, HashMap<ComponentId, UnknownComponent>>,
 }
 
 impl<S: 'static + GeneratedSchema> World<S> {
@@ -101,11 +148,14 @@ impl<S: 'static + GeneratedSchema> World<S> {
             connection,
             entities: EntityCollection::new(),
             added_this_cs: HashMap::new(),
-            entity_ids: HashMap::new(),
             systems: Vec::new(),
             world_time: WorldTime::new(),
             commands: Commands::new(),
             shared_resources: SharedResources::new(),
+            next_local_entity_id: FIRST_LOCAL_ENTITY_ID,
+            command_buffer: CommandBuffer::new(),
+            event_loopback: false,
+            unknown_components: HashMap::new(),
         });
 
         manager
@@ -117,12 +167,14 @@ impl<S: 'static + GeneratedSchema> World<S> {
     /// * Get's the list of ops from SpatialOS.
     /// * Processes each of these ops. This will in turn update component data and
     ///   trigger command callbacks and handlers.
-    /// * Calls each registered system's `on_update` method.
+    /// * Calls each registered system's `on_update` method, and then plays back
+    ///   anything which it recorded into the `CommandBuffer`.
     /// * Sends any updates to components which were changed by a system.
     pub fn process(&mut self, timeout_millis: u32) -> Result<(), WorldError> {
         if !self.connection.is_connected() {
             return Result::Err(WorldError::ConnectionLost);
         }
+        self.entities.check_not_any_borrowed("process the World");
 
         let world_ptr = self as *mut World<S>;
 
@@ -131,6 +183,22 @@ impl<S: 'static + GeneratedSchema> World<S> {
             self.process_op_list(op_list);
         }
 
+        self.update_systems();
+
+        Result::Ok(())
+    }
+
+    // Runs each system's `on_update`, replicates any changes and cleans up events which every
+    // system has seen.
+    fn update_systems(&mut self) {
+        let world_ptr = self as *mut World<S>;
+
+        // Components may have been changed, and events triggered, by command handlers.
+        self.entities.update_locally_changed(&mut self.world_time);
+        if self.event_loopback {
+            self.entities.loop_back_events(&mut self.world_time);
+        }
+
         unsafe {
             for system in (*world_ptr).systems.iter_mut() {
                 {
@@ -140,19 +208,41 @@ impl<S: 'static + GeneratedSchema> World<S> {
                     );
                     system.system.on_update(self, &mut entities_view);
                 }
+                self.command_buffer.clone().play_back(self);
+                self.entities.update_locally_changed(&mut self.world_time);
+                if self.event_loopback {
+                    self.entities.loop_back_events(&mut self.world_time);
+                }
                 system.last_update = self.world_time.get_time();
             }
         }
 
         self.entities.replicate(&mut self.connection);
-        self.entities.cleanup_after_frame();
 
-        Result::Ok(())
+        // Events are kept until every system has had a chance to read them.
+        let now = self.world_time.get_time();
+        let oldest_cursor = self.systems
+            .iter()
+            .fold(&now, |oldest, system| oldest.min_time(&system.last_update))
+            .clone();
+        self.entities.cleanup_after_frame(&oldest_cursor);
+    }
+
+    // Runs the systems as `process` does, without receiving any ops.
+    #[cfg(test)]
+    pub fn update_systems_without_ops(&mut self) {
+        self.update_systems();
+    }
+
+    // A view of every entity, as seen by a system which last ran at `from_time`.
+    #[cfg(test)]
+    pub fn entities_since<'a>(&'a mut self, from_time: &'a WorldTime) -> Entities<'a, S> {
+        Entities::entities_from_time(&mut self.entities, from_time)
     }
 
     /// Registers a system to the World. The system's `on_ready` method will be
     /// called during this method.
-    pub fn register<A: 'static + System<S> + Sized>(&mut self, mut system: A) {
+    pub fn register<A: 'static + System<S> + Send + Sized>(&mut self, mut system: A) {
         {
             system.on_ready(self);
         }
@@ -162,6 +252,47 @@ impl<S: 'static + GeneratedSchema> World<S> {
         });
     }
 
+    /// Gets the `CommandBuffer`, which can be used to perform operations on the `World`
+    /// from within `Entities::par_for_each`. Anything recorded into it is played back
+    /// once the current system has finished updating.
+    pub fn command_buffer(&self) -> CommandBuffer<S> {
+        self.command_buffer.clone()
+    }
+
+    /// Sets whether events triggered by this worker should also be seen by systems
+    /// in this worker. This is disabled by default.
+    pub fn set_event_loopback(&mut self, enabled: bool) {
+        self.event_loopback = enabled;
+    }
+
+    /// Sets the maximum number of entities which are stored together in a single chunk.
+    /// This is 1024 by default.
+    ///
+    /// Chunks only allocate memory for the entities they contain, so this does not need
+    /// to be lowered for archetypes with few entities. A smaller size can reduce the
+    /// time taken to grow a chunk, while a larger size can improve iteration speed.
+    ///
+    /// This only affects chunks which are created after it is called, so it should be
+    /// called before the first call to `process`.
+    pub fn set_max_entities_per_chunk(&mut self, max_entities_per_chunk: usize) {
+        if max_entities_per_chunk == 0 {
+            panic!("A chunk must be able to hold at least one entity.");
+        }
+        self.entities
+            .set_max_entities_per_chunk(max_entities_per_chunk);
+    }
+
+    /// Merges chunks of entities which have the same components but are only partly
+    /// full, and frees any chunks which are empty.
+    ///
+    /// Chunks are never freed automatically, so a long-running worker whose entities
+    /// change their components often should call this periodically, such as every few
+    /// hundred ticks. It panics if called while iterating over entities or while a
+    /// `ComponentLookup` exists.
+    pub fn compact_entities(&mut self) {
+        self.entities.compact();
+    }
+
     /// Sends a log message to SpatialOS, as well as logging it to `stdout`.
     ///
     /// It is invalid to call this method if the connection is no longer active.
@@ -176,24 +307,53 @@ impl<S: 'static + GeneratedSchema> World<S> {
         self.shared_resources.get::<R>()
     }
 
-    /// Sets or replaces the shared resource of type `R`.
-    pub fn set_shared_resource<R: 'static>(&mut self, resource: R) {
+    /// Sets or replaces the shared resource of type `R`. The resource must be `Send`, as it
+    /// moves with the `World`; use `Arc` rather than `Rc` to share data with a resource.
+    pub fn set_shared_resource<R: 'static + Send>(&mut self, resource: R) {
         self.shared_resources.add(resource)
     }
 
+    /// Immutably borrows the shared resource of type `R`.
+    ///
+    /// Panics if the resource does not exist or is already mutably borrowed.
+    pub fn resource<R: 'static>(&self) -> Resource<R> {
+        match self.shared_resources.borrow::<R>() {
+            Ok(resource) => resource,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Mutably borrows the shared resource of type `R`.
+    ///
+    /// Panics if the resource does not exist or is already borrowed.
+    pub fn resource_mut<R: 'static>(&self) -> ResourceMut<R> {
+        match self.shared_resources.borrow_mut::<R>() {
+            Ok(resource) => resource,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Immutably borrows the shared resource of type `R`, returning an error if it
+    /// does not exist or is already mutably borrowed.
+    pub fn try_resource<R: 'static>(&self) -> Result<Resource<R>, ResourceError> {
+        self.shared_resources.borrow::<R>()
+    }
+
+    /// Mutably borrows the shared resource of type `R`, returning an error if it
+    /// does not exist or is already borrowed.
+    pub fn try_resource_mut<R: 'static>(&self) -> Result<ResourceMut<R>, ResourceError> {
+        self.shared_resources.borrow_mut::<R>()
+    }
+
     /// Gets an immutable reference to the component data of the given `EntityId` for component `C`.
     pub fn get_component<C: 'static + Component<S>>(
         &mut self,
         entity_id: EntityId,
     ) -> Option<Read<S, C>> {
-        if let Some(entity) = self.entity_ids.get(&entity_id) {
-            let entity = entity.borrow();
-            self.entities
-                .get_chunk_for_entity(&entity)
+        if let Some((chunk, index)) = self.entities.get_chunk_for_entity(entity_id) {
+            chunk
                 .get_component_storage::<C>()
-                .map(|storage| {
-                    Read::new(&storage.get_component_data_entry(entity.index_in_chunk).data)
-                })
+                .map(|storage| Read::new(&storage.get_component_data_entry(index).data))
         } else {
             None
         }
@@ -203,38 +363,183 @@ impl<S: 'static + GeneratedSchema> World<S> {
     ///
     /// Any changes made to the component will be replicated over the network at the end of the
     /// current tick.
+    ///
+    /// Panics if a `ComponentLookup` of `C`, or an iteration over entities which uses `C`,
+    /// exists.
     pub fn get_mut_component<C: 'static + Component<S>>(
         &mut self,
         entity_id: EntityId,
     ) -> Option<Write<S, C>> {
-        if let Some(entity) = self.entity_ids.get(&entity_id) {
-            let entity = entity.borrow();
-            let chunk = self.entities.get_chunk_for_entity(&entity);
+        self.entities.check_not_borrowed(C::component_id());
+        if let Some((chunk, index)) = self.entities.get_chunk_for_entity(entity_id) {
+            // Only mark the storage as dirty if this worker can write to it, otherwise
+            // the storage would be checked for changes it can't have.
+            match chunk
+                .get_component_storage::<C>()
+                .map(|storage| storage.get_authority(index))
+            {
+                None | Some(Authority::NotAuthoritative) => return None,
+                _ => (),
+            }
             chunk.mark_component_storage_as_dirty::<C>();
-            chunk.get_component_storage::<C>().and_then(|storage| {
-                match storage.get_authority(entity.index_in_chunk) {
-                    Authority::NotAuthoritative => None,
-                    _ => Some(Write::new(
-                        &mut storage.get_component_data_entry(entity.index_in_chunk).data,
-                    )),
-                }
-            })
+            chunk
+                .get_component_storage::<C>()
+                .map(|storage| Write::new(&mut storage.get_component_data_entry(index).data))
         } else {
             None
         }
     }
 
+    /// Registers the local component `C`, so that it can be used in this worker.
+    ///
+    /// Local component IDs are assigned in the order the components are registered, and
+    /// are shared by every `World` using the same schema. Registering a component which has
+    /// already been registered does nothing.
+    ///
+    /// Returns `WorldError::TooManyLocalComponents` if `MAX_LOCAL_COMPONENTS` have already
+    /// been registered.
+    pub fn register_local_component<C: LocalComponent>(&mut self) -> Result<(), WorldError> {
+        match local_component::register_local_component::<S, C>() {
+            Some(_) => Ok(()),
+            None => Err(WorldError::TooManyLocalComponents),
+        }
+    }
+
+    /// Adds the local component `C` to the given entity. If the entity already has
+    /// a component of this type, its value is replaced.
+    ///
+    /// Returns `false` if the entity is not in the worker's local view.
+    ///
+    /// This may move the entity to a different chunk, so it panics if called while iterating
+    /// over entities or while a `ComponentLookup` exists.
+    pub fn add_local_component<C: LocalComponent>(
+        &mut self,
+        entity_id: EntityId,
+        component: C,
+    ) -> bool {
+        let mut bit_field = match self.entities.get_entity(entity_id) {
+            Some(entity) => entity.bit_field,
+            None => return false,
+        };
+        let component_id = <C as Component<S>>::component_id();
+        self.entities.check_not_borrowed(component_id);
+
+        if !bit_field.has_component(component_id) {
+            bit_field.add_component(component_id);
+            self.entities.move_entity(
+                entity_id,
+                bit_field,
+                &mut self.world_time,
+                Some((component_id, ComponentStorage::<S, C>::new_boxed)),
+            );
+        }
+
+        let (chunk, index) = self.entities.get_chunk_for_entity(entity_id).unwrap();
+        chunk.get_component_storage::<C>().unwrap().insert_data(
+            &mut self.world_time,
+            index,
+            component,
+            Authority::Authoritative,
+        );
+
+        true
+    }
+
+    /// Removes the local component `C` from the given entity, returning its value if
+    /// the entity had one.
+    ///
+    /// This may move the entity to a different chunk, so it panics if called while iterating
+    /// over entities or while a `ComponentLookup` exists.
+    pub fn remove_local_component<C: LocalComponent>(&mut self, entity_id: EntityId) -> Option<C> {
+        let mut bit_field = match self.entities.get_entity(entity_id) {
+            Some(entity) => entity.bit_field,
+            None => return None,
+        };
+        let component_id = <C as Component<S>>::component_id();
+
+        if !bit_field.has_component(component_id) {
+            return None;
+        }
+        self.entities.check_not_any_borrowed("remove components");
+        bit_field.remove_component(component_id);
+
+        let component = {
+            let (chunk, index) = self.entities.get_chunk_for_entity(entity_id).unwrap();
+            let storage = chunk.get_component_storage::<C>().unwrap();
+            mem::replace(
+                &mut storage.get_component_data_entry(index).data,
+                C::default(),
+            )
+        };
+
+        self.entities
+            .move_entity(entity_id, bit_field, &mut self.world_time, None);
+
+        Some(component)
+    }
+
+    /// Creates a new local entity from the given template, returning its `EntityId`.
+    ///
+    /// Local entities only exist in this worker and are never sent to SpatialOS. They are
+    /// given negative entity IDs so that they can never collide with a SpatialOS entity.
+    /// Commands sent to a local entity fail immediately with `CommandStatus::NotFound`, and
+    /// `delete_entity` removes it immediately in the same way as `despawn_local`.
+    ///
+    /// This panics if called while iterating over entities or while a `ComponentLookup`
+    /// exists.
+    pub fn spawn_local(&mut self, template: LocalEntityTemplate<S>) -> EntityId {
+        let entity_id = self.next_local_entity_id;
+        self.next_local_entity_id = self.next_local_entity_id - 1;
+
+        let bit_field = template.get_bit_field();
+        let world_time = &mut self.world_time;
+        let (chunk, entity_index) = self.entities
+            .push_entity_with_storages(entity_id, bit_field, || {
+                template.create_storages(world_time)
+            });
+        template.insert_into_chunk(chunk, world_time, entity_index);
+
+        entity_id
+    }
+
+    /// Removes a local entity which was created with `spawn_local`.
+    ///
+    /// Returns `false` if the given `EntityId` is not a local entity which currently exists.
+    ///
+    /// This panics if called while iterating over entities or while a `ComponentLookup`
+    /// exists.
+    pub fn despawn_local(&mut self, entity_id: EntityId) -> bool {
+        if !is_local_entity(entity_id) {
+            return false;
+        }
+
+        self.entities.remove_entity(entity_id)
+    }
+
+    /// Gets the components of the given entity which are not part of the generated schema,
+    /// such as components added to the schema after this worker was built.
+    ///
+    /// These are kept so that they can be copied into an `EntityTemplate` using
+    /// `EntityTemplate::with_unknown_component`.
+    pub fn get_unknown_components<'a>(
+        &'a self,
+        entity_id: EntityId,
+    ) -> Box<Iterator<Item = &'a UnknownComponent> + 'a> {
+        match self.unknown_components.get(&entity_id) {
+            Some(components) => Box::new(components.values()),
+            None => Box::new(None.into_iter()),
+        }
+    }
+
     /// Gets the current authority of component `C` for the given `EntityId`.
     pub fn get_authority<C: 'static + Component<S>>(
         &mut self,
         entity_id: EntityId,
     ) -> Option<Authority> {
-        if let Some(entity) = self.entity_ids.get(&entity_id) {
-            let entity = entity.borrow();
-            self.entities
-                .get_chunk_for_entity(&entity)
+        if let Some((chunk, index)) = self.entities.get_chunk_for_entity(entity_id) {
+            chunk
                 .get_component_storage::<C>()
-                .map(|storage| storage.get_authority(entity.index_in_chunk))
+                .map(|storage| storage.get_authority(index))
         } else {
             None
         }
@@ -263,7 +568,7 @@ impl<S: 'static + GeneratedSchema> World<S> {
     ///     },
     /// );
     /// ```
-    pub fn register_command_handler<C: 'static + Command<S>, H: 'static>(
+    pub fn register_command_handler<C: 'static + Command<S>, H: 'static + Send>(
         &mut self,
         _command: C,
         handler: H,
@@ -288,6 +593,9 @@ impl<S: 'static + GeneratedSchema> World<S> {
     ///
     /// Short circuiting is enabled for this command.
     ///
+    /// Local entities can't receive commands, so sending a command to one calls `failure`
+    /// immediately with `CommandStatus::NotFound`.
+    ///
     /// ## Example
     ///
     /// ```
@@ -303,7 +611,7 @@ impl<S: 'static + GeneratedSchema> World<S> {
     ///     },
     /// );
     /// ```
-    pub fn send_command<C: 'static + Command<S>, A: 'static, F: 'static>(
+    pub fn send_command<C: 'static + Command<S>, A: 'static + Send, F: 'static + Send>(
         &mut self,
         _command: C,
         entity_id: EntityId,
@@ -314,6 +622,12 @@ impl<S: 'static + GeneratedSchema> World<S> {
         A: FnOnce(&mut World<S>, EntityId, &C::Response),
         F: FnOnce(&mut World<S>, CommandStatus, String),
     {
+        if is_local_entity(entity_id) {
+            let message = format!("Commands cannot be sent to local entity {}.", entity_id);
+            failure(self, CommandStatus::NotFound, message);
+            return;
+        }
+
         self.commands.send_command::<C, A, F>(
             &mut self.connection,
             entity_id,
@@ -360,7 +674,7 @@ impl<S: 'static + GeneratedSchema> World<S> {
     ///     },
     /// );
     /// ```
-    pub fn create_entity<A: 'static, F: 'static>(
+    pub fn create_entity<A: 'static + Send, F: 'static + Send>(
         &mut self,
         entity_template: EntityTemplate,
         success: A,
@@ -385,6 +699,10 @@ impl<S: 'static + GeneratedSchema> World<S> {
     /// * The failure code.
     /// * The failure error message.
     ///
+    /// A local entity is removed immediately, as if by `despawn_local`, and the closures are
+    /// called before this returns. This panics if called while iterating over entities or
+    /// while a `ComponentLookup` exists.
+    ///
     /// ## Example
     ///
     /// ```
@@ -398,7 +716,7 @@ impl<S: 'static + GeneratedSchema> World<S> {
     ///     },
     /// );
     /// ```
-    pub fn delete_entity<A: 'static, F: 'static>(
+    pub fn delete_entity<A: 'static + Send, F: 'static + Send>(
         &mut self,
         entity_id: EntityId,
         success: A,
@@ -407,6 +725,16 @@ impl<S: 'static + GeneratedSchema> World<S> {
         A: FnOnce(&mut World<S>, EntityId),
         F: FnOnce(&mut World<S>, CommandStatus, String),
     {
+        if is_local_entity(entity_id) {
+            if self.despawn_local(entity_id) {
+                success(self, entity_id);
+            } else {
+                let message = format!("Local entity {} does not exist.", entity_id);
+                failure(self, CommandStatus::NotFound, message);
+            }
+            return;
+        }
+
         self.commands
             .delete_entity(&mut self.connection, entity_id, success, failure);
     }
@@ -415,12 +743,8 @@ impl<S: 'static + GeneratedSchema> World<S> {
 impl<S: 'static + GeneratedSchema> Dispatcher<S> for World<S> {
     fn on_critical_section(&mut self, in_critical_section: bool) {
         if !in_critical_section {
-            for (entity_id, entity) in self.added_this_cs.drain() {
-                let chunk: &mut Chunk<S> =
-                    self.entities
-                        .get_free_chunk(&entity.bit_field, &mut self.world_time, &entity);
-                let new_entity = chunk.add_entity(&mut self.world_time, entity);
-                self.entity_ids.insert(entity_id, new_entity);
+            for (_, entity) in self.added_this_cs.drain() {
+                self.entities.add_entity(&mut self.world_time, entity);
             }
         }
     }
@@ -438,13 +762,8 @@ impl<S: 'static + GeneratedSchema> Dispatcher<S> for World<S> {
     }
 
     fn on_remove_entity(&mut self, entity_id: EntityId) {
-        {
-            let entity = self.entity_ids[&entity_id].borrow();
-            let chunk = self.entities.get_chunk_for_entity(&entity);
-            chunk.remove_entity(&entity);
-        }
-
-        self.entity_ids.remove(&entity_id);
+        self.entities.remove_entity(entity_id);
+        self.unknown_components.remove(&entity_id);
     }
 
     fn on_add_component(
@@ -471,9 +790,29 @@ impl<S: 'static + GeneratedSchema> Dispatcher<S> for World<S> {
                 &mut entity.component_data.get_mut(&component_id).unwrap();
             data.apply_update(&update);
         } else {
-            let entity = self.entity_ids[&entity_id].borrow();
-            let chunk = self.entities.get_chunk_for_entity(&entity);
-            chunk.apply_component_update(component_id, &mut self.world_time, &entity, update);
+            let (chunk, index) = self.entities.get_chunk_for_entity(entity_id).unwrap();
+            chunk.apply_component_update(component_id, &mut self.world_time, index, update);
+        }
+    }
+
+    fn on_add_unknown_component(&mut self, entity_id: EntityId, component: UnknownComponent) {
+        self.unknown_components
+            .entry(entity_id)
+            .or_insert_with(HashMap::new)
+            .insert(component.component_id(), component);
+    }
+
+    fn on_unknown_component_update(
+        &mut self,
+        entity_id: EntityId,
+        component_id: ComponentId,
+        update: Box<Schema_ComponentUpdate>,
+    ) {
+        if let Some(component) = self.unknown_components
+            .get_mut(&entity_id)
+            .and_then(|components| components.get_mut(&component_id))
+        {
+            component.apply_update(update);
         }
     }
 
@@ -486,9 +825,8 @@ impl<S: 'static + GeneratedSchema> Dispatcher<S> for World<S> {
         if let Some(ref mut entity) = self.added_this_cs.get_mut(&entity_id) {
             entity.write_authority.insert(component_id, authority);
         } else {
-            let entity = self.entity_ids[&entity_id].borrow();
-            let chunk = self.entities.get_chunk_for_entity(&entity);
-            chunk.apply_authority(component_id, &entity, authority);
+            let (chunk, index) = self.entities.get_chunk_for_entity(entity_id).unwrap();
+            chunk.apply_authority(component_id, index, authority);
         }
     }
 
@@ -570,3 +908,418 @@ impl<S: 'static + GeneratedSchema> Dispatcher<S> for World<S> {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use component_group::{Events, ModifiedField, ModifiedRead};
+    use std::sync::{Arc, Mutex};
+    use schema::{add_remote_entity, new_world, ComponentData, ComponentUpdate, Health,
+                 HealthCurrent, HealthGotHit, HealthMaximum, HealthPing, HealthUpdate,
+                 PingRequest, Position, Schema};
+
+    #[derive(ComponentGroup)]
+    struct Hits<'a> {
+        id: EntityId,
+        hits: Events<'a, Schema, Health, HealthGotHit>,
+    }
+
+    // Records every hit which it reads from an `Events` field.
+    struct HitReader {
+        seen: Arc<Mutex<Vec<(EntityId, i32)>>>,
+    }
+
+    impl System<Schema> for HitReader {
+        fn on_update(&mut self, _world: &mut World<Schema>, entities: &mut Entities<Schema>) {
+            for entity in entities.get::<Hits>() {
+                for hit in entity.hits.iter() {
+                    self.seen.lock().unwrap().push((entity.id, *hit));
+                }
+            }
+        }
+    }
+
+    #[derive(ComponentGroup)]
+    struct Owned<'a> {
+        health: Write<'a, Schema, Health>,
+    }
+
+    // Triggers a hit on every entity it is authoritative over, in its first update only.
+    struct Attacker {
+        attacked: bool,
+    }
+
+    impl System<Schema> for Attacker {
+        fn on_update(&mut self, _world: &mut World<Schema>, entities: &mut Entities<Schema>) {
+            if !self.attacked {
+                for mut entity in entities.get::<Owned>() {
+                    entity.health.got_hit.trigger(1);
+                }
+                self.attacked = true;
+            }
+        }
+    }
+
+    #[derive(ComponentGroup)]
+    struct Moved<'a> {
+        id: EntityId,
+        position: ModifiedRead<'a, Schema, Position>,
+    }
+
+    // Records which entities have had their position changed.
+    struct MovedReader {
+        seen: Arc<Mutex<Vec<EntityId>>>,
+    }
+
+    impl System<Schema> for MovedReader {
+        fn on_update(&mut self, _world: &mut World<Schema>, entities: &mut Entities<Schema>) {
+            for entity in entities.get::<Moved>() {
+                self.seen.lock().unwrap().push(entity.id);
+            }
+        }
+    }
+
+    #[derive(ComponentGroup)]
+    struct Moving<'a> {
+        id: EntityId,
+        position: Write<'a, Schema, Position>,
+    }
+
+    // Moves `target` in its second update, either through a `Write` field, which is also
+    // given for every other entity, or through `World::get_mut_component`.
+    struct Mover {
+        target: EntityId,
+        through_world: bool,
+        updates: u32,
+    }
+
+    impl System<Schema> for Mover {
+        fn on_update(&mut self, world: &mut World<Schema>, entities: &mut Entities<Schema>) {
+            self.updates += 1;
+            if self.updates != 2 {
+                return;
+            }
+            if self.through_world {
+                *world.get_mut_component::<Position>(self.target).unwrap().x += 1;
+            } else {
+                for mut entity in entities.get::<Moving>() {
+                    if entity.id == self.target {
+                        *entity.position.x += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_local_writes_are_modified(through_world: bool) {
+        let mut world = new_world();
+        for entity_id in 1..3 {
+            let data = ComponentData::Position(Position { x: 0 }.into_data());
+            add_remote_entity(&mut world, entity_id, vec![data], &[Position::component_id()]);
+        }
+        let before = Arc::new(Mutex::new(Vec::new()));
+        let after = Arc::new(Mutex::new(Vec::new()));
+        world.register(MovedReader { seen: before.clone() });
+        world.register(Mover {
+            target: 1,
+            through_world,
+            updates: 0,
+        });
+        world.register(MovedReader { seen: after.clone() });
+
+        // Every entity is new in the first tick.
+        world.update_systems_without_ops();
+        take(&before);
+        take(&after);
+
+        world.update_systems_without_ops();
+        assert!(take(&before).is_empty());
+        assert_eq!(take(&after), vec![1]);
+
+        world.update_systems_without_ops();
+        assert_eq!(take(&before), vec![1]);
+        assert!(take(&after).is_empty());
+
+        world.update_systems_without_ops();
+        assert!(take(&before).is_empty());
+        assert!(take(&after).is_empty());
+    }
+
+    #[derive(ComponentGroup)]
+    struct CurrentChanged<'a> {
+        id: EntityId,
+        health: ModifiedField<'a, Schema, Health, HealthCurrent>,
+    }
+
+    #[derive(ComponentGroup)]
+    struct MaximumChanged<'a> {
+        id: EntityId,
+        health: ModifiedField<'a, Schema, Health, HealthMaximum>,
+    }
+
+    #[derive(ComponentGroup)]
+    struct HealthChanged<'a> {
+        id: EntityId,
+        health: ModifiedRead<'a, Schema, Health>,
+    }
+
+    fn add_hit_reader(world: &mut World<Schema>) -> Arc<Mutex<Vec<(EntityId, i32)>>> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        world.register(HitReader { seen: seen.clone() });
+        seen
+    }
+
+    fn add_health(world: &mut World<Schema>, entity_id: EntityId, authoritative: bool) {
+        let data = ComponentData::Health(Health { current: 10, maximum: 10 }.into_data());
+        let authority = if authoritative { vec![Health::component_id()] } else { vec![] };
+        add_remote_entity(world, entity_id, vec![data], &authority);
+    }
+
+    fn receive_hits(world: &mut World<Schema>, entity_id: EntityId, got_hit: Vec<i32>) {
+        let update = ComponentUpdate::Health(HealthUpdate {
+            current: None,
+            maximum: None,
+            got_hit,
+        });
+        world.on_component_update(entity_id, Health::component_id(), update);
+    }
+
+    fn take<T>(seen: &Arc<Mutex<Vec<T>>>) -> Vec<T> {
+        mem::replace(&mut *seen.lock().unwrap(), Vec::new())
+    }
+
+    #[test]
+    fn local_entities_are_given_decreasing_negative_ids() {
+        let mut world = new_world();
+        let first = world.spawn_local(LocalEntityTemplate::new().with_component(Position { x: 1 }));
+        let second = world.spawn_local(LocalEntityTemplate::new());
+
+        assert!(first < 0 && second < first);
+        assert!(is_local_entity(first) && is_local_entity(second));
+        assert_eq!(*world.get_component::<Position>(first).unwrap().x, 1);
+        assert!(world.get_component::<Position>(second).is_none());
+    }
+
+    #[test]
+    fn this_worker_is_authoritative_over_local_entities() {
+        let mut world = new_world();
+        let entity_id = world.spawn_local(
+            LocalEntityTemplate::new().with_component(Health { current: 10, maximum: 10 }),
+        );
+
+        assert_eq!(
+            world.get_authority::<Health>(entity_id),
+            Some(Authority::Authoritative)
+        );
+        *world.get_mut_component::<Health>(entity_id).unwrap().current = 5;
+        assert_eq!(*world.get_component::<Health>(entity_id).unwrap().current, 5);
+    }
+
+    #[test]
+    fn only_local_entities_can_be_despawned() {
+        let mut world = new_world();
+        let entity_id = world.spawn_local(LocalEntityTemplate::new());
+
+        assert!(!world.despawn_local(1));
+        assert!(world.despawn_local(entity_id));
+        assert!(!world.despawn_local(entity_id));
+    }
+
+    #[test]
+    fn commands_to_local_entities_fail_immediately() {
+        let mut world = new_world();
+        let entity_id = world.spawn_local(
+            LocalEntityTemplate::new().with_component(Health { current: 10, maximum: 10 }),
+        );
+        let result = Arc::new(Mutex::new(None));
+
+        let failure_result = result.clone();
+        world.send_command(
+            HealthPing,
+            entity_id,
+            PingRequest,
+            |_world, _entity_id, _response| panic!("The command should not succeed."),
+            move |_world, status, message| {
+                *failure_result.lock().unwrap() = Some((status, message));
+            },
+        );
+
+        let (status, message) = result.lock().unwrap().take().unwrap();
+        assert_eq!(status, CommandStatus::NotFound);
+        assert!(message.contains(&entity_id.to_string()));
+    }
+
+    #[test]
+    fn deleting_a_local_entity_despawns_it_immediately() {
+        let mut world = new_world();
+        let entity_id = world.spawn_local(
+            LocalEntityTemplate::new().with_component(Position { x: 3 }),
+        );
+        let deleted = Arc::new(Mutex::new(None));
+
+        let success_deleted = deleted.clone();
+        world.delete_entity(
+            entity_id,
+            move |world, entity_id| {
+                assert!(world.get_component::<Position>(entity_id).is_none());
+                *success_deleted.lock().unwrap() = Some(entity_id);
+            },
+            |_world, status, message| panic!("Deletion failed: {:?} {}", status, message),
+        );
+
+        assert_eq!(*deleted.lock().unwrap(), Some(entity_id));
+        assert!(world.get_component::<Position>(entity_id).is_none());
+    }
+
+    #[test]
+    fn deleting_a_missing_local_entity_fails_immediately() {
+        let mut world = new_world();
+        let entity_id = world.spawn_local(LocalEntityTemplate::new());
+        assert!(world.despawn_local(entity_id));
+        let result = Arc::new(Mutex::new(None));
+
+        let failure_result = result.clone();
+        world.delete_entity(
+            entity_id,
+            |_world, _entity_id| panic!("The deletion should not succeed."),
+            move |_world, status, _message| {
+                *failure_result.lock().unwrap() = Some(status);
+            },
+        );
+
+        assert_eq!(*result.lock().unwrap(), Some(CommandStatus::NotFound));
+    }
+
+    #[test]
+    fn every_system_reads_each_event_once() {
+        let mut world = new_world();
+        add_health(&mut world, 1, false);
+        add_health(&mut world, 2, false);
+        let first = add_hit_reader(&mut world);
+        let second = add_hit_reader(&mut world);
+
+        receive_hits(&mut world, 1, vec![5, 6]);
+        world.update_systems_without_ops();
+        assert_eq!(take(&first), vec![(1, 5), (1, 6)]);
+        assert_eq!(take(&second), vec![(1, 5), (1, 6)]);
+
+        world.update_systems_without_ops();
+        assert!(take(&first).is_empty());
+        assert!(take(&second).is_empty());
+
+        receive_hits(&mut world, 2, vec![7]);
+        world.update_systems_without_ops();
+        assert_eq!(take(&first), vec![(2, 7)]);
+        assert_eq!(take(&second), vec![(2, 7)]);
+    }
+
+    #[test]
+    fn events_fields_only_match_entities_with_new_events() {
+        let mut world = new_world();
+        add_health(&mut world, 1, false);
+        add_health(&mut world, 2, false);
+        let before = world.world_time.get_time();
+        receive_hits(&mut world, 2, vec![3]);
+
+        let entities = world.entities_since(&before);
+        let ids: Vec<EntityId> = entities.get::<Hits>().map(|entity| entity.id).collect();
+        assert_eq!(ids, vec![2]);
+    }
+
+    #[test]
+    fn looped_back_events_are_read_once_by_every_system() {
+        let mut world = new_world();
+        world.set_event_loopback(true);
+        add_health(&mut world, 1, true);
+        add_health(&mut world, 2, false);
+        let before = add_hit_reader(&mut world);
+        world.register(Attacker { attacked: false });
+        let after = add_hit_reader(&mut world);
+
+        // Systems after the one which triggered the event see it in the same tick, after
+        // events received from SpatialOS, and systems before it see it in the next tick.
+        receive_hits(&mut world, 1, vec![9]);
+        world.update_systems_without_ops();
+        assert_eq!(take(&before), vec![(1, 9)]);
+        assert_eq!(take(&after), vec![(1, 9), (1, 1)]);
+
+        world.update_systems_without_ops();
+        assert_eq!(take(&before), vec![(1, 1)]);
+        assert!(take(&after).is_empty());
+
+        world.update_systems_without_ops();
+        assert!(take(&before).is_empty());
+        assert!(take(&after).is_empty());
+    }
+
+    #[test]
+    fn triggered_events_are_not_looped_back_by_default() {
+        let mut world = new_world();
+        add_health(&mut world, 1, true);
+        let before = add_hit_reader(&mut world);
+        world.register(Attacker { attacked: false });
+        let after = add_hit_reader(&mut world);
+
+        world.update_systems_without_ops();
+        world.update_systems_without_ops();
+        assert!(take(&before).is_empty());
+        assert!(take(&after).is_empty());
+    }
+
+    #[test]
+    fn local_writes_are_seen_as_modified_by_later_systems() {
+        check_local_writes_are_modified(false);
+    }
+
+    #[test]
+    fn local_writes_through_the_world_are_seen_as_modified() {
+        check_local_writes_are_modified(true);
+    }
+
+    #[test]
+    fn modified_fields_only_match_entities_where_that_field_changed() {
+        let mut world = new_world();
+        add_health(&mut world, 1, false);
+        add_health(&mut world, 2, true);
+        add_health(&mut world, 3, true);
+        add_health(&mut world, 4, true);
+        let before = world.world_time.get_time();
+
+        let update = ComponentUpdate::Health(HealthUpdate {
+            current: Some(3),
+            maximum: None,
+            got_hit: Vec::new(),
+        });
+        world.on_component_update(1, Health::component_id(), update);
+        *world.get_mut_component::<Health>(2).unwrap().maximum = 20;
+        *world.get_mut_component::<Health>(3).unwrap().current = 5;
+        // Borrowing the component without writing to a field is not a change.
+        assert_eq!(*world.get_mut_component::<Health>(4).unwrap().current, 10);
+        world.entities.update_locally_changed(&mut world.world_time);
+
+        let entities = world.entities_since(&before);
+        let mut current: Vec<EntityId> = entities.get::<CurrentChanged>().map(|e| e.id).collect();
+        let maximum: Vec<EntityId> = entities.get::<MaximumChanged>().map(|e| e.id).collect();
+        let mut any: Vec<EntityId> = entities.get::<HealthChanged>().map(|e| e.id).collect();
+        current.sort();
+        any.sort();
+        assert_eq!(current, vec![1, 3]);
+        assert_eq!(maximum, vec![2]);
+        assert_eq!(any, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn worlds_can_be_sent_between_threads() {
+        fn assert_send<T: Send>() {}
+        assert_send::<World<Schema>>();
+        assert_send::<Entities<Schema>>();
+
+        let mut world = new_world();
+        let template = LocalEntityTemplate::new().with_component(Position { x: 4 });
+        let entity_id = world.spawn_local(template);
+        let x = ::std::thread::spawn(move || *world.get_component::<Position>(entity_id).unwrap().x)
+            .join()
+            .unwrap();
+        assert_eq!(x, 4);
+    }
+}